@@ -0,0 +1,498 @@
+use anyhow::{bail, Result};
+use fnv::FnvHashMap;
+use memmap::MmapOptions;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use super::*;
+use crate::ch_file::{as_bytes, checksum};
+
+/// file name of landmark distance tables inside of output directory
+pub const LANDMARK_FILE_NAME: &str = "landmarks.bin";
+
+const MAGIC: [u8; 8] = *b"CHALT\0\0\0";
+const VERSION: u32 = 1;
+const ENDIAN_MARK: u32 = 0x0102_0304;
+
+/// number of landmarks used for a query, chosen by bounds between source and destination
+const ACTIVE_LANDMARKS: usize = 4;
+
+/// On-disk header. Payload follows right after the header:
+///
+/// - `nodes`: `[u32; landmark_len]`
+/// - `from`: `[u32; node_len * landmark_len]`, see `Landmarks::from`
+/// - `to`: `[u32; node_len * landmark_len]`, see `Landmarks::to`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    endian: u32,
+    node_len: u64,
+    landmark_len: u64,
+    /// checksum of payload
+    checksum: u64,
+}
+
+const HEADER_LEN: usize = std::mem::size_of::<Header>();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LandmarkSelection {
+    /// node farthest from landmarks selected so far
+    Farthest,
+    /// leaf of shortest path tree from a random node, reached by following subtrees where
+    /// current landmarks give poor bounds
+    Avoid,
+}
+
+/// landmarks and costs between landmarks and all nodes, for ALT search. Costs are `u32::MAX`
+/// if unreachable.
+pub struct Landmarks {
+    node_len: usize,
+    nodes: Vec<IdxNodeKey>,
+    /// cost from landmark `i` to node `v` at `v * nodes.len() + i`
+    from: Vec<u32>,
+    /// cost from node `v` to landmark `i` at `v * nodes.len() + i`
+    to: Vec<u32>,
+}
+
+impl Graph {
+    /// costs from `src` to all nodes on `dir`, `u32::MAX` if unreachable, with previous node of
+    /// each node on the shortest path tree
+    pub(crate) fn one_to_all(
+        &self,
+        src: IdxNodeKey,
+        dir: IdxLinkDir,
+    ) -> (Vec<u32>, Vec<IdxNodeKey>) {
+        let mut search = dijkstra::Search::with_node_len(self.node_len);
+        search.add_src(src);
+        while self.search_step(&mut search, dir).is_some() {}
+
+        (0..self.node_len)
+            .map(|i| match search.get_cost(&IdxNodeKey::new(i)) {
+                Some(c) => (c.cost, c.prev_key),
+                None => (u32::MAX, IdxNodeKey::NONE),
+            })
+            .unzip()
+    }
+
+    /// A* search, where `heuristic` returns a lower bound of cost from a node to `dst`. Paths
+    /// are shortest if the heuristic is consistent, i.e. it does not decrease by more than cost
+    /// of a link along the link.
+    pub fn search_astar<H>(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        heuristic: H,
+    ) -> Option<(Vec<IdxNodeKey>, u32)>
+    where
+        H: Fn(IdxNodeKey) -> u32,
+    {
+        // labels of the search are cost plus heuristic, so nodes are settled in order of
+        // estimated cost of paths through them. Costs are kept separately, as labels which
+        // overflow can't be ordered and are skipped.
+        let mut search = dijkstra::Search::new();
+        let mut costs = FnvHashMap::default();
+        search.add_src_with_cost(src, heuristic(src));
+        costs.insert(src, 0u32);
+
+        while let Some(entry) = search.next() {
+            let cost = costs[&entry.key];
+            if entry.key == dst {
+                return Some((search.decode(dst), cost));
+            }
+
+            for link in self
+                .idx_links
+                .dir_links(entry.key.index(), IdxLinkDir::Forward)
+            {
+                let next_key = link.enode_idx;
+                let next_cost = match cost.checked_add(link.cost()) {
+                    Some(next_cost) => next_cost,
+                    None => continue,
+                };
+                let label = match next_cost.checked_add(heuristic(next_key)) {
+                    Some(label) => label,
+                    None => continue,
+                };
+                if search.update(&entry, next_key, label) {
+                    costs.insert(next_key, next_cost);
+                }
+            }
+        }
+        None
+    }
+
+    /// A* search with lower bounds from `landmarks`
+    pub fn search_alt(
+        &self,
+        landmarks: &Landmarks,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32)> {
+        assert_eq!(landmarks.node_len(), self.node_len);
+        let active = landmarks.active(src, dst);
+        self.search_astar(src, dst, |key| landmarks.bound(&active, key, dst))
+    }
+}
+
+impl Landmarks {
+    /// select `count` landmarks of `graph` and compute costs between landmarks and all nodes
+    pub fn build(graph: &Graph, count: usize, selection: LandmarkSelection) -> Self {
+        let sw = Timer::new();
+        let node_len = graph.node_len;
+
+        // nodes without links are never useful as landmarks
+        let candidates = (0..node_len)
+            .filter(|i| !graph.idx_links.links(*i).is_empty())
+            .map(IdxNodeKey::new)
+            .collect::<Vec<_>>();
+        let count = std::cmp::min(count, candidates.len());
+        if count == 0 {
+            return Self {
+                node_len,
+                nodes: Vec::new(),
+                from: Vec::new(),
+                to: Vec::new(),
+            };
+        }
+
+        // fixed seed, so builds are reproducible
+        let (roots, _) = random_pairs(candidates.len(), count + 1, Some(0x5eed));
+        let roots = roots
+            .into_iter()
+            .map(|(k, _)| candidates[k.index()])
+            .collect::<Vec<_>>();
+
+        let mut nodes = Vec::with_capacity(count);
+        let mut from_costs: Vec<Vec<u32>> = Vec::with_capacity(count);
+        let mut to_costs: Vec<Vec<u32>> = Vec::with_capacity(count);
+
+        // minimum cost between each node and selected landmarks on either direction, starting
+        // from a random node
+        let (mut min_costs, _) = graph.one_to_all(roots[0], IdxLinkDir::Forward);
+        let (to, _) = graph.one_to_all(roots[0], IdxLinkDir::Backward);
+        for (c, to) in min_costs.iter_mut().zip(to) {
+            *c = std::cmp::min(*c, to);
+        }
+
+        for root in roots.iter().skip(1) {
+            let selected = match selection {
+                LandmarkSelection::Farthest => None,
+                LandmarkSelection::Avoid => {
+                    let landmarks = Landmarks {
+                        node_len,
+                        nodes: nodes.clone(),
+                        from: interleave(&from_costs, node_len),
+                        to: interleave(&to_costs, node_len),
+                    };
+                    landmarks.select_avoid(graph, *root)
+                }
+            };
+            // farthest node, where unreachable nodes are the farthest
+            let landmark = selected.unwrap_or_else(|| {
+                *candidates
+                    .iter()
+                    .filter(|k| !nodes.contains(*k))
+                    .max_by_key(|k| (min_costs[k.index()], Reverse(**k)))
+                    .unwrap()
+            });
+
+            let (from, _) = graph.one_to_all(landmark, IdxLinkDir::Forward);
+            let (to, _) = graph.one_to_all(landmark, IdxLinkDir::Backward);
+            for (i, c) in min_costs.iter_mut().enumerate() {
+                *c = std::cmp::min(*c, std::cmp::min(from[i], to[i]));
+            }
+
+            nodes.push(landmark);
+            from_costs.push(from);
+            to_costs.push(to);
+        }
+
+        eprintln!(
+            "selecting landmarks took: {}, landmarks={}",
+            sw.took(),
+            nodes.len()
+        );
+        Self {
+            node_len,
+            nodes,
+            from: interleave(&from_costs, node_len),
+            to: interleave(&to_costs, node_len),
+        }
+    }
+
+    /// descend shortest path tree from `root` toward the subtree with the largest sum of
+    /// differences between costs and lower bounds, skipping subtrees with a landmark
+    fn select_avoid(&self, graph: &Graph, root: IdxNodeKey) -> Option<IdxNodeKey> {
+        let (costs, prevs) = graph.one_to_all(root, IdxLinkDir::Forward);
+        let all = (0..self.nodes.len()).collect::<Vec<_>>();
+
+        let mut weights = vec![0u64; graph.node_len];
+        let mut reached = (0..graph.node_len)
+            .filter(|i| costs[*i] != u32::MAX)
+            .map(IdxNodeKey::new)
+            .collect::<Vec<_>>();
+        for key in reached.iter() {
+            let cost = costs[key.index()];
+            weights[key.index()] = (cost - self.bound(&all, root, *key).min(cost)) as u64;
+        }
+
+        // children are visited after parents in ascending cost, so sizes are summed up in
+        // reverse order
+        reached.sort_unstable_by_key(|k| (costs[k.index()], *k));
+        let mut has_landmark = vec![false; graph.node_len];
+        for key in self.nodes.iter() {
+            has_landmark[key.index()] = true;
+        }
+        for key in reached.iter().rev() {
+            let prev = prevs[key.index()];
+            if prev == *key {
+                continue;
+            }
+            if has_landmark[key.index()] {
+                has_landmark[prev.index()] = true;
+            }
+            weights[prev.index()] += weights[key.index()];
+        }
+
+        let mut children = vec![Vec::new(); graph.node_len];
+        for key in reached.iter() {
+            let prev = prevs[key.index()];
+            if prev != *key && !has_landmark[key.index()] {
+                children[prev.index()].push(*key);
+            }
+        }
+
+        if has_landmark[root.index()] && children[root.index()].is_empty() {
+            return None;
+        }
+        let mut cur = root;
+        while let Some(next) = children[cur.index()]
+            .iter()
+            .max_by_key(|k| (weights[k.index()], Reverse(**k)))
+        {
+            cur = *next;
+        }
+        if self.nodes.contains(&cur) {
+            None
+        } else {
+            Some(cur)
+        }
+    }
+
+    pub fn nodes(&self) -> &[IdxNodeKey] {
+        &self.nodes
+    }
+
+    pub fn node_len(&self) -> usize {
+        self.node_len
+    }
+
+    /// landmarks giving best bounds between `src` and `dst`
+    fn active(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Vec<usize> {
+        let mut bounds = (0..self.nodes.len())
+            .map(|i| (self.bound(&[i], src, dst), i))
+            .filter(|(bound, _)| *bound > 0)
+            .collect::<Vec<_>>();
+        bounds.sort_unstable_by_key(|(bound, i)| (Reverse(*bound), *i));
+        bounds
+            .into_iter()
+            .take(ACTIVE_LANDMARKS)
+            .map(|(_, i)| i)
+            .collect()
+    }
+
+    /// lower bound of cost from `key` to `dst` by triangle inequality on given landmarks
+    #[inline]
+    fn bound(&self, landmarks: &[usize], key: IdxNodeKey, dst: IdxNodeKey) -> u32 {
+        let len = self.nodes.len();
+        let (k, d) = (key.index() * len, dst.index() * len);
+        let mut bound = 0;
+        for &i in landmarks {
+            // cost(l, dst) - cost(l, key)
+            let (from_k, from_d) = (self.from[k + i], self.from[d + i]);
+            if from_k != u32::MAX && from_d != u32::MAX && from_d > from_k {
+                bound = std::cmp::max(bound, from_d - from_k);
+            }
+            // cost(key, l) - cost(dst, l)
+            let (to_k, to_d) = (self.to[k + i], self.to[d + i]);
+            if to_k != u32::MAX && to_d != u32::MAX && to_k > to_d {
+                bound = std::cmp::max(bound, to_k - to_d);
+            }
+        }
+        bound
+    }
+
+    /// lower bound of cost from `src` to `dst` using all landmarks
+    pub fn lower_bound(&self, src: IdxNodeKey, dst: IdxNodeKey) -> u32 {
+        let all = (0..self.nodes.len()).collect::<Vec<_>>();
+        self.bound(&all, src, dst)
+    }
+
+    /// write to `landmarks.bin` in `path` directory
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+
+        let node_bytes = unsafe { as_bytes(&self.nodes) };
+        let from_bytes = unsafe { as_bytes(&self.from) };
+        let to_bytes = unsafe { as_bytes(&self.to) };
+
+        let header = Header {
+            magic: MAGIC,
+            version: VERSION,
+            endian: ENDIAN_MARK,
+            node_len: self.node_len() as u64,
+            landmark_len: self.nodes.len() as u64,
+            checksum: checksum(&[node_bytes, from_bytes, to_bytes]),
+        };
+
+        let mut writer = BufWriter::new(File::create(path.join(LANDMARK_FILE_NAME))?);
+        writer.write_all(unsafe { as_bytes(std::slice::from_ref(&header)) })?;
+        for bytes in [node_bytes, from_bytes, to_bytes].iter() {
+            writer.write_all(bytes)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// load from `landmarks.bin` in `path` directory, written by `Landmarks::write`
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().join(LANDMARK_FILE_NAME);
+        let file = File::open(&path)?;
+        let mmap = unsafe { MmapOptions::new().map(&file)? };
+
+        if mmap.len() < HEADER_LEN {
+            bail!("invalid landmark file: {:?}, too short", path);
+        }
+        let header = unsafe { std::ptr::read_unaligned(mmap.as_ptr() as *const Header) };
+        if header.magic != MAGIC {
+            bail!("invalid landmark file: {:?}, bad magic", path);
+        }
+        if header.endian != ENDIAN_MARK {
+            bail!("invalid landmark file: {:?}, endianness mismatch", path);
+        }
+        if header.version != VERSION {
+            bail!(
+                "invalid landmark file: {:?}, unsupported version {}",
+                path,
+                header.version
+            );
+        }
+
+        let landmark_len = header.landmark_len as usize;
+        let table_len = header.node_len as usize * landmark_len;
+        let expected_len = HEADER_LEN + (landmark_len + table_len * 2) * std::mem::size_of::<u32>();
+        if mmap.len() != expected_len {
+            bail!(
+                "invalid landmark file: {:?}, expected {} bytes, found {}",
+                path,
+                expected_len,
+                mmap.len()
+            );
+        }
+
+        let read = |offset: usize, len: usize| -> Vec<u32> {
+            mmap[HEADER_LEN + offset * 4..HEADER_LEN + (offset + len) * 4]
+                .par_chunks_exact(4)
+                .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                .collect()
+        };
+        let landmarks = Self {
+            node_len: header.node_len as usize,
+            nodes: read(0, landmark_len)
+                .into_iter()
+                .map(|v| IdxNodeKey::new(v as usize))
+                .collect(),
+            from: read(landmark_len, table_len),
+            to: read(landmark_len + table_len, table_len),
+        };
+
+        let sum = unsafe {
+            checksum(&[
+                as_bytes(&landmarks.nodes),
+                as_bytes(&landmarks.from),
+                as_bytes(&landmarks.to),
+            ])
+        };
+        if sum != header.checksum {
+            bail!("invalid landmark file: {:?}, checksum mismatch", path);
+        }
+        Ok(landmarks)
+    }
+}
+
+/// per-landmark cost tables to a table indexed by `node * tables.len() + landmark`
+fn interleave(tables: &[Vec<u32>], node_len: usize) -> Vec<u32> {
+    (0..node_len)
+        .into_par_iter()
+        .flat_map_iter(|i| tables.iter().map(move |t| t[i]))
+        .collect()
+}
+
+const _CHECK_HEADER: [u8; 40] = [0; HEADER_LEN];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn alt_matches_dijkstra() {
+        let graph = testutil::grid_with_dead_end(7, 21);
+
+        for selection in [LandmarkSelection::Farthest, LandmarkSelection::Avoid] {
+            let landmarks = Landmarks::build(&graph, 4, selection);
+            for (src, dst) in testutil::all_pairs(graph.node_len) {
+                assert_eq!(
+                    graph.search_alt(&landmarks, src, dst).map(|r| r.1),
+                    graph.search(src, dst).map(|r| r.1),
+                    "{:?}: {:?} -> {:?}",
+                    selection,
+                    src,
+                    dst
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn astar_matches_dijkstra() {
+        let graph = testutil::grid(7, 23);
+        for dst in 0..graph.node_len {
+            let dst = IdxNodeKey::new(dst);
+            // exact costs to `dst`, which is the tightest consistent heuristic
+            let (exact, _) = graph.one_to_all(dst, IdxLinkDir::Backward);
+            for src in 0..graph.node_len {
+                let src = IdxNodeKey::new(src);
+                let expected = graph.search(src, dst).map(|r| r.1);
+                let zero = graph.search_astar(src, dst, |_| 0);
+                let tight = graph.search_astar(src, dst, |k| exact[k.index()]);
+                assert_eq!(zero.map(|r| r.1), expected);
+                assert_eq!(tight.map(|r| r.1), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn astar_skips_overflowing_labels() {
+        // heuristic of node 1 overflows with its cost, while the path through node 2 is fine
+        let graph = testutil::graph(4, &[(0, 1, 10), (1, 3, 10), (0, 2, 30), (2, 3, 30)]);
+        let heuristic = |k: IdxNodeKey| if k.index() == 1 { u32::MAX - 5 } else { 0 };
+        assert_eq!(
+            graph.search_astar(IdxNodeKey::new(0), IdxNodeKey::new(3), heuristic),
+            Some((
+                vec![IdxNodeKey::new(0), IdxNodeKey::new(2), IdxNodeKey::new(3)],
+                60
+            ))
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::*;
+use clap::{value_parser, Arg, Command};
+
+use ch::*;
+
+fn main() -> Result<()> {
+    let args = Command::new("alt-build")
+        .author("Jihyun Yu <j.yu@naverlabs.com>")
+        .arg(Arg::new("network").long("network").required(true))
+        .arg(Arg::new("ty").long("ty").required(true))
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_parser(COST_MODELS)
+                .help("cost model of road network, default: fastest"),
+        )
+        .arg(Arg::new("out").long("out").required(true))
+        .arg(
+            Arg::new("landmarks")
+                .long("landmarks")
+                .value_parser(value_parser!(usize))
+                .default_value("16")
+                .help("number of landmarks"),
+        )
+        .arg(
+            Arg::new("selection")
+                .long("selection")
+                .value_parser(["farthest", "avoid"])
+                .default_value("avoid")
+                .help("landmark selection"),
+        )
+        .get_matches();
+
+    let network_path = args.get_one::<String>("network").unwrap();
+    let out_path = args.get_one::<String>("out").unwrap();
+    let ty = args.get_one::<String>("ty").unwrap();
+    let profile = args.get_one::<String>("profile");
+    if profile.is_some() && ty != "road" {
+        bail!("--profile is only supported for road network");
+    }
+    let count = *args.get_one::<usize>("landmarks").unwrap();
+    let selection = match args.get_one::<String>("selection").unwrap().as_str() {
+        "farthest" => LandmarkSelection::Farthest,
+        _ => LandmarkSelection::Avoid,
+    };
+
+    let sw = took::Timer::new();
+    let g = match ty.as_str() {
+        "walk" => {
+            let network = walk::Network::from_path(network_path)?;
+            Graph::from(&network)
+        }
+        "road" => {
+            let network = road::Network::from_path(network_path)?;
+            let model = cost_model(profile.map_or("fastest", |p| p.as_str()))?;
+            network.to_graph(model.as_ref())
+        }
+        "shp" => {
+            let network = shp::Network::from_path(network_path)?;
+            Graph::from(&network)
+        }
+        _ => {
+            bail!("unknown type: {}", ty);
+        }
+    };
+    eprintln!("graph took: {}", sw.took());
+
+    let landmarks = Landmarks::build(&g, count, selection);
+
+    let sw = took::Timer::new();
+    landmarks.write(out_path)?;
+    eprintln!("writing landmarks took: {}", sw.took());
+    Ok(())
+}
@@ -1,21 +1,197 @@
 use anyhow::*;
-use clap::{Arg, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 
 use ch::*;
 
+/// parse hop steppings, formatted as `deg:hop,deg:hop,..`
+fn parse_hop_steppings(s: &str) -> Result<Vec<(f32, u16)>> {
+    s.split(',')
+        .map(|item| {
+            let (deg, hop) = item
+                .split_once(':')
+                .with_context(|| format!("invalid hop stepping: {}", item))?;
+            Ok((deg.trim().parse()?, hop.trim().parse()?))
+        })
+        .collect()
+}
+
+fn build_config(args: &ArgMatches) -> Result<CHBuildConfig> {
+    let mut config = CHBuildConfig::default();
+    if let Some(step) = args.get_one::<usize>("step") {
+        config.step = *step;
+        config.heap_min_interval = step * 2;
+    }
+    if let Some(v) = args.get_one::<i32>("score-tolerance") {
+        config.score_tolerance = *v;
+    }
+    if let Some(v) = args.get_one::<usize>("heap-min-interval") {
+        config.heap_min_interval = *v;
+    }
+    if let Some(v) = args.get_one::<String>("hop-steppings") {
+        config.hop_steppings = parse_hop_steppings(v)?;
+    }
+    if let Some(v) = args.get_one::<usize>("heap-limit") {
+        config.heap_limit = *v;
+    }
+    config.parallel = args.get_flag("parallel");
+
+    let priority = &mut config.priority;
+    for (name, weight) in [
+        ("edge-difference", &mut priority.edge_difference),
+        ("deleted-neighbors", &mut priority.deleted_neighbors),
+        ("original-edges", &mut priority.original_edges),
+        ("level", &mut priority.level),
+    ] {
+        if let Some(v) = args.get_one::<i32>(name) {
+            *weight = *v;
+        }
+    }
+    Ok(config)
+}
+
 fn main() -> Result<()> {
     let args = Command::new("ch-build")
         .author("Jihyun Yu <j.yu@naverlabs.com>")
         .arg(Arg::new("network").long("network").required(true))
         .arg(Arg::new("ty").long("ty").required(true))
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_parser(COST_MODELS)
+                .help("cost model of road network, default: fastest"),
+        )
         .arg(Arg::new("out").long("out").required(true))
+        .arg(
+            Arg::new("cch")
+                .long("cch")
+                .action(ArgAction::SetTrue)
+                .help("build customizable contraction hierarchies with current costs"),
+        )
+        .arg(
+            Arg::new("overflow")
+                .long("overflow")
+                .value_parser(["error", "saturate"])
+                .default_value("saturate")
+                .help("on shp link whose cost exceeds 30 bits, fail or saturate and report count"),
+        )
+        .arg(
+            Arg::new("renumber")
+                .long("renumber")
+                .value_parser(["order", "bfs", "curve"])
+                .conflicts_with("cch")
+                .help("write hierarchy with nodes renumbered by given method, curve requires shp"),
+        )
+        .arg(
+            Arg::new("step")
+                .long("step")
+                .value_parser(value_parser!(usize))
+                .help("print progress for every given number of nodes, default: 10000"),
+        )
+        .arg(
+            Arg::new("score-tolerance")
+                .long("score-tolerance")
+                .value_parser(value_parser!(i32))
+                .allow_negative_numbers(true)
+                .help("tolerance of re-calculated score, default: 2"),
+        )
+        .arg(
+            Arg::new("heap-min-interval")
+                .long("heap-min-interval")
+                .value_parser(value_parser!(usize))
+                .help("minimum number of tries between rebuilding heap, default: step * 2"),
+        )
+        .arg(
+            Arg::new("hop-steppings")
+                .long("hop-steppings")
+                .help("hop limits by average degree, default: 8:5,5:3,3.3:2"),
+        )
+        .arg(
+            Arg::new("heap-limit")
+                .long("heap-limit")
+                .value_parser(value_parser!(usize))
+                .help("maximum number of heap items of witness search, default: 1024"),
+        )
+        .arg(
+            Arg::new("parallel")
+                .long("parallel")
+                .action(ArgAction::SetTrue)
+                .help("contract independent sets of nodes in parallel"),
+        )
+        .arg(
+            Arg::new("check-deterministic")
+                .long("check-deterministic")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("resume")
+                .help("build twice and fail if results differ"),
+        )
+        .arg(
+            Arg::new("checkpoint")
+                .long("checkpoint")
+                .help("directory to write checkpoint of build"),
+        )
+        .arg(
+            Arg::new("checkpoint-interval")
+                .long("checkpoint-interval")
+                .value_parser(value_parser!(usize))
+                .help("write checkpoint for every given number of contracted nodes"),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .action(ArgAction::SetTrue)
+                .requires("checkpoint")
+                .conflicts_with_all([
+                    "score-tolerance",
+                    "heap-min-interval",
+                    "hop-steppings",
+                    "heap-limit",
+                    "parallel",
+                    "edge-difference",
+                    "deleted-neighbors",
+                    "original-edges",
+                    "level",
+                ])
+                .help("resume build from checkpoint, with config of checkpoint"),
+        )
+        .args(
+            [
+                ("edge-difference", "weight of edge difference, default: 1"),
+                (
+                    "deleted-neighbors",
+                    "weight of deleted neighbors, default: 1",
+                ),
+                (
+                    "original-edges",
+                    "weight of original edge count, default: 0",
+                ),
+                ("level", "weight of level, default: 0"),
+            ]
+            .map(|(name, help)| {
+                Arg::new(name)
+                    .long(name)
+                    .value_parser(value_parser!(i32))
+                    .allow_negative_numbers(true)
+                    .help(help)
+            }),
+        )
         .get_matches();
 
     let network_path = args.get_one::<String>("network").unwrap();
     let out_path = args.get_one::<String>("out").unwrap();
     let ty = args.get_one::<String>("ty").unwrap();
+    let profile = args.get_one::<String>("profile");
+    if profile.is_some() && ty != "road" {
+        bail!("--profile is only supported for road network");
+    }
+    let overflow = args.get_one::<String>("overflow").unwrap();
+    let renumber = args.get_one::<String>("renumber");
+    if renumber.map(|r| r.as_str()) == Some("curve") && ty != "shp" {
+        bail!("--renumber curve is only supported for shp network");
+    }
 
     let sw = took::Timer::new();
+    // positions of nodes on space-filling curve, only for network with coordinates
+    let mut positions = None;
     let g = match ty.as_str() {
         "walk" => {
             let network = walk::Network::from_path(network_path)?;
@@ -23,11 +199,18 @@ fn main() -> Result<()> {
         }
         "road" => {
             let network = road::Network::from_path(network_path)?;
-            Graph::from(&network)
+            let model = cost_model(profile.map_or("fastest", |p| p.as_str()))?;
+            network.to_graph(model.as_ref())
         }
         "shp" => {
+            // lengths are in centimeters, so long links may overflow
             let network = shp::Network::from_path(network_path)?;
-            Graph::from(&network)
+            positions = Some(network.curve_positions());
+            if overflow == "error" {
+                WideGraph::from(&network).to_graph()?
+            } else {
+                Graph::from(&network)
+            }
         }
         _ => {
             bail!("unknown type: {}", ty);
@@ -35,12 +218,77 @@ fn main() -> Result<()> {
     };
     eprintln!("graph took: {}", sw.took());
 
-    let mut ch = CH::new(&g);
+    if args.get_flag("cch") {
+        let sw = took::Timer::new();
+        let cch = CCH::new(&g);
+        let ch = cch.customize_graph(&g)?;
+        eprintln!("cch build took: {}", sw.took());
+
+        cch.write(out_path)?;
+        ch.write(out_path)?;
+        return Ok(());
+    }
+
+    let mut config = build_config(&args)?;
+    let checkpoint_path = args.get_one::<String>("checkpoint");
+    let mut ch = match checkpoint_path {
+        Some(path) if args.get_flag("resume") => {
+            let ch = CH::resume(&g, path)?;
+            config = CHBuildConfig {
+                step: config.step,
+                ..ch.config().clone()
+            };
+            ch
+        }
+        _ => CH::new(&g),
+    };
+    eprintln!("config: {:?}", config);
+
+    // first signal cancels build, writing checkpoint if `--checkpoint` is given, and second one
+    // exits immediately
+    let cancel = CancelToken::new();
+    for signal in signal_hook::consts::TERM_SIGNALS {
+        signal_hook::flag::register_conditional_shutdown(*signal, 1, cancel.flag().clone())?;
+        signal_hook::flag::register(*signal, cancel.flag().clone())?;
+    }
+    let hooks = CHBuildHooks {
+        cancel: Some(cancel),
+        checkpoint_path: checkpoint_path.map(Into::into),
+        checkpoint_interval: args
+            .get_one::<usize>("checkpoint-interval")
+            .copied()
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+
     let sw = took::Timer::new();
-    ch.build();
+    ch.build_with_hooks(config.clone(), &hooks)?;
     eprintln!("ch build took: {}", sw.took());
 
-    ch.write(out_path).expect("failed to write");
+    if args.get_flag("check-deterministic") {
+        let sw = took::Timer::new();
+        let mut ch2 = CH::new(&g);
+        ch2.build_with_config(config);
+        eprintln!("second ch build took: {}", sw.took());
+        if !ch.is_identical(&ch2) {
+            bail!("ch build is not deterministic");
+        }
+    }
+
+    if let Some(method) = renumber {
+        let sw = took::Timer::new();
+        let renumbering = match method.as_str() {
+            "order" => Renumbering::by_order(ch.order()),
+            "bfs" => Renumbering::by_bfs(&g),
+            _ => Renumbering::by_curve(positions.as_ref().unwrap()),
+        };
+        let query = ch.to_query().with_renumbering(renumbering);
+        eprintln!("renumbering by {} took: {}", method, sw.took());
+        query.write(out_path).expect("failed to write");
+        ch.config().write(out_path)?;
+    } else {
+        ch.write(out_path).expect("failed to write");
+    }
 
     Ok(())
 }
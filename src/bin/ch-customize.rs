@@ -0,0 +1,89 @@
+use anyhow::*;
+use clap::{Arg, Command};
+
+use ch::*;
+
+/// read costs of links, one cost per line in order of `Graph::forward_links`
+fn read_costs(path: &str) -> Result<Vec<u32>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_path(path)?;
+    let mut costs = Vec::new();
+    for result in rdr.deserialize() {
+        let cost: u32 = result?;
+        costs.push(cost);
+    }
+    Ok(costs)
+}
+
+fn main() -> Result<()> {
+    let args = Command::new("ch-customize")
+        .author("Jihyun Yu <j.yu@naverlabs.com>")
+        .arg(Arg::new("network").long("network").required(true))
+        .arg(Arg::new("ty").long("ty").required(true))
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_parser(COST_MODELS)
+                .help("cost model of road network, default: fastest"),
+        )
+        .arg(
+            Arg::new("cch")
+                .long("cch")
+                .required(true)
+                .help("directory of cch.bin written by ch-build --cch"),
+        )
+        .arg(
+            Arg::new("costs")
+                .long("costs")
+                .help("csv of link costs, one per line in order of forward links of graph"),
+        )
+        .arg(
+            Arg::new("out")
+                .long("out")
+                .help("directory to write ch.bin, default: directory of --cch"),
+        )
+        .get_matches();
+
+    let network_path = args.get_one::<String>("network").unwrap();
+    let cch_path = args.get_one::<String>("cch").unwrap();
+    let out_path = args.get_one::<String>("out").unwrap_or(cch_path);
+    let ty = args.get_one::<String>("ty").unwrap();
+    let profile = args.get_one::<String>("profile");
+    if profile.is_some() && ty != "road" {
+        bail!("--profile is only supported for road network");
+    }
+
+    let sw = took::Timer::new();
+    let g = match ty.as_str() {
+        "walk" => {
+            let network = walk::Network::from_path(network_path)?;
+            Graph::from(&network)
+        }
+        "road" => {
+            let network = road::Network::from_path(network_path)?;
+            let model = cost_model(profile.map_or("fastest", |p| p.as_str()))?;
+            network.to_graph(model.as_ref())
+        }
+        "shp" => {
+            let network = shp::Network::from_path(network_path)?;
+            Graph::from(&network)
+        }
+        _ => {
+            bail!("unknown type: {}", ty);
+        }
+    };
+    eprintln!("graph took: {}", sw.took());
+
+    let sw = took::Timer::new();
+    let cch = CCH::from_file(&g, cch_path)?;
+    eprintln!("loading cch took: {}", sw.took());
+
+    let query = match args.get_one::<String>("costs") {
+        Some(path) => cch.customize(&read_costs(path)?)?,
+        None => cch.customize_graph(&g)?,
+    };
+
+    query.write(out_path)?;
+    Ok(())
+}
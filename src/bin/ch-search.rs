@@ -1,4 +1,4 @@
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 
 use ch::*;
 
@@ -13,14 +13,19 @@ fn main() {
         .arg(Arg::new("emesh").long("emesh").required(true))
         .arg(Arg::new("elink").long("elink").required(true))
         .arg(Arg::new("enode").long("enode").required(true))
+        .arg(
+            Arg::new("renumber")
+                .long("renumber")
+                .action(ArgAction::SetTrue)
+                .help("renumber nodes by descending order before search, unless renumbered by ch-build"),
+        )
         .get_matches();
 
     let network_path = args.get_one::<String>("network").unwrap();
     let ch_path = args.get_one::<String>("ch").unwrap();
 
     let network = road::Network::from_path(network_path).expect("failed to load network");
-    let graph = Graph::from(&network);
-    let ch = CH::from_file(&graph, ch_path).expect("failed to load ch");
+    let ch = CHQuery::from_file(ch_path).expect("failed to load ch");
 
     let smesh = args
         .get_one::<String>("smesh")
@@ -57,10 +62,34 @@ fn main() {
     let src = road::LinkKey::new(smesh, slink, snode);
     let dst = road::LinkKey::new(emesh, elink, enode);
 
-    let src = network.link_key_to_idx(src);
-    let dst = network.link_key_to_idx(dst);
+    let route = if args.get_flag("renumber") && ch.renumbering().is_none() {
+        let sw = took::Timer::new();
+        let ch = ch.into_renumbered();
+        eprintln!("renumbering took {}", sw.took());
 
-    let sw = took::Timer::new();
-    let (_seq, dist) = ch.search(src, dst).expect("failed to find");
-    eprintln!("took {}, {} links, cost={}", sw.took(), _seq.len(), dist);
+        let sw = took::Timer::new();
+        let route = network.search_route(&ch, src, dst);
+        eprintln!("search took {}", sw.took());
+        route
+    } else {
+        let sw = took::Timer::new();
+        let route = network.search_route(&ch, src, dst);
+        eprintln!("search took {}", sw.took());
+        route
+    };
+    let route = route.expect("invalid link").expect("failed to find");
+    eprintln!(
+        "{} links, cost={}, length={}, time={}",
+        route.items.len(),
+        route.cost,
+        route.length(),
+        route.time()
+    );
+
+    for item in route.items.iter() {
+        println!(
+            "{:?}\t{}\t{}\t{}\t{}",
+            item.key, item.length, item.max_speed, item.time, item.cost
+        );
+    }
 }
@@ -0,0 +1,353 @@
+use anyhow::*;
+use clap::{value_parser, Arg, ArgAction, Command};
+
+use ch::*;
+
+fn main() -> Result<()> {
+    let args = Command::new("ch-verify")
+        .author("Jihyun Yu <j.yu@naverlabs.com>")
+        .arg(Arg::new("network").long("network").required(true))
+        .arg(Arg::new("ty").long("ty").required(true))
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_parser(COST_MODELS)
+                .help("cost model of road network, default: fastest"),
+        )
+        .arg(Arg::new("ch").long("ch").required(true))
+        .arg(
+            Arg::new("queries")
+                .long("queries")
+                .value_parser(value_parser!(usize))
+                .default_value("1000")
+                .help("number of random queries"),
+        )
+        .arg(
+            Arg::new("matrix")
+                .long("matrix")
+                .value_parser(value_parser!(usize))
+                .default_value("32")
+                .help("verify many-to-many of given number of sources and targets of queries"),
+        )
+        .arg(
+            Arg::new("phast")
+                .long("phast")
+                .value_parser(value_parser!(usize))
+                .default_value("4")
+                .help("verify one-to-all of given number of sources of queries"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_parser(value_parser!(u64))
+                .help("seed of random queries, current time if not given"),
+        )
+        .arg(
+            Arg::new("renumber")
+                .long("renumber")
+                .value_parser(["order", "bfs", "curve"])
+                .help("also verify hierarchy renumbered by given method, curve requires shp"),
+        )
+        .arg(
+            Arg::new("landmarks")
+                .long("landmarks")
+                .help("also verify ALT search with landmarks of given directory, and A* on shp"),
+        )
+        .arg(
+            Arg::new("wide")
+                .long("wide")
+                .action(ArgAction::SetTrue)
+                .help("also verify costs with 64-bit dijkstra, reporting paths which overflow"),
+        )
+        .get_matches();
+
+    let network_path = args.get_one::<String>("network").unwrap();
+    let ch_path = args.get_one::<String>("ch").unwrap();
+    let ty = args.get_one::<String>("ty").unwrap();
+    let profile = args.get_one::<String>("profile");
+    if profile.is_some() && ty != "road" {
+        bail!("--profile is only supported for road network");
+    }
+    let queries = *args.get_one::<usize>("queries").unwrap();
+    let seed = args.get_one::<u64>("seed").copied();
+
+    let renumber = args.get_one::<String>("renumber");
+    if renumber.map(|r| r.as_str()) == Some("curve") && ty != "shp" {
+        bail!("--renumber curve is only supported for shp network");
+    }
+
+    let sw = took::Timer::new();
+    // positions of nodes on space-filling curve, only for network with coordinates
+    let mut positions = None;
+    // graph with costs before saturation, only for network whose costs may overflow
+    let mut wide = None;
+    // network with coordinates, for A* search
+    let mut shp_network = None;
+    let g = match ty.as_str() {
+        "walk" => {
+            let network = walk::Network::from_path(network_path)?;
+            Graph::from(&network)
+        }
+        "road" => {
+            let network = road::Network::from_path(network_path)?;
+            let model = cost_model(profile.map_or("fastest", |p| p.as_str()))?;
+            network.to_graph(model.as_ref())
+        }
+        "shp" => {
+            let network = shp::Network::from_path(network_path)?;
+            positions = Some(network.curve_positions());
+            // saturated as ch-build does by default, same graph if no link overflows
+            let g = Graph::from(&network);
+            wide = Some(WideGraph::from(&network));
+            shp_network = Some(network);
+            g
+        }
+        _ => {
+            bail!("unknown type: {}", ty);
+        }
+    };
+    eprintln!("graph took: {}", sw.took());
+
+    let ch = CH::from_file(&g, ch_path)?;
+
+    let (pairs, seed) = random_pairs(g.node_len, queries, seed);
+    eprintln!("verifying {} queries, seed={}", pairs.len(), seed);
+
+    let sw = took::Timer::new();
+    let report = verify(&g, &ch, &pairs);
+    eprintln!(
+        "verify took: {}, queries={}, reachable={}, errors={}",
+        sw.took(),
+        report.queries,
+        report.reachable,
+        report.errors.len()
+    );
+
+    for error in report.errors.iter() {
+        println!(
+            "{:?}\t{:?}\t{}\t{}\t{:?}\t{:?}",
+            error.search,
+            error.kind,
+            error.src.index(),
+            error.dst.index(),
+            error.expected,
+            error.actual
+        );
+    }
+
+    for search in [
+        VerifySearch::Dijkstra,
+        VerifySearch::Bidir,
+        VerifySearch::CH,
+    ] {
+        eprintln!(
+            "{:?}: cost mismatches={}, unreachable={}, path costs={}",
+            search,
+            report.count(search, VerifyErrorKind::CostMismatch),
+            report.count(search, VerifyErrorKind::Unreachable),
+            report.count(search, VerifyErrorKind::PathCost),
+        );
+    }
+    if g.secondary.is_empty() {
+        eprintln!(
+            "CH: secondary mismatches=-, {} network has no secondary metric",
+            ty
+        );
+    } else {
+        eprintln!(
+            "CH: secondary mismatches={}",
+            report.count(VerifySearch::CH, VerifyErrorKind::SecondaryMismatch)
+        );
+    }
+
+    if !report.is_ok() {
+        bail!("found {} errors, seed={}", report.errors.len(), seed);
+    }
+
+    // average work of CH queries, to compare stall-on-demand across builds
+    let mut stats = SearchStats::default();
+    for (src, dst) in pairs.iter() {
+        let (_, s) = ch.search_with_stats(*src, *dst);
+        stats.settled += s.settled;
+        stats.relaxed += s.relaxed;
+        stats.stalled += s.stalled;
+    }
+    let count = pairs.len().max(1) as f64;
+    eprintln!(
+        "CH: avg settled={:.1}, relaxed={:.1}, stalled={:.1}",
+        stats.settled as f64 / count,
+        stats.relaxed as f64 / count,
+        stats.stalled as f64 / count,
+    );
+
+    let matrix = *args.get_one::<usize>("matrix").unwrap();
+    let (srcs, dsts): (Vec<_>, Vec<_>) = pairs.iter().take(matrix).cloned().unzip();
+    let sw = took::Timer::new();
+    let report = verify_many_to_many(&g, &ch, &srcs, &dsts);
+    eprintln!(
+        "verify many-to-many took: {}, cells={}, reachable={}, cost mismatches={}, unreachable={}",
+        sw.took(),
+        report.queries,
+        report.reachable,
+        report.count(VerifySearch::ManyToMany, VerifyErrorKind::CostMismatch),
+        report.count(VerifySearch::ManyToMany, VerifyErrorKind::Unreachable),
+    );
+    for error in report.errors.iter() {
+        println!(
+            "{:?}\t{:?}\t{}\t{}\t{:?}\t{:?}",
+            error.search,
+            error.kind,
+            error.src.index(),
+            error.dst.index(),
+            error.expected,
+            error.actual
+        );
+    }
+    if !report.is_ok() {
+        bail!(
+            "found {} errors on many-to-many, seed={}",
+            report.errors.len(),
+            seed
+        );
+    }
+
+    let phast = *args.get_one::<usize>("phast").unwrap();
+    let srcs = pairs.iter().take(phast).map(|p| p.0).collect::<Vec<_>>();
+    let sw = took::Timer::new();
+    let report = verify_one_to_all(&g, &ch, &srcs);
+    eprintln!(
+        "verify one-to-all took: {}, nodes={}, reachable={}, cost mismatches={}, unreachable={}",
+        sw.took(),
+        report.queries,
+        report.reachable,
+        report.count(VerifySearch::Phast, VerifyErrorKind::CostMismatch),
+        report.count(VerifySearch::Phast, VerifyErrorKind::Unreachable),
+    );
+    if !report.is_ok() {
+        bail!(
+            "found {} errors on one-to-all, seed={}",
+            report.errors.len(),
+            seed
+        );
+    }
+
+    if args.get_flag("wide") {
+        let sw = took::Timer::new();
+        let wide = wide.unwrap_or_else(|| WideGraph::from(&g));
+        let mut mismatches = 0;
+        let mut overflows = 0;
+        for (src, dst) in pairs.iter() {
+            let expected = wide.search(*src, *dst).map(|(_, cost)| cost);
+            match (ch.search_bounded(*src, *dst, &Default::default()), expected) {
+                (SearchResult::Found(_, cost), Some(expected)) if cost as u64 == expected => {}
+                (SearchResult::Overflow, Some(expected)) if expected >= u32::MAX as u64 => {
+                    overflows += 1;
+                }
+                (SearchResult::Unreachable, None) => {}
+                (actual, expected) => {
+                    mismatches += 1;
+                    println!(
+                        "Wide\t{}\t{}\t{:?}\t{:?}",
+                        src.index(),
+                        dst.index(),
+                        expected,
+                        actual.into_option().map(|(_, cost)| cost)
+                    );
+                }
+            }
+        }
+        eprintln!(
+            "verify wide took: {}, overflows={}, cost mismatches={}",
+            sw.took(),
+            overflows,
+            mismatches
+        );
+        if mismatches > 0 {
+            bail!("found {} mismatches on wide, seed={}", mismatches, seed);
+        }
+    }
+
+    if let Some(path) = args.get_one::<String>("landmarks") {
+        let landmarks = Landmarks::from_file(path)?;
+        if landmarks.node_len() != g.node_len {
+            bail!(
+                "node count mismatch: graph={}, landmarks={}",
+                g.node_len,
+                landmarks.node_len()
+            );
+        }
+
+        let sw = took::Timer::new();
+        let mut alt_mismatches = 0;
+        let mut astar_mismatches = 0;
+        for (src, dst) in pairs.iter() {
+            let expected = g.search(*src, *dst).map(|(_, cost)| cost);
+            if g.search_alt(&landmarks, *src, *dst).map(|(_, cost)| cost) != expected {
+                alt_mismatches += 1;
+            }
+            if let Some(ref network) = shp_network {
+                if network.search_astar(&g, *src, *dst).map(|(_, cost)| cost) != expected {
+                    astar_mismatches += 1;
+                }
+            }
+        }
+        eprintln!(
+            "verify alt took: {}, alt mismatches={}, astar mismatches={}",
+            sw.took(),
+            alt_mismatches,
+            astar_mismatches
+        );
+        if alt_mismatches + astar_mismatches > 0 {
+            bail!(
+                "found {} mismatches on alt and {} on astar, seed={}",
+                alt_mismatches,
+                astar_mismatches,
+                seed
+            );
+        }
+    }
+
+    // hierarchy renumbered by given method, or by ch-build
+    let query = CHQuery::from_file(ch_path)?;
+    let renumbered: Option<Box<dyn ShortestPath>> = match renumber {
+        Some(_) if query.renumbering().is_some() => {
+            bail!("{} is already renumbered by ch-build", ch_path);
+        }
+        Some(method) => {
+            let sw = took::Timer::new();
+            let renumbered = match method.as_str() {
+                "order" => query.into_renumbered(),
+                "bfs" => query.into_renumbered_with(Renumbering::by_bfs(&g)),
+                _ => query.into_renumbered_with(Renumbering::by_curve(positions.as_ref().unwrap())),
+            };
+            eprintln!("renumbering by {} took: {}", method, sw.took());
+            Some(Box::new(renumbered))
+        }
+        None if query.renumbering().is_some() => Some(Box::new(query)),
+        None => None,
+    };
+
+    if let Some(renumbered) = renumbered {
+        let sw = took::Timer::new();
+        let mismatches = pairs
+            .iter()
+            .filter(|(src, dst)| {
+                let expected = g.search(*src, *dst).map(|(_, cost)| cost);
+                renumbered.shortest_path(*src, *dst).map(|(_, cost)| cost) != expected
+            })
+            .count();
+        eprintln!(
+            "verify renumbered took: {}, cost mismatches={}",
+            sw.took(),
+            mismatches
+        );
+        if mismatches > 0 {
+            bail!(
+                "found {} mismatches on renumbered, seed={}",
+                mismatches,
+                seed
+            );
+        }
+    }
+    Ok(())
+}
@@ -9,7 +9,7 @@ use rayon::prelude::*;
 type Result<T> = std::result::Result<T, Error>;
 
 // const FILENAME: &'static str = "data/planet-latest.osm.pbf";
-const FILENAME: &'static str = "data/seoul.osm.pbf";
+const FILENAME: &str = "data/seoul.osm.pbf";
 
 use std::f64;
 #[derive(Clone, Copy)]
@@ -142,5 +142,5 @@ fn main() {
 
     let filename = args.get_one::<String>("filename").unwrap();
 
-    seq_mmap(&filename).expect("failed to read");
+    seq_mmap(filename).expect("failed to read");
 }
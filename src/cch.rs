@@ -0,0 +1,585 @@
+use anyhow::{bail, Result};
+use rayon::prelude::*;
+
+use super::*;
+
+/// customizable contraction hierarchies. Node order and shortcut structure only depend on
+/// topology of the graph, so costs can be changed by `customize` without rebuilding hierarchy.
+pub struct CCH {
+    /// order of each node, starting from 1
+    pub(crate) order: Vec<u32>,
+
+    /// upward edges of each node in CSR form: edges of node `i` are
+    /// `heads[first_out[i]..first_out[i + 1]]`, sorted by node index
+    first_out: Vec<u32>,
+    pub(crate) heads: Vec<IdxNodeKey>,
+
+    /// maps each forward link of original graph to (edge, dir) of hierarchy. `dir` is forward if
+    /// link goes upward. Links are enumerated same as `Graph::forward_links`.
+    link_edges: Vec<(u32, IdxLinkDir)>,
+
+    /// lower neighbors of each node with the edge from them, in CSR form like `heads`
+    down_first: Vec<u32>,
+    downs: Vec<(IdxNodeKey, u32)>,
+    /// nodes grouped by levels, where lower neighbors of a node are on lower levels. Nodes of
+    /// a level are customized in parallel.
+    levels: Vec<Vec<IdxNodeKey>>,
+}
+
+/// file name of node order of `CCH` inside of output directory
+pub const CCH_FILE_NAME: &str = "cch.bin";
+
+const NO_EDGE: u32 = u32::MAX;
+
+/// middle node of link for query, from middle node of customization
+fn mid_key(mid: u32) -> IdxNodeKey {
+    if mid == NO_EDGE {
+        IdxNodeKey::NONE
+    } else {
+        IdxNodeKey::new(mid as usize)
+    }
+}
+
+enum Dissection {
+    Dissect(Vec<u32>),
+    Emit(Vec<u32>),
+}
+
+/// undirected adjacency without self-loops and duplicated links
+fn undirected_neighbors(graph: &Graph) -> Vec<Vec<u32>> {
+    graph
+        .idx_links
+        .par_iter()
+        .enumerate()
+        .map(|(i, links)| {
+            let mut v = links
+                .iter()
+                .map(|l| l.enode_idx.index() as u32)
+                .filter(|n| *n as usize != i)
+                .collect::<Vec<_>>();
+            v.sort_unstable();
+            v.dedup();
+            v
+        })
+        .collect()
+}
+
+/// metric-independent node order with recursive bisection. Each set of nodes is split with a
+/// BFS level from pseudo-peripheral node; separator nodes are ordered after both halves.
+fn nested_dissection(neighbors: &[Vec<u32>]) -> Vec<u32> {
+    let node_len = neighbors.len();
+
+    // nodes in current set are marked with `gen`
+    let mut mark = vec![0u32; node_len];
+    let mut gen = 0u32;
+    let mut level = vec![u32::MAX; node_len];
+
+    // returns nodes reachable from `src` within current set, with BFS levels
+    let bfs = |src: u32, gen: u32, mark: &[u32], level: &mut [u32]| -> Vec<u32> {
+        let mut queue = vec![src];
+        level[src as usize] = 0;
+        let mut i = 0;
+        while i < queue.len() {
+            let n = queue[i];
+            i += 1;
+            let next_level = level[n as usize] + 1;
+            for &m in neighbors[n as usize].iter() {
+                if mark[m as usize] != gen || level[m as usize] != u32::MAX {
+                    continue;
+                }
+                level[m as usize] = next_level;
+                queue.push(m);
+            }
+        }
+        queue
+    };
+
+    let mut sequence = Vec::with_capacity(node_len);
+    let mut stack = vec![Dissection::Dissect((0..node_len as u32).collect())];
+    while let Some(item) = stack.pop() {
+        let nodes = match item {
+            Dissection::Emit(nodes) => {
+                sequence.extend_from_slice(&nodes);
+                continue;
+            }
+            Dissection::Dissect(nodes) => nodes,
+        };
+        if nodes.len() <= 2 {
+            sequence.extend_from_slice(&nodes);
+            continue;
+        }
+
+        gen += 1;
+        for &n in nodes.iter() {
+            mark[n as usize] = gen;
+            level[n as usize] = u32::MAX;
+        }
+
+        let reached = bfs(nodes[0], gen, &mark, &mut level);
+        if reached.len() != nodes.len() {
+            // disconnected, dissect each component independently
+            let rest = nodes
+                .into_iter()
+                .filter(|n| level[*n as usize] == u32::MAX)
+                .collect::<Vec<_>>();
+            stack.push(Dissection::Dissect(rest));
+            stack.push(Dissection::Dissect(reached));
+            continue;
+        }
+
+        // restart from farthest node, which is pseudo-peripheral
+        let far = *reached.last().unwrap();
+        for &n in reached.iter() {
+            level[n as usize] = u32::MAX;
+        }
+        let reached = bfs(far, gen, &mark, &mut level);
+
+        let median = level[reached[reached.len() / 2] as usize];
+        let mut lower = Vec::new();
+        let mut upper = Vec::new();
+        let mut separator = Vec::new();
+        for &n in reached.iter() {
+            let l = level[n as usize];
+            if l > median {
+                upper.push(n);
+            } else if l == median
+                && neighbors[n as usize]
+                    .iter()
+                    .any(|m| mark[*m as usize] == gen && level[*m as usize] > median)
+            {
+                separator.push(n);
+            } else {
+                lower.push(n);
+            }
+        }
+
+        if upper.is_empty() || lower.is_empty() {
+            // failed to split
+            sequence.extend_from_slice(&reached);
+            continue;
+        }
+
+        stack.push(Dissection::Emit(separator));
+        stack.push(Dissection::Dissect(upper));
+        stack.push(Dissection::Dissect(lower));
+    }
+
+    let mut order = vec![0u32; node_len];
+    for (i, n) in sequence.into_iter().enumerate() {
+        order[n as usize] = i as u32 + 1;
+    }
+    order
+}
+
+impl CCH {
+    /// build metric-independent hierarchy from topology of `graph`
+    pub fn new(graph: &Graph) -> Self {
+        let sw = Timer::new();
+        let neighbors = undirected_neighbors(graph);
+        let order = nested_dissection(&neighbors);
+        eprintln!("nested dissection took: {}", sw.took());
+
+        Self::with_order(graph, neighbors, order)
+    }
+
+    /// rebuild hierarchy from node order written by `CCH::write`. Binary `cch.bin` is used if
+    /// exists, otherwise falls back to legacy `cch_order.csv`.
+    pub fn from_file<P>(graph: &Graph, path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let bin_path = path.join(CCH_FILE_NAME);
+        let order: Vec<u32> = if bin_path.exists() {
+            CHFile::open(bin_path, true)?.order().to_vec()
+        } else {
+            decode_csv_noheader(path.join("cch_order.csv"))?
+        };
+        if order.len() != graph.node_len {
+            bail!(
+                "node count mismatch: graph={}, cch={}",
+                graph.node_len,
+                order.len()
+            );
+        }
+        let neighbors = undirected_neighbors(graph);
+        Ok(Self::with_order(graph, neighbors, order))
+    }
+
+    /// write node order to `cch.bin`, which is enough to rebuild hierarchy for the same graph
+    /// topology. It is a `CHFile` without links and contractions.
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let links = Csr::from(vec![Vec::new(); self.node_len()]);
+        CHFile::write(
+            path.as_ref().join(CCH_FILE_NAME),
+            &self.order,
+            &links,
+            &[],
+            &[],
+            &[],
+            &[],
+        )
+    }
+
+    fn with_order(graph: &Graph, neighbors: Vec<Vec<u32>>, order: Vec<u32>) -> Self {
+        let node_len = graph.node_len;
+
+        // contract nodes in order, connecting all upward neighbors of contracted node. It is
+        // enough to connect upward neighbors to lowest one of them, as lowest one is contracted
+        // later and it propagates again.
+        let sw = Timer::new();
+        let mut ups = neighbors
+            .into_par_iter()
+            .enumerate()
+            .map(|(i, v)| {
+                v.into_iter()
+                    .filter(|n| order[*n as usize] > order[i])
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut sequence = (0..node_len as u32).collect::<Vec<_>>();
+        sequence.par_sort_unstable_by_key(|n| order[*n as usize]);
+        for &n in sequence.iter() {
+            let mut up = std::mem::take(&mut ups[n as usize]);
+            up.sort_unstable();
+            up.dedup();
+            if let Some(&lowest) = up.iter().min_by_key(|m| order[**m as usize]) {
+                let lowest_up = &mut ups[lowest as usize];
+                lowest_up.extend(up.iter().filter(|m| **m != lowest));
+            }
+            ups[n as usize] = up;
+        }
+
+        let mut first_out = Vec::with_capacity(node_len + 1);
+        let mut heads = Vec::new();
+        first_out.push(0);
+        for up in ups.iter() {
+            heads.extend(up.iter().map(|n| IdxNodeKey::new(*n as usize)));
+            first_out.push(heads.len() as u32);
+        }
+
+        let mut down_lists = vec![Vec::new(); node_len];
+        for (i, up) in ups.iter().enumerate() {
+            for (j, n) in up.iter().enumerate() {
+                let edge = first_out[i] + j as u32;
+                down_lists[*n as usize].push((IdxNodeKey::new(i), edge));
+            }
+        }
+        let mut node_levels = vec![0usize; node_len];
+        let mut levels: Vec<Vec<IdxNodeKey>> = Vec::new();
+        for &n in sequence.iter() {
+            let level = down_lists[n as usize]
+                .iter()
+                .map(|(m, _)| node_levels[m.index()] + 1)
+                .max()
+                .unwrap_or(0);
+            node_levels[n as usize] = level;
+            if levels.len() <= level {
+                levels.resize(level + 1, Vec::new());
+            }
+            levels[level].push(IdxNodeKey::new(n as usize));
+        }
+        let mut down_first = Vec::with_capacity(node_len + 1);
+        down_first.push(0);
+        let mut downs = Vec::new();
+        for v in down_lists.into_iter() {
+            downs.extend(v);
+            down_first.push(downs.len() as u32);
+        }
+
+        let mut cch = Self {
+            order,
+            first_out,
+            heads,
+            link_edges: Vec::new(),
+            down_first,
+            downs,
+            levels,
+        };
+
+        let link_edges = graph
+            .idx_links
+            .par_iter()
+            .enumerate()
+            .flat_map_iter(|(i, links)| {
+                let snode_idx = IdxNodeKey::new(i);
+                let cch = &cch;
+                links
+                    .iter()
+                    .take_while(|l| l.dir() == IdxLinkDir::Forward)
+                    .map(move |l| {
+                        let enode_idx = l.enode_idx;
+                        if cch.order[i] < cch.order[enode_idx.index()] {
+                            (cch.edge(snode_idx, enode_idx), IdxLinkDir::Forward)
+                        } else if snode_idx != enode_idx {
+                            (cch.edge(enode_idx, snode_idx), IdxLinkDir::Backward)
+                        } else {
+                            (NO_EDGE, IdxLinkDir::Forward)
+                        }
+                    })
+            })
+            .collect::<Vec<_>>();
+        cch.link_edges = link_edges;
+
+        eprintln!(
+            "cch topology took: {}, nodes={}, edges={}",
+            sw.took(),
+            node_len,
+            cch.heads.len()
+        );
+        cch
+    }
+
+    pub fn node_len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub(crate) fn edge_range(&self, key: IdxNodeKey) -> std::ops::Range<usize> {
+        let idx = key.index();
+        self.first_out[idx] as usize..self.first_out[idx + 1] as usize
+    }
+
+    /// edge from lower-ordered `snode_idx` to higher-ordered `enode_idx`
+    pub(crate) fn edge(&self, snode_idx: IdxNodeKey, enode_idx: IdxNodeKey) -> u32 {
+        let r = self.edge_range(snode_idx);
+        let start = r.start;
+        match self.heads[r].binary_search(&enode_idx) {
+            Ok(pos) => (start + pos) as u32,
+            Err(_) => panic!("missing cch edge: {:?} -> {:?}", snode_idx, enode_idx),
+        }
+    }
+
+    /// apply costs of `graph` which has same topology with the graph used to build hierarchy,
+    /// carrying secondary metric of `graph` as well
+    pub fn customize_graph(&self, graph: &Graph) -> Result<CHQuery> {
+        let costs = graph
+            .forward_links()
+            .map(|(_, l)| l.cost())
+            .collect::<Vec<_>>();
+        let mut query = self.customize(&costs)?;
+        query.carry_secondary(graph);
+        Ok(query)
+    }
+
+    /// compute weights of hierarchy for `costs`, which has a cost for each link index of
+    /// original graph, see `Graph::forward_links`. Result has no secondary metric.
+    pub fn customize(&self, costs: &[u32]) -> Result<CHQuery> {
+        if costs.len() != self.link_edges.len() {
+            bail!(
+                "invalid costs: expected {} links, found {}",
+                self.link_edges.len(),
+                costs.len()
+            );
+        }
+
+        let sw = Timer::new();
+        let edge_len = self.heads.len();
+        let mut forward = vec![u32::MAX; edge_len];
+        let mut backward = vec![u32::MAX; edge_len];
+        let mut forward_mid = vec![NO_EDGE; edge_len];
+        let mut backward_mid = vec![NO_EDGE; edge_len];
+
+        for (&(edge, dir), &cost) in self.link_edges.iter().zip(costs) {
+            if edge == NO_EDGE {
+                continue;
+            }
+            let weights = match dir {
+                IdxLinkDir::Forward => &mut forward,
+                IdxLinkDir::Backward => &mut backward,
+            };
+            let w = &mut weights[edge as usize];
+            *w = std::cmp::min(*w, cost);
+        }
+
+        // lower triangles: for node `u`, its lower neighbor `v` and upward neighbor `w` of both,
+        // u -> v -> w may be shorter than u -> w, and w -> v -> u shorter than w -> u. Edges of
+        // `u` only depend on edges of lower neighbors, so nodes of a level are independent.
+        for level in self.levels.iter() {
+            let updates = level
+                .par_iter()
+                .map(|&u| {
+                    let r = self.edge_range(u);
+                    let mut edges = r
+                        .clone()
+                        .map(|e| (forward[e], forward_mid[e], backward[e], backward_mid[e]))
+                        .collect::<Vec<_>>();
+                    let down_r = self.down_first[u.index()] as usize
+                        ..self.down_first[u.index() + 1] as usize;
+                    for &(v, e1) in self.downs[down_r].iter() {
+                        let e1 = e1 as usize;
+                        for e2 in self.edge_range(v) {
+                            let w = self.heads[e2];
+                            if self.order[u.index()] >= self.order[w.index()] {
+                                continue;
+                            }
+                            let pos = match self.heads[r.clone()].binary_search(&w) {
+                                Ok(pos) => pos,
+                                Err(_) => panic!("missing cch edge: {:?} -> {:?}", u, w),
+                            };
+                            let edge = &mut edges[pos];
+
+                            let cost = backward[e1].saturating_add(forward[e2]);
+                            if cost < edge.0 {
+                                edge.0 = cost;
+                                edge.1 = v.index() as u32;
+                            }
+                            let cost = backward[e2].saturating_add(forward[e1]);
+                            if cost < edge.2 {
+                                edge.2 = cost;
+                                edge.3 = v.index() as u32;
+                            }
+                        }
+                    }
+                    (r, edges)
+                })
+                .collect::<Vec<_>>();
+
+            for (r, edges) in updates.into_iter() {
+                for (e, (f, f_mid, b, b_mid)) in r.zip(edges) {
+                    forward[e] = f;
+                    forward_mid[e] = f_mid;
+                    backward[e] = b;
+                    backward_mid[e] = b_mid;
+                }
+            }
+        }
+
+        // u32::MAX is kept for missing edges, other costs should fit in links
+        if let Some(cost) = forward
+            .iter()
+            .chain(backward.iter())
+            .find(|c| **c > MAX_COST && **c != u32::MAX)
+        {
+            bail!("cost overflow: {} exceeds {}", cost, MAX_COST);
+        }
+
+        let mut contractions = Vec::with_capacity(self.node_len());
+        let mut mids = Vec::with_capacity(self.node_len());
+        let mut all_contractions = Vec::new();
+        for i in 0..self.node_len() {
+            let key = IdxNodeKey::new(i);
+            let r = self.edge_range(key);
+            let mut links = Vec::with_capacity(r.len() * 2);
+            let mut link_mids = Vec::with_capacity(r.len() * 2);
+            for e in r.clone() {
+                let head = self.heads[e];
+                if forward[e] != u32::MAX {
+                    links.push(IdxLink::new(head, forward[e], IdxLinkDir::Forward));
+                    link_mids.push(mid_key(forward_mid[e]));
+                    if forward_mid[e] != NO_EDGE {
+                        all_contractions.push(CHContraction {
+                            snode_idx: key,
+                            enode_idx: head,
+                            mnode_idx: IdxNodeKey::new(forward_mid[e] as usize),
+                            length: forward[e],
+                            secondary: 0,
+                        });
+                    }
+                }
+            }
+            for e in r {
+                let head = self.heads[e];
+                if backward[e] != u32::MAX {
+                    links.push(IdxLink::new(head, backward[e], IdxLinkDir::Backward));
+                    link_mids.push(mid_key(backward_mid[e]));
+                    if backward_mid[e] != NO_EDGE {
+                        all_contractions.push(CHContraction {
+                            snode_idx: head,
+                            enode_idx: key,
+                            mnode_idx: IdxNodeKey::new(backward_mid[e] as usize),
+                            length: backward[e],
+                            secondary: 0,
+                        });
+                    }
+                }
+            }
+            contractions.push(links);
+            mids.push(link_mids);
+        }
+        sort_contractions(&mut all_contractions);
+
+        eprintln!(
+            "customization took: {}, contractions={}",
+            sw.took(),
+            all_contractions.len()
+        );
+        // links of each node are forward links first, so middle nodes are kept aligned
+        Ok(CHQuery::from_parts(
+            self.order.clone(),
+            Csr::from(contractions),
+            mids.concat(),
+            Vec::new(),
+            all_contractions,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn write_and_load_order() {
+        let graph = testutil::grid(6, 3);
+        let cch = CCH::new(&graph);
+        let path = testutil::temp_dir("cch-order");
+        cch.write(&path).unwrap();
+
+        let loaded = CCH::from_file(&graph, &path).unwrap();
+        assert_eq!(loaded.order, cch.order);
+        let query = loaded.customize_graph(&graph).unwrap();
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            assert_eq!(
+                query.search(src, dst).map(|r| r.1),
+                graph.search(src, dst).map(|r| r.1)
+            );
+        }
+
+        let other = testutil::grid(5, 3);
+        assert!(CCH::from_file(&other, &path).is_err());
+    }
+
+    #[test]
+    fn customize_with_other_costs() {
+        let graph = testutil::grid(7, 8);
+        let cch = CCH::new(&graph);
+        assert!(cch.levels.len() > 1);
+        assert_eq!(
+            cch.levels.iter().map(|l| l.len()).sum::<usize>(),
+            graph.node_len
+        );
+
+        // costs changed by link index, including links far more expensive than before
+        let links = graph
+            .forward_links()
+            .enumerate()
+            .map(|(i, (snode_idx, l))| {
+                let cost = if i % 3 == 0 {
+                    l.cost() * 20
+                } else {
+                    l.cost() + 1
+                };
+                (snode_idx.index(), l.enode_idx.index(), cost)
+            })
+            .collect::<Vec<_>>();
+        let costs = links.iter().map(|l| l.2).collect::<Vec<_>>();
+        let updated = testutil::graph(graph.node_len, &links);
+
+        let query = cch.customize(&costs).unwrap();
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            assert_eq!(
+                query.search(src, dst).map(|r| r.1),
+                updated.search(src, dst).map(|r| r.1),
+                "src={:?}, dst={:?}",
+                src,
+                dst
+            );
+        }
+        assert!(cch.customize(&costs[1..]).is_err());
+    }
+}
@@ -1,10 +1,10 @@
-use anyhow::Result;
-use ordslice::Ext;
+use anyhow::{bail, Result};
 use rayon::prelude::*;
 
 use super::*;
 
 use fnv::*;
+use std::borrow::Cow;
 
 #[derive(Default)]
 struct Stat {
@@ -36,34 +36,105 @@ struct CHEntry {
 }
 impl PartialOrd for CHEntry {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.score.partial_cmp(&other.score)
+        Some(self.cmp(other))
     }
 }
+/// entry with higher score comes first, ties are broken by smaller key so builds are
+/// reproducible
 impl Ord for CHEntry {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.score.cmp(&other.score)
+        self.score
+            .cmp(&other.score)
+            .then_with(|| other.key.cmp(&self.key))
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CHContraction {
-    snode_idx: IdxNodeKey,
-    enode_idx: IdxNodeKey,
-    mnode_idx: IdxNodeKey,
-    length: u32,
+    pub(crate) snode_idx: IdxNodeKey,
+    pub(crate) enode_idx: IdxNodeKey,
+    pub(crate) mnode_idx: IdxNodeKey,
+    pub(crate) length: u32,
+    /// secondary metric of the contraction, sum of both halves. See `Graph::secondary`.
+    #[serde(default)]
+    pub(crate) secondary: u32,
 }
 
 /// data structure for contraction hierarchies
 pub struct CH<'a> {
     graph: &'a Graph,
 
+    /// links of original graph. Borrowed from `graph` unless costs are updated.
+    pub(crate) links: Cow<'a, Csr>,
+
     /// order of each node, starting from 1. 0 if given node is not ordered yet
-    order: Vec<u32>,
+    pub(crate) order: Section<u32>,
+
+    /// links of remaining graph while building, empty after build
+    pub(crate) contractions: DynCsr<IdxLink>,
+
+    /// upward/downward links for query, empty while building
+    pub(crate) hierarchy_links: Csr,
+
+    /// middle node of each link of `hierarchy_links`
+    pub(crate) mids: Section<IdxNodeKey>,
 
-    /// contractions
-    contractions: Vec<Vec<IdxLink>>,
+    /// secondary metric of each link of `hierarchy_links`, empty if graph has none
+    pub(crate) secondary: Section<u32>,
 
-    all_contractions: Vec<CHContraction>,
+    pub(crate) all_contractions: Section<CHContraction>,
+
+    /// parameters of last build, default if loaded from file
+    pub(crate) config: CHBuildConfig,
+
+    /// intermediate state of build, set if resumed from checkpoint
+    pub(crate) build_state: Option<BuildState>,
+}
+
+/// per-node terms of priority while building
+pub(crate) struct Priorities {
+    pub(crate) deleted_count: Vec<u16>,
+    pub(crate) levels: Vec<u32>,
+    /// number of original links of each contraction, 1 if not found
+    pub(crate) original_counts: FnvHashMap<(IdxNodeKey, IdxNodeKey), u32>,
+}
+
+/// state of build other than `CH` itself, saved to checkpoint
+pub(crate) struct BuildState {
+    pub(crate) priorities: Priorities,
+    pub(crate) hop_limit: u16,
+    /// number of contracted nodes
+    pub(crate) order: u32,
+    pub(crate) try_count: usize,
+}
+
+impl Priorities {
+    fn original_count(&self, snode_idx: IdxNodeKey, enode_idx: IdxNodeKey) -> u32 {
+        *self
+            .original_counts
+            .get(&(snode_idx, enode_idx))
+            .unwrap_or(&1)
+    }
+}
+
+/// fail if hierarchy of `node_len` nodes is not built for `graph`
+fn check_node_len(graph: &Graph, node_len: usize) -> Result<()> {
+    if node_len != graph.node_len {
+        bail!(
+            "node count mismatch: graph={}, ch={}",
+            graph.node_len,
+            node_len
+        );
+    }
+    Ok(())
+}
+
+/// sort contractions by start node. Remaining fields break ties, so the result does not depend
+/// on the order contractions are found.
+pub(crate) fn sort_contractions(all_contractions: &mut [CHContraction]) {
+    all_contractions
+        .par_sort_unstable_by_key(|c| (c.snode_idx, c.enode_idx, c.mnode_idx, c.length));
 }
 
 pub fn filter_order(links: Vec<Vec<IdxLink>>, order: &[u32]) -> Vec<Vec<IdxLink>> {
@@ -83,72 +154,232 @@ impl<'a> CH<'a> {
         let node_len = graph.node_len;
 
         let mut order = Vec::with_capacity(node_len);
-        order.resize(node_len, std::u32::MAX);
+        order.resize(node_len, u32::MAX);
 
         Self {
             graph,
-            order,
-            contractions: graph.idx_links.clone(),
-            all_contractions: Vec::new(),
+            links: Cow::Borrowed(&graph.idx_links),
+            order: order.into(),
+            contractions: DynCsr::from(&graph.idx_links),
+            hierarchy_links: Default::default(),
+            mids: Default::default(),
+            secondary: Default::default(),
+            all_contractions: Default::default(),
+            config: Default::default(),
+            build_state: None,
         }
     }
 
+    /// load contraction hierarchies from `path`. Binary `ch.bin` is used if exists, otherwise
+    /// falls back to legacy `contractions.csv` and `order.csv`. Hierarchy renumbered at build
+    /// time is copied back to original nodes, use `CHQuery::from_file` to search it as is.
     pub fn from_file<P>(graph: &'a Graph, path: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
-        let node_len = graph.node_len;
+        Self::open(graph, path, true)
+    }
 
+    /// same as `from_file`, verifying checksum of `ch.bin` only if `verify` is set, as it reads
+    /// whole file. See `CHFile::open`.
+    pub fn open<P>(graph: &'a Graph, path: P, verify: bool) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
         let path = path.as_ref();
-        let mut all_contractions: Vec<CHContraction> = decode_csv(path.join("contractions.csv"))?;
-        let order: Vec<u32> = decode_csv_noheader(path.join("order.csv"))?;
-
-        all_contractions.sort_unstable_by_key(|c| c.snode_idx);
-
-        let mut contractions = Vec::with_capacity(node_len);
-        contractions.resize(node_len, Vec::new());
-
-        let link_partitions = partition::partition_range_by_key(&all_contractions, |c| c.snode_idx);
-        for (k, r) in link_partitions {
-            let mut v = all_contractions[r]
-                .iter()
-                .map(|c| IdxLink::new(c.enode_idx, c.length, IdxLinkDir::Forward))
-                .collect::<Vec<_>>();
-
-            let idx = k.index();
-            v.extend_from_slice(&graph.idx_links[idx]);
-            contractions[idx] = v;
-        }
-
-        let contractions_rev = map_rev(contractions.as_slice());
-
-        let mut contractions = filter_order(contractions, &order);
-        let mut contractions_rev = filter_order(contractions_rev, &order);
-
-        for (i, v) in contractions.iter_mut().enumerate() {
-            v.append(&mut contractions_rev[i]);
-        }
+        let bin_path = path.join(CH_FILE_NAME);
+        let (order, hierarchy_links, mids, secondary, all_contractions) = if bin_path.exists() {
+            let ch_file = CHFile::open(bin_path, verify)?;
+            check_node_len(graph, ch_file.node_len())?;
+            // nodes of `graph` are in original order, so renumbered hierarchy is copied back
+            let query = CHQuery::from_ch_file(&ch_file).into_original();
+            (
+                query.order,
+                query.contractions,
+                query.mids,
+                query.secondary,
+                query.all_contractions,
+            )
+        } else {
+            let mut all_contractions: Vec<CHContraction> =
+                decode_csv(path.join("contractions.csv"))?;
+            sort_contractions(&mut all_contractions);
+            let order: Vec<u32> = decode_csv_noheader(path.join("order.csv"))?;
+            check_node_len(graph, order.len())?;
+            let (hierarchy_links, mids) = query_links(&graph.idx_links, &order, &all_contractions);
+            let secondary = carry_secondary(
+                &graph.idx_links,
+                &graph.secondary,
+                &order,
+                &hierarchy_links,
+                &mids,
+                &mut all_contractions,
+            );
+            (
+                order.into(),
+                hierarchy_links,
+                mids.into(),
+                secondary.into(),
+                all_contractions.into(),
+            )
+        };
 
         Ok(Self {
             graph,
+            links: Cow::Borrowed(&graph.idx_links),
             order,
-            contractions,
+            contractions: Default::default(),
+            hierarchy_links,
+            mids,
+            secondary,
             all_contractions,
+            config: CHBuildConfig::from_dir_or_default(path)?,
+            build_state: None,
         })
     }
 
+    /// write contraction hierarchies to `path` directory as binary `ch.bin`, with build config
+    /// for `update_costs`, see `CHBuildConfig::write`
     pub fn write<P>(&self, path: P) -> Result<()>
     where
         P: AsRef<Path>,
     {
-        let path = path.as_ref();
-        encode_csv(
-            path.join("contractions.csv"),
-            self.all_contractions.as_slice(),
-        )?;
-        encode_csv(path.join("order.csv"), self.order.as_slice())?;
-        Ok(())
+        self.hierarchy().write(path.as_ref(), &[])?;
+        self.config.write(path)
+    }
+
+    /// build self-contained query object, which does not borrow `Graph`
+    pub fn to_query(&self) -> CHQuery {
+        CHQuery {
+            order: self.order.clone(),
+            contractions: self.hierarchy_links.clone(),
+            mids: self.mids.clone(),
+            secondary: self.secondary.clone(),
+            all_contractions: self.all_contractions.clone(),
+            renumbering: None,
+        }
+    }
+
+    /// order of each node, starting from 1. 0 if given node is not ordered yet
+    pub fn order(&self) -> &[u32] {
+        &self.order
+    }
+
+    /// parameters of last build, or of checkpoint if resumed. Loaded from file written by
+    /// `CH::write`, default if missing.
+    pub fn config(&self) -> &CHBuildConfig {
+        &self.config
+    }
+
+    /// copy of original graph with current costs of links. Unlike `Graph` given to `CH::new`,
+    /// this reflects `update_costs`, so use this to verify or search the updated graph.
+    pub fn to_graph(&self) -> Graph {
+        Graph {
+            node_len: self.graph.node_len,
+            idx_links: self.links.as_ref().clone(),
+            secondary: self.graph.secondary.clone(),
+        }
+    }
+
+    /// true if `other` has same order and contractions, i.e. written files are identical
+    pub fn is_identical(&self, other: &CH) -> bool {
+        self.order == other.order
+            && self.hierarchy_links == other.hierarchy_links
+            && self.mids == other.mids
+            && self.secondary == other.secondary
+            && self.all_contractions == other.all_contractions
+    }
+
+    pub(crate) fn hierarchy(&self) -> Hierarchy<'_> {
+        Hierarchy {
+            order: &self.order,
+            contractions: &self.hierarchy_links,
+            mids: &self.mids,
+            secondary: &self.secondary,
+            all_contractions: &self.all_contractions,
+        }
+    }
+
+    /// compute secondary metric of hierarchy links and contractions, see `carry_secondary`
+    pub(crate) fn carry_secondary(&mut self) {
+        self.secondary = carry_secondary(
+            &self.links,
+            &self.graph.secondary,
+            &self.order,
+            &self.hierarchy_links,
+            &self.mids,
+            &mut self.all_contractions,
+        )
+        .into();
+    }
+}
+
+/// build upward/downward links for query from original graph and contractions, with middle
+/// node of each link. Forward links point to higher-ordered nodes, backward links point from
+/// higher-ordered nodes. Middle node is `IdxNodeKey::NONE` for links of original graph.
+pub(crate) fn query_links(
+    links: &Csr,
+    order: &[u32],
+    all_contractions: &[CHContraction],
+) -> (Csr, Vec<IdxNodeKey>) {
+    // start from forward links of original graph, so links between nodes without any
+    // contraction are kept.
+    let mut forward = (0..links.node_len())
+        .into_par_iter()
+        .map(|i| {
+            links
+                .dir_links(i, IdxLinkDir::Forward)
+                .iter()
+                .map(|l| (*l, IdxNodeKey::NONE))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let link_partitions = partition::partition_range_by_key(all_contractions, |c| c.snode_idx);
+    for (k, r) in link_partitions {
+        let v = &mut forward[k.index()];
+        v.extend(all_contractions[r].iter().map(|c| {
+            (
+                IdxLink::new(c.enode_idx, c.length, IdxLinkDir::Forward),
+                c.mnode_idx,
+            )
+        }));
+    }
+
+    let mut backward = vec![Vec::new(); forward.len()];
+    for (i, v) in forward.iter().enumerate() {
+        let snode_idx = IdxNodeKey::new(i);
+        for (link, mid) in v.iter() {
+            let link_rev = IdxLink::new(snode_idx, link.cost(), IdxLinkDir::Backward);
+            backward[link.enode_idx.index()].push((link_rev, *mid));
+        }
+    }
+
+    let nodes = forward
+        .into_par_iter()
+        .zip(backward)
+        .enumerate()
+        .map(|(i, (forward, backward))| {
+            forward
+                .into_iter()
+                .chain(backward)
+                .filter(|(l, _)| order[l.enode_idx.index()] > order[i])
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut offsets = Vec::with_capacity(nodes.len() + 1);
+    offsets.push(0u32);
+    let mut flat_links = Vec::new();
+    let mut mids = Vec::new();
+    for v in nodes.into_iter() {
+        for (link, mid) in v.into_iter() {
+            flat_links.push(link);
+            mids.push(mid);
+        }
+        offsets.push(flat_links.len() as u32);
     }
+    (Csr::from_parts(offsets, flat_links), mids)
 }
 
 impl<'a> CH<'a> {
@@ -159,44 +390,57 @@ impl<'a> CH<'a> {
         length: u32,
         dir: IdxLinkDir,
     ) {
-        let v = &mut self.contractions[snode_idx.index()];
-        let mut found = false;
-        if let Some(prev_link) = v
-            .iter_mut()
-            .find(|l| l.enode_idx == enode_idx && l.dir() == dir)
-        {
+        let idx = snode_idx.index();
+        let r = self.contractions.dir_range(idx, dir);
+        let links = &mut self.contractions.links_mut(idx)[r.clone()];
+        if let Some(prev_link) = links.iter_mut().find(|l| l.enode_idx == enode_idx) {
             if prev_link.cost() > length {
                 prev_link.set_cost(length);
             }
-            found = true;
-        }
-        if !found {
-            let link = IdxLink::new(enode_idx, length, dir);
-            let idx = v.upper_bound(&link);
-            v.insert(idx, link);
+            return;
         }
+        // new link goes last on its direction
+        self.contractions
+            .insert(idx, r.end, IdxLink::new(enode_idx, length, dir));
     }
 
-    fn add_contraction(&mut self, c: CHContraction) {
+    /// add `c` to remaining graph. Fails if its cost exceeds `MAX_COST`, as saturated cost
+    /// would make queries return wrong paths.
+    fn add_contraction(&mut self, c: CHContraction) -> Result<()> {
+        if c.length > MAX_COST {
+            bail!(
+                "cost overflow: contraction {:?} -> {:?} via {:?} costs {}, exceeds {}",
+                c.snode_idx,
+                c.enode_idx,
+                c.mnode_idx,
+                c.length,
+                MAX_COST
+            );
+        }
         self.add_contraction_dir(c.snode_idx, c.enode_idx, c.length, IdxLinkDir::Forward);
         self.add_contraction_dir(c.enode_idx, c.snode_idx, c.length, IdxLinkDir::Backward);
 
-        self.all_contractions.push(c);
+        self.all_contractions.to_mut().push(c);
+        Ok(())
     }
 
     fn gc_contraction(&mut self, key: IdxNodeKey, contractions: &[IdxLink]) {
         for link in contractions.iter() {
-            let other_contractions = &mut self.contractions[link.enode_idx.index()];
-            let idx = other_contractions
+            let other_idx = link.enode_idx.index();
+            let r = self.contractions.dir_range(other_idx, link.dir().rev());
+            let pos = self.contractions.links(other_idx)[r.clone()]
                 .iter()
-                .position(|c| c.enode_idx == key && c.dir() != link.dir());
+                .position(|c| c.enode_idx == key);
 
-            match idx {
-                Some(idx) => other_contractions.remove(idx),
+            match pos {
+                Some(pos) => self.contractions.remove(other_idx, r.start + pos),
                 None => {
                     panic!(
                         "invalid contraction: key={:?}, link={:?}, from={:?}, to={:?}",
-                        key, link, contractions, other_contractions
+                        key,
+                        link,
+                        contractions,
+                        self.contractions.links(other_idx)
                     );
                 }
             };
@@ -228,7 +472,7 @@ impl<'a> CH<'a> {
             .expect("invalid neighbor count");
 
         let mut search = dijkstra::Search::with_capacity(1024);
-        search.heap_limit = 1024;
+        search.heap_limit = self.config.heap_limit;
         search.hop_limit = hop_limit;
         search.add_src(from_link.enode_idx);
         search.cost_limit = from_link.cost() + max_cost;
@@ -237,7 +481,7 @@ impl<'a> CH<'a> {
         let dst_len = dsts_map.len();
         let mut costs = Vec::with_capacity(dst_len);
         let mut found = 0;
-        costs.resize(dst_len, std::u32::MAX);
+        costs.resize(dst_len, u32::MAX);
 
         while let Some(entry) = search.next() {
             let key = entry.key;
@@ -253,14 +497,14 @@ impl<'a> CH<'a> {
                 }
             }
 
-            for link in &self.contractions[key.index()] {
-                if link.dir() != IdxLinkDir::Forward {
-                    break;
-                }
+            for link in self
+                .contractions
+                .dir_links(key.index(), IdxLinkDir::Forward)
+            {
                 // assert!(self.order[link.enode_idx.index()] > order);
 
                 let next_key = link.enode_idx;
-                let next_cost = entry.cost + link.cost();
+                let next_cost = entry.cost.saturating_add(link.cost());
                 search.update(&entry, next_key, next_cost);
             }
         }
@@ -281,8 +525,12 @@ impl<'a> CH<'a> {
         let mut backwards: FnvHashSet<_> = Default::default();
 
         for (i, neighbor) in neighbors.iter().enumerate() {
-            for next_neighbor in self.contractions[neighbor.enode_idx.index()].iter() {
-                if next_neighbor.dir() == IdxLinkDir::Forward {
+            for next_neighbor in self
+                .contractions
+                .dir_links(neighbor.enode_idx.index(), IdxLinkDir::Backward)
+            {
+                // skip nodes being contracted in the same batch
+                if self.order[next_neighbor.enode_idx.index()] != u32::MAX {
                     continue;
                 }
                 bucket.push((next_neighbor.enode_idx, i, next_neighbor.cost()));
@@ -290,21 +538,20 @@ impl<'a> CH<'a> {
             }
         }
 
+        // build distances
+        let dst_len = neighbors.len();
+        let mut costs = Vec::with_capacity(dst_len);
+        costs.resize(dst_len, u32::MAX);
+
         let max_cost = neighbors
             .iter()
             .map(|link| link.cost())
             .max()
             .expect("invalid neighbor count");
-        let backward_min_cost = bucket
-            .iter()
-            .map(|&(_, _, cost)| cost)
-            .min()
-            .expect("invalid backwards");
-
-        // build distances
-        let dst_len = neighbors.len();
-        let mut costs = Vec::with_capacity(dst_len);
-        costs.resize(dst_len, std::u32::MAX);
+        let backward_min_cost = match bucket.iter().map(|&(_, _, cost)| cost).min() {
+            Some(cost) => cost,
+            None => return costs,
+        };
 
         if hop_limit == 1 {
             let key = from_link.enode_idx;
@@ -318,14 +565,14 @@ impl<'a> CH<'a> {
             }
         } else {
             let mut search = dijkstra::Search::with_capacity(128);
-            search.heap_limit = 1024;
+            search.heap_limit = self.config.heap_limit;
             search.hop_limit = hop_limit - 1;
             search.add_src(from_link.enode_idx);
-            search.cost_limit = from_link.cost() + max_cost - backward_min_cost;
+            search.cost_limit = (from_link.cost() + max_cost).saturating_sub(backward_min_cost);
 
             while let Some(entry) = search.next() {
                 let key = entry.key;
-                if key == src {
+                if key == src || self.order[key.index()] != u32::MAX {
                     continue;
                 }
 
@@ -334,15 +581,16 @@ impl<'a> CH<'a> {
                         if key != n2 {
                             continue;
                         }
-                        costs[idx] = std::cmp::min(costs[idx], entry.cost + cost);
+                        costs[idx] = std::cmp::min(costs[idx], entry.cost.saturating_add(cost));
                     }
                 }
 
-                for link in &self.contractions[key.index()] {
-                    if link.dir() != IdxLinkDir::Forward {
-                        break;
-                    }
-                    search.update(&entry, link.enode_idx, entry.cost + link.cost());
+                for link in self
+                    .contractions
+                    .dir_links(key.index(), IdxLinkDir::Forward)
+                {
+                    let next_cost = entry.cost.saturating_add(link.cost());
+                    search.update(&entry, link.enode_idx, next_cost);
                 }
             }
         }
@@ -385,6 +633,7 @@ impl<'a> CH<'a> {
                     enode_idx: to_key,
                     mnode_idx: src,
                     length: via_cost,
+                    secondary: 0,
                 };
                 contractions.push(c);
             }
@@ -409,7 +658,7 @@ impl<'a> CH<'a> {
             forward_links.clear();
             for (j, forward_link) in neighbors.iter().enumerate() {
                 if i != j && forward_link.dir() == IdxLinkDir::Forward {
-                    forward_links.push(forward_link.clone());
+                    forward_links.push(*forward_link);
                 }
             }
 
@@ -419,27 +668,56 @@ impl<'a> CH<'a> {
         contractions
     }
 
-    fn heap_entry(&self, key: IdxNodeKey, deleted_count: &[u16], hop_limit: u16) -> CHEntry {
+    /// score of contracting `key`, see `CHPriority`
+    fn score(
+        &self,
+        key: IdxNodeKey,
+        neighbors: &[IdxLink],
+        contractions: &[CHContraction],
+        priorities: &Priorities,
+    ) -> i32 {
+        let priority = &self.config.priority;
         let idx = key.index();
-        let neighbors = self.contractions[idx].as_slice();
-        let contractions = self.dijkstra_contract(key, neighbors, hop_limit);
 
         let edge_difference = (neighbors.len() as i32) - (contractions.len() as i32);
-        let deleted_count = deleted_count[idx];
-        let score = edge_difference - deleted_count as i32;
+        let original_edges = if priority.original_edges != 0 {
+            contractions
+                .iter()
+                .map(|c| {
+                    priorities.original_count(c.snode_idx, c.mnode_idx)
+                        + priorities.original_count(c.mnode_idx, c.enode_idx)
+                })
+                .sum()
+        } else {
+            0
+        };
+        priority.score(
+            edge_difference,
+            priorities.deleted_count[idx],
+            original_edges,
+            priorities.levels[idx],
+        )
+    }
+
+    fn heap_entry(&self, key: IdxNodeKey, priorities: &Priorities, hop_limit: u16) -> CHEntry {
+        let idx = key.index();
+        let neighbors = self.contractions.links(idx);
+        let contractions = self.dijkstra_contract(key, neighbors, hop_limit);
+
+        let score = self.score(key, neighbors, &contractions, priorities);
         CHEntry { score, key }
     }
 
-    fn build_heap(&self, deleted_count: &[u16], hop_limit: u16) -> BinaryHeap<CHEntry> {
+    fn build_heap(&self, priorities: &Priorities, hop_limit: u16) -> BinaryHeap<CHEntry> {
         let sw = Timer::new();
         let len = self.graph.node_len;
 
         let entries = (0..len)
             .into_par_iter()
-            .filter(|idx| !self.contractions[*idx].is_empty())
+            .filter(|idx| !self.contractions.links(*idx).is_empty())
             .map(|idx| {
                 let key = IdxNodeKey::new(idx);
-                self.heap_entry(key, deleted_count, hop_limit)
+                self.heap_entry(key, priorities, hop_limit)
             })
             .collect::<Vec<_>>();
 
@@ -457,10 +735,10 @@ impl<'a> CH<'a> {
 
         let entries = (0..len)
             .into_par_iter()
-            .filter(|idx| !self.contractions[*idx].is_empty())
+            .filter(|idx| !self.contractions.links(*idx).is_empty())
             .flat_map(|idx| {
                 let key = IdxNodeKey::new(idx);
-                let neighbors = self.contractions[idx].as_slice();
+                let neighbors = self.contractions.links(idx);
                 let contractions = self.dijkstra_contract(key, neighbors, hop_limit);
                 let mut links = Vec::new();
                 for n in neighbors.iter() {
@@ -479,8 +757,7 @@ impl<'a> CH<'a> {
             })
             .collect::<Vec<_>>();
 
-        self.contractions = Vec::with_capacity(len);
-        self.contractions.resize(len, Vec::new());
+        self.contractions = DynCsr::new(len);
 
         for (from, to, cost) in entries.into_iter() {
             self.add_contraction_dir(from, to, cost, IdxLinkDir::Forward);
@@ -490,27 +767,81 @@ impl<'a> CH<'a> {
     }
 
     pub fn build(&mut self) {
+        self.build_with_config(Default::default())
+    }
+
+    /// panics if cost of a contraction exceeds `MAX_COST`, use `build_with_hooks` to handle it
+    pub fn build_with_config(&mut self, config: CHBuildConfig) {
+        if let Err(e) = self.build_with_hooks(config, &Default::default()) {
+            panic!("failed to build: {}", e);
+        }
+    }
+
+    /// build with `hooks`, which observe progress, cancel or checkpoint the build. Every `step`
+    /// contracted nodes, observer is notified, cancellation is checked and checkpoint is written
+    /// if needed. Returns error if cancelled, after writing checkpoint if `checkpoint_path` is
+    /// set, or if cost of a contraction exceeds `MAX_COST`.
+    ///
+    /// If resumed from checkpoint, `config` should equal the config of checkpoint except `step`.
+    pub fn build_with_hooks(&mut self, config: CHBuildConfig, hooks: &CHBuildHooks) -> Result<()> {
+        if self.build_state.is_some() {
+            let resumed = CHBuildConfig {
+                step: config.step,
+                ..self.config.clone()
+            };
+            if config != resumed {
+                bail!(
+                    "config differs from checkpoint: {:?}, checkpoint: {:?}",
+                    config,
+                    resumed
+                );
+            }
+        }
+        self.config = config;
+
         let node_len = self.graph.node_len;
-        let link_len: usize = self.contractions.par_iter().map(|v| v.len()).sum();
+        let link_len = self.contractions.link_len();
 
         eprintln!("start contraction: nodes={}, links={}", node_len, link_len);
 
-        let mut deleted_count = Vec::with_capacity(node_len);
-        deleted_count.resize(node_len, 0);
+        let state = self.build_state.take().unwrap_or_else(|| BuildState {
+            priorities: Priorities {
+                deleted_count: vec![0; node_len],
+                levels: vec![0; node_len],
+                original_counts: Default::default(),
+            },
+            hop_limit: 1,
+            order: 0,
+            try_count: 0,
+        });
+        if state.order > 0 {
+            eprintln!(
+                "resuming contraction: order={}, hop_limit={}",
+                state.order, state.hop_limit
+            );
+        }
+
+        if self.config.parallel {
+            return self.build_parallel(state, hooks);
+        }
+
+        let BuildState {
+            mut priorities,
+            mut hop_limit,
+            mut order,
+            mut try_count,
+        } = state;
 
-        let mut hop_limit = 1;
-        let hop_steppings = [(8., 5), (5., 3), (3.3, 2)];
-        let mut heap = self.build_heap(&deleted_count, hop_limit);
+        let mut heap = self.build_heap(&priorities, hop_limit);
 
         // parameters
-        const STEP: usize = 10000;
-        const SCORE_TOLERANCE: i32 = 2;
+        let step = std::cmp::max(self.config.step, 1);
+        let score_tolerance = self.config.score_tolerance;
+        let heap_min_interval = std::cmp::max(self.config.heap_min_interval, 1);
 
-        let mut order = 0;
-        let mut try_count = 0;
+        let mut checkpoint_order = order;
 
-        const HEAP_MIN_INTERVAL: usize = STEP * 2;
-        let mut rebuild_try_count = std::cmp::max(HEAP_MIN_INTERVAL, heap.len() / 10);
+        let mut rebuild_try_count = std::cmp::max(heap_min_interval, heap.len() / 10);
 
         let sw = Timer::new();
         let mut stat_score = Stat::default();
@@ -526,19 +857,19 @@ impl<'a> CH<'a> {
             rebuild_try_count -= 1;
             if rebuild_try_count == 0 {
                 let remain_count = node_len - order as usize;
-                let num_vertices: usize = self.contractions.par_iter().map(|c| c.len()).sum();
+                let num_vertices = self.contractions.link_len();
                 let avg_degree = (num_vertices as f32) / (remain_count) as f32 / 2f32;
 
                 let prev_hop_limit = hop_limit;
-                for &(deg, hop) in hop_steppings.iter() {
+                for &(deg, hop) in self.config.hop_steppings.iter() {
                     if avg_degree > deg {
                         hop_limit = std::cmp::max(hop_limit, hop);
                     }
                 }
                 if hop_limit != prev_hop_limit {
                     self.rebuild_contractions(hop_limit);
-                    deleted_count.clear();
-                    deleted_count.resize(node_len, 0);
+                    priorities.deleted_count.clear();
+                    priorities.deleted_count.resize(node_len, 0);
                 }
 
                 eprintln!(
@@ -547,22 +878,21 @@ impl<'a> CH<'a> {
                     avg_degree,
                     hop_limit,
                 );
-                heap = self.build_heap(&deleted_count, hop_limit);
-                rebuild_try_count = std::cmp::max(HEAP_MIN_INTERVAL, heap.len() / 10);
+                heap = self.build_heap(&priorities, hop_limit);
+                rebuild_try_count = std::cmp::max(heap_min_interval, heap.len() / 10);
             }
 
             // lazy update: re-calculate score
             let key = entry.key;
             let idx = key.index();
-            let mut neighbors = Vec::new();
-            std::mem::swap(&mut neighbors, &mut self.contractions[idx]);
+            let neighbors = self.contractions.take(idx);
             let contractions = self.dijkstra_contract(key, neighbors.as_slice(), hop_limit);
             let edge_difference = (neighbors.len() as i32) - (contractions.len() as i32);
-            let score = edge_difference - deleted_count[idx] as i32;
+            let score = self.score(key, &neighbors, &contractions, &priorities);
 
             // score is updated
-            if score < entry.score - SCORE_TOLERANCE {
-                std::mem::swap(&mut neighbors, &mut self.contractions[idx]);
+            if score < entry.score - score_tolerance {
+                self.contractions.set(idx, &neighbors);
                 heap.push(CHEntry { score, key });
                 continue;
             }
@@ -572,12 +902,12 @@ impl<'a> CH<'a> {
             stat_neighbors.push(neighbors.len() as i64);
 
             order += 1;
-            if (order as usize) % STEP == 0 {
+            if (order as usize).is_multiple_of(step) {
                 eprintln!(
                     "{}/{}/{}, contractions={}, score={:.2}, diff={:.2}, neighbors={:.2}",
-                    try_count / STEP,
-                    (order as usize) / STEP,
-                    node_len / STEP,
+                    try_count / step,
+                    (order as usize) / step,
+                    node_len / step,
                     self.all_contractions.len(),
                     stat_score.avg(),
                     stat_diff.avg(),
@@ -589,39 +919,593 @@ impl<'a> CH<'a> {
                 stat_neighbors.clear();
             }
 
+            let level = priorities.levels[idx] + 1;
             for neighbor in neighbors.iter() {
-                deleted_count[neighbor.enode_idx.index()] += 1;
+                let neighbor_idx = neighbor.enode_idx.index();
+                priorities.deleted_count[neighbor_idx] += 1;
+                let neighbor_level = &mut priorities.levels[neighbor_idx];
+                *neighbor_level = std::cmp::max(*neighbor_level, level);
             }
 
             // add contractions
             for contraction in contractions.into_iter() {
-                self.add_contraction(contraction);
+                if self.config.priority.original_edges != 0 {
+                    let count = priorities.original_count(contraction.snode_idx, key)
+                        + priorities.original_count(key, contraction.enode_idx);
+                    priorities
+                        .original_counts
+                        .insert((contraction.snode_idx, contraction.enode_idx), count);
+                }
+                self.add_contraction(contraction)?;
             }
 
             // update order
             self.order[idx] = order;
             self.gc_contraction(key, neighbors.as_slice());
+
+            if !(order as usize).is_multiple_of(step) {
+                continue;
+            }
+
+            self.end_of_batch(
+                hooks,
+                &priorities,
+                order,
+                hop_limit,
+                try_count,
+                &mut checkpoint_order,
+            )?;
+        }
+
+        self.finish_build();
+        Ok(())
+    }
+
+    /// notify observer, check cancellation and write checkpoint if needed
+    fn end_of_batch(
+        &self,
+        hooks: &CHBuildHooks,
+        priorities: &Priorities,
+        order: u32,
+        hop_limit: u16,
+        try_count: usize,
+        checkpoint_order: &mut u32,
+    ) -> Result<()> {
+        let node_len = self.graph.node_len;
+        if let Some(observer) = hooks.observer {
+            observer.progress(&CHBuildProgress {
+                order: order as usize,
+                node_len,
+                contractions: self.all_contractions.len(),
+                hop_limit,
+            });
+        }
+
+        let cancelled = hooks
+            .cancel
+            .as_ref()
+            .map(|c| c.is_cancelled())
+            .unwrap_or(false);
+        let checkpoint_due = hooks.checkpoint_interval > 0
+            && (order - *checkpoint_order) as usize >= hooks.checkpoint_interval;
+        if let Some(path) = hooks.checkpoint_path.as_ref() {
+            if cancelled || checkpoint_due {
+                self.write_checkpoint(path, priorities, order, hop_limit, try_count)?;
+                *checkpoint_order = order;
+            }
+        }
+        if cancelled {
+            bail!("build cancelled: order={}/{}", order, node_len);
+        }
+        Ok(())
+    }
+
+    fn finish_build(&mut self) {
+        sort_contractions(&mut self.all_contractions);
+
+        // every node is contracted, replace contractions with links for query
+        let (hierarchy_links, mids) = query_links(&self.links, &self.order, &self.all_contractions);
+        self.hierarchy_links = hierarchy_links;
+        self.mids = mids.into();
+        self.carry_secondary();
+        self.contractions = Default::default();
+    }
+
+    /// true if `key` has the highest score among nodes within two hops, ties are broken by
+    /// smaller key. Nodes selected this way share neither a link nor a neighbor.
+    fn is_local_best(&self, key: IdxNodeKey, scores: &[i32]) -> bool {
+        let rank = |k: IdxNodeKey| (scores[k.index()], Reverse(k));
+        let key_rank = rank(key);
+        for link in self.contractions.links(key.index()) {
+            if rank(link.enode_idx) > key_rank {
+                return false;
+            }
+            for next_link in self.contractions.links(link.enode_idx.index()) {
+                if next_link.enode_idx != key && rank(next_link.enode_idx) > key_rank {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// contract independent sets of nodes in rounds. Witness searches and contractions of
+    /// nodes in a set are computed in parallel, then applied as a batch. Witness searches skip
+    /// nodes of the same set, so each contraction is valid after the others are applied.
+    fn build_parallel(&mut self, state: BuildState, hooks: &CHBuildHooks) -> Result<()> {
+        let node_len = self.graph.node_len;
+        let step = std::cmp::max(self.config.step, 1);
+
+        let BuildState {
+            mut priorities,
+            mut hop_limit,
+            mut order,
+            mut try_count,
+        } = state;
+        let mut checkpoint_order = order;
+
+        let mut candidates = (0..node_len)
+            .filter(|idx| self.order[*idx] == u32::MAX && !self.contractions.links(*idx).is_empty())
+            .map(IdxNodeKey::new)
+            .collect::<Vec<_>>();
+
+        let sw = Timer::new();
+        let mut scores = vec![i32::MIN; node_len];
+        // nodes which score should be re-calculated
+        let mut dirty = candidates.clone();
+
+        while !candidates.is_empty() {
+            // `try_count` counts rounds in parallel build
+            try_count += 1;
+
+            let remain_count = node_len - order as usize;
+            let num_vertices = self.contractions.link_len();
+            let avg_degree = (num_vertices as f32) / (remain_count) as f32 / 2f32;
+
+            let prev_hop_limit = hop_limit;
+            for &(deg, hop) in self.config.hop_steppings.iter() {
+                if avg_degree > deg {
+                    hop_limit = std::cmp::max(hop_limit, hop);
+                }
+            }
+            if hop_limit != prev_hop_limit {
+                eprintln!(
+                    "raising hop limit, elapsed: {}, avg_degrees={:.3}, hop_limit={}",
+                    sw.took(),
+                    avg_degree,
+                    hop_limit,
+                );
+                self.rebuild_contractions(hop_limit);
+                priorities.deleted_count.clear();
+                priorities.deleted_count.resize(node_len, 0);
+                dirty = candidates.clone();
+            }
+
+            let updated = dirty
+                .par_iter()
+                .map(|key| {
+                    let neighbors = self.contractions.links(key.index());
+                    let contractions = self.dijkstra_contract(*key, neighbors, hop_limit);
+                    self.score(*key, neighbors, &contractions, &priorities)
+                })
+                .collect::<Vec<_>>();
+            for (key, score) in dirty.iter().zip(updated) {
+                scores[key.index()] = score;
+            }
+
+            let selected = candidates
+                .par_iter()
+                .filter(|key| self.is_local_best(**key, &scores))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            // assign order first, so witness searches skip nodes of the same set
+            let prev_order = order;
+            for key in selected.iter() {
+                order += 1;
+                self.order[key.index()] = order;
+            }
+
+            let all_contractions = selected
+                .par_iter()
+                .map(|key| {
+                    let neighbors = self.contractions.links(key.index());
+                    self.dijkstra_contract(*key, neighbors, hop_limit)
+                })
+                .collect::<Vec<_>>();
+
+            dirty.clear();
+            for (key, contractions) in selected.iter().zip(all_contractions) {
+                let idx = key.index();
+                let neighbors = self.contractions.take(idx);
+
+                let level = priorities.levels[idx] + 1;
+                for neighbor in neighbors.iter() {
+                    let neighbor_idx = neighbor.enode_idx.index();
+                    priorities.deleted_count[neighbor_idx] += 1;
+                    let neighbor_level = &mut priorities.levels[neighbor_idx];
+                    *neighbor_level = std::cmp::max(*neighbor_level, level);
+                    dirty.push(neighbor.enode_idx);
+                }
+
+                for contraction in contractions.into_iter() {
+                    if self.config.priority.original_edges != 0 {
+                        let count = priorities.original_count(contraction.snode_idx, *key)
+                            + priorities.original_count(*key, contraction.enode_idx);
+                        priorities
+                            .original_counts
+                            .insert((contraction.snode_idx, contraction.enode_idx), count);
+                    }
+                    self.add_contraction(contraction)?;
+                }
+
+                self.gc_contraction(*key, neighbors.as_slice());
+            }
+            dirty.sort_unstable();
+            dirty.dedup();
+            candidates.retain(|key| self.order[key.index()] == u32::MAX);
+
+            if prev_order as usize / step == order as usize / step {
+                continue;
+            }
+
+            eprintln!(
+                "{}/{}/{}, elapsed: {}, selected={}, contractions={}",
+                try_count,
+                (order as usize) / step,
+                node_len / step,
+                sw.took(),
+                selected.len(),
+                self.all_contractions.len(),
+            );
+            self.end_of_batch(
+                hooks,
+                &priorities,
+                order,
+                hop_limit,
+                try_count,
+                &mut checkpoint_order,
+            )?;
+        }
+
+        self.finish_build();
+        Ok(())
+    }
+}
+
+/// self-contained contraction hierarchies for query. Unlike `CH`, it owns upward/downward links
+/// and contractions, so neither `Graph` nor network is required to answer queries.
+pub struct CHQuery {
+    order: Section<u32>,
+    contractions: Csr,
+    mids: Section<IdxNodeKey>,
+    secondary: Section<u32>,
+    all_contractions: Section<CHContraction>,
+    /// renumbering of hierarchy, searches map original nodes to it. See `with_renumbering`.
+    pub(crate) renumbering: Option<Renumbering>,
+}
+
+impl CHQuery {
+    pub(crate) fn from_parts(
+        order: Vec<u32>,
+        contractions: Csr,
+        mids: Vec<IdxNodeKey>,
+        secondary: Vec<u32>,
+        all_contractions: Vec<CHContraction>,
+    ) -> Self {
+        Self {
+            order: order.into(),
+            contractions,
+            mids: mids.into(),
+            secondary: secondary.into(),
+            all_contractions: all_contractions.into(),
+            renumbering: None,
+        }
+    }
+
+    /// load from `ch.bin` in `path` directory, written by `CH::write` or `CHQuery::write`.
+    /// Hierarchy is borrowed from the memory map without copying. Renumbering written with the
+    /// hierarchy is loaded as well, so searches take and return original nodes.
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::open(path, true)
+    }
+
+    /// same as `from_file`, verifying checksum only if `verify` is set, as it reads whole file.
+    /// See `CHFile::open`.
+    pub fn open<P>(path: P, verify: bool) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let ch_file = CHFile::open(path.as_ref().join(CH_FILE_NAME), verify)?;
+        Ok(Self::from_ch_file(&ch_file))
+    }
+
+    fn from_ch_file(ch_file: &CHFile) -> Self {
+        let old_keys = ch_file.old_keys();
+        Self {
+            order: ch_file.order_section(),
+            contractions: ch_file.csr(),
+            mids: ch_file.mids_section(),
+            secondary: ch_file.secondary_section(),
+            all_contractions: ch_file.contractions_section(),
+            renumbering: if old_keys.is_empty() {
+                None
+            } else {
+                Some(Renumbering::from_old_keys(old_keys.to_vec()))
+            },
+        }
+    }
+
+    /// write hierarchy with its renumbering, if any
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let old_keys = self.renumbering.as_ref().map_or(&[][..], |r| r.old_keys());
+        self.hierarchy().write(path, old_keys)
+    }
+
+    /// renumbering of hierarchy, set by `with_renumbering` or loaded from file
+    pub fn renumbering(&self) -> Option<&Renumbering> {
+        self.renumbering.as_ref()
+    }
+
+    /// copy of hierarchy on original nodes, undoing `renumbering`
+    pub(crate) fn into_original(mut self) -> CHQuery {
+        match self.renumbering.take() {
+            Some(renumbering) => self.renumber(&renumbering.inverse()),
+            None => self,
+        }
+    }
+
+    /// node of hierarchy for original node `key`
+    pub(crate) fn inner_key(&self, key: IdxNodeKey) -> IdxNodeKey {
+        match self.renumbering {
+            Some(ref renumbering) => renumbering.new_key(key),
+            None => key,
+        }
+    }
+
+    /// map path on nodes of hierarchy back to original nodes
+    fn outer_path(&self, mut path: Vec<IdxNodeKey>) -> Vec<IdxNodeKey> {
+        if let Some(ref renumbering) = self.renumbering {
+            for key in path.iter_mut() {
+                *key = renumbering.old_key(*key);
+            }
+        }
+        path
+    }
+
+    pub fn node_len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// whether hierarchy is borrowed from memory map of `CHFile`, not copied
+    pub fn is_mapped(&self) -> bool {
+        self.order.is_mapped() && self.all_contractions.is_mapped() && self.contractions.is_mapped()
+    }
+
+    /// same as `CH::search`
+    pub fn search(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u32)> {
+        self.search_with_stats(src, dst).0
+    }
+
+    /// same as `search`, reusing `ctx` instead of allocating search state
+    pub fn search_with(
+        &self,
+        ctx: &mut QueryContext,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32)> {
+        let (src, dst) = (self.inner_key(src), self.inner_key(dst));
+        let result = self.hierarchy().search_with(ctx, src, dst);
+        result.map(|(path, cost)| (self.outer_path(path), cost))
+    }
+
+    /// same as `search` within limits of `options`
+    pub fn search_bounded(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        options: &SearchOptions,
+    ) -> SearchResult {
+        let (src, dst) = (self.inner_key(src), self.inner_key(dst));
+        match self.hierarchy().search_bounded(src, dst, options) {
+            SearchResult::Found(path, cost) => SearchResult::Found(self.outer_path(path), cost),
+            result => result,
+        }
+    }
+
+    /// same as `search`, with statistics of the query
+    pub fn search_with_stats(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> (Option<(Vec<IdxNodeKey>, u32)>, SearchStats) {
+        let (src, dst) = (self.inner_key(src), self.inner_key(dst));
+        let (result, stats) = self.hierarchy().search(src, dst);
+        (
+            result.map(|(path, cost)| (self.outer_path(path), cost)),
+            stats,
+        )
+    }
+
+    /// same as `search_with`, with secondary metric of the path, e.g. length of the fastest
+    /// path if the graph is built on time. Secondary metric is 0 if the graph has none, see
+    /// `Graph::secondary`.
+    pub fn search_metrics(
+        &self,
+        ctx: &mut QueryContext,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32, u32)> {
+        let (src, dst) = (self.inner_key(src), self.inner_key(dst));
+        let result = self.hierarchy().search_metrics(ctx, src, dst);
+        result.map(|(path, cost, secondary)| (self.outer_path(path), cost, secondary))
+    }
+
+    /// compute secondary metric of hierarchy links and contractions from `graph`, see
+    /// `carry_secondary`
+    pub(crate) fn carry_secondary(&mut self, graph: &Graph) {
+        assert!(self.renumbering.is_none(), "renumbered hierarchy");
+        self.secondary = carry_secondary(
+            &graph.idx_links,
+            &graph.secondary,
+            &self.order,
+            &self.contractions,
+            &self.mids,
+            &mut self.all_contractions,
+        )
+        .into();
+    }
+
+    pub(crate) fn hierarchy(&self) -> Hierarchy<'_> {
+        Hierarchy {
+            order: &self.order,
+            contractions: &self.contractions,
+            mids: &self.mids,
+            secondary: &self.secondary,
+            all_contractions: &self.all_contractions,
         }
+    }
+}
 
-        self.all_contractions.sort_unstable_by_key(|c| c.snode_idx);
+impl<'a> From<CH<'a>> for CHQuery {
+    fn from(ch: CH<'a>) -> Self {
+        Self {
+            order: ch.order,
+            contractions: ch.hierarchy_links,
+            mids: ch.mids,
+            secondary: ch.secondary,
+            all_contractions: ch.all_contractions,
+            renumbering: None,
+        }
     }
 }
 
 // search
 impl<'a> CH<'a> {
-    fn search_step(
+    /// shortest path from `src` to `dst` and its cost. None if unreachable or if the cost of the
+    /// path overflows, use `search_bounded` to tell them apart.
+    pub fn search(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u32)> {
+        self.hierarchy().search(src, dst).0
+    }
+
+    /// same as `search`, reusing `ctx` instead of allocating search state
+    pub fn search_with(
+        &self,
+        ctx: &mut QueryContext,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32)> {
+        self.hierarchy().search_with(ctx, src, dst)
+    }
+
+    /// same as `search` within limits of `options`
+    pub fn search_bounded(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        options: &SearchOptions,
+    ) -> SearchResult {
+        self.hierarchy().search_bounded(src, dst, options)
+    }
+
+    /// same as `search`, with statistics of the query
+    pub fn search_with_stats(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> (Option<(Vec<IdxNodeKey>, u32)>, SearchStats) {
+        self.hierarchy().search(src, dst)
+    }
+
+    /// same as `search_with`, with secondary metric of the path, e.g. length of the fastest
+    /// path if the graph is built on time. Secondary metric is 0 if the graph has none, see
+    /// `Graph::secondary`.
+    pub fn search_metrics(
         &self,
-        search: &mut dijkstra::Search<IdxNodeKey>,
+        ctx: &mut QueryContext,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32, u32)> {
+        self.hierarchy().search_metrics(ctx, src, dst)
+    }
+}
+
+/// statistics of a query, summed over forward and backward searches
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchStats {
+    /// nodes popped from heap, including stalled nodes
+    pub settled: usize,
+    /// links relaxed from settled nodes
+    pub relaxed: usize,
+    /// settled nodes which are not expanded by stall-on-demand
+    pub stalled: usize,
+}
+
+/// borrowed view of hierarchy shared by `CH` and `CHQuery`
+#[derive(Clone, Copy)]
+pub(crate) struct Hierarchy<'a> {
+    pub(crate) order: &'a [u32],
+    pub(crate) contractions: &'a Csr,
+    /// middle node of each link of `contractions`
+    pub(crate) mids: &'a [IdxNodeKey],
+    /// secondary metric of each link of `contractions`, empty if there is none
+    pub(crate) secondary: &'a [u32],
+    pub(crate) all_contractions: &'a [CHContraction],
+}
+
+impl<'a> Hierarchy<'a> {
+    fn write<P>(&self, path: P, old_keys: &[IdxNodeKey]) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        CHFile::write(
+            path.as_ref().join(CH_FILE_NAME),
+            self.order,
+            self.contractions,
+            self.mids,
+            self.secondary,
+            self.all_contractions,
+            old_keys,
+        )
+    }
+
+    /// cost of the cheapest original link from `snode_idx` to `enode_idx`, which is kept on the
+    /// lower one of them. None if there is no such link.
+    pub(crate) fn link_cost(&self, snode_idx: IdxNodeKey, enode_idx: IdxNodeKey) -> Option<u32> {
+        let (lower, higher, dir) = if self.order[snode_idx.index()] < self.order[enode_idx.index()]
+        {
+            (snode_idx, enode_idx, IdxLinkDir::Forward)
+        } else {
+            (enode_idx, snode_idx, IdxLinkDir::Backward)
+        };
+        let r = self.contractions.range(lower.index());
+        self.contractions.flat_links()[r.clone()]
+            .iter()
+            .zip(self.mids[r].iter())
+            .filter(|(l, mid)| l.dir() == dir && l.enode_idx == higher && **mid == IdxNodeKey::NONE)
+            .map(|(l, _)| l.cost())
+            .min()
+    }
+
+    pub(crate) fn search_step<M>(
+        &self,
+        search: &mut dijkstra::Search<IdxNodeKey, M>,
         dir: IdxLinkDir,
-    ) -> Option<dijkstra::HeapEntry<IdxNodeKey>> {
+    ) -> Option<dijkstra::HeapEntry<IdxNodeKey>>
+    where
+        M: dijkstra::CostMap<IdxNodeKey>,
+    {
         if let Some(entry) = search.next() {
             let key = entry.key;
-            for link in &self.contractions[key.index()] {
-                if link.dir() != dir {
-                    continue;
-                }
+            for link in self.contractions.dir_links(key.index(), dir) {
                 let next_key = link.enode_idx;
-                let next_cost = entry.cost + link.cost();
+                let next_cost = entry.cost.saturating_add(link.cost());
                 search.update(&entry, next_key, next_cost);
             }
             return Some(entry);
@@ -629,133 +1513,420 @@ impl<'a> CH<'a> {
         None
     }
 
-    fn decode_contraction(&self, mut path: Vec<IdxNodeKey>, dir: IdxLinkDir) -> Vec<IdxNodeKey> {
-        let all_contractions = self.all_contractions.as_slice();
+    /// same as `search_step`, but skips expanding a node if its cost is dominated by a path via
+    /// higher-ordered neighbor, which is stall-on-demand, if `stall` is set. Stalled node is
+    /// still returned, as its cost is a cost of valid path.
+    fn search_step_stall<M>(
+        &self,
+        search: &mut dijkstra::Search<IdxNodeKey, M>,
+        dir: IdxLinkDir,
+        stall: bool,
+        stats: &mut SearchStats,
+    ) -> Option<dijkstra::HeapEntry<IdxNodeKey>>
+    where
+        M: dijkstra::CostMap<IdxNodeKey>,
+    {
+        let entry = search.next()?;
+        stats.settled += 1;
+
+        let idx = entry.key.index();
+        // links on opposite direction are from higher-ordered nodes toward the node
+        let stalled = stall
+            && self
+                .contractions
+                .dir_links(idx, dir.rev())
+                .iter()
+                .any(|link| {
+                    search
+                        .get_cost(&link.enode_idx)
+                        .map(|c| c.cost.saturating_add(link.cost()) < entry.cost)
+                        .unwrap_or(false)
+                });
+        if stalled {
+            stats.stalled += 1;
+            return Some(entry);
+        }
 
-        let mut decoded = Vec::new();
-        // use path as a stack
-        while !path.is_empty() {
-            if path.len() == 1 {
-                decoded.push(path.pop().unwrap());
-                break;
-            }
+        for link in self.contractions.dir_links(idx, dir) {
+            stats.relaxed += 1;
+            let next_cost = entry.cost.saturating_add(link.cost());
+            search.update(&entry, link.enode_idx, next_cost);
+        }
+        Some(entry)
+    }
 
-            let key1 = path.pop().unwrap();
-            let key2 = *path.last().unwrap();
+    /// cheapest link on `key` toward `other` on given direction, with its middle node
+    fn min_link(&self, key: IdxNodeKey, other: IdxNodeKey, dir: IdxLinkDir) -> (u32, IdxNodeKey) {
+        let r = self.contractions.range(key.index());
+        let links = &self.contractions.flat_links()[r.clone()];
+        links
+            .iter()
+            .zip(self.mids[r].iter())
+            .filter(|(l, _)| l.enode_idx == other && l.dir() == dir)
+            .map(|(l, mid)| (l.cost(), *mid))
+            .min()
+            .expect("missing link of contraction")
+    }
+
+    /// append nodes of link `snode_idx -> enode_idx` with middle node `mid` to `decoded`,
+    /// excluding `snode_idx`. Middle node is lower than both ends, so sub-links are found on
+    /// links of middle node: backward link toward `snode_idx`, forward link toward `enode_idx`.
+    fn unpack_link(
+        &self,
+        snode_idx: IdxNodeKey,
+        enode_idx: IdxNodeKey,
+        mid: IdxNodeKey,
+        decoded: &mut Vec<IdxNodeKey>,
+    ) {
+        // use stack of (end node, middle node), sub-links are pushed in reverse order
+        let mut stack = vec![(enode_idx, mid)];
+        let mut cur = snode_idx;
+        while let Some((next, mid)) = stack.pop() {
+            if mid == IdxNodeKey::NONE {
+                decoded.push(next);
+                cur = next;
+                continue;
+            }
+            let (_, mid_next) = self.min_link(mid, next, IdxLinkDir::Forward);
+            let (_, mid_prev) = self.min_link(mid, cur, IdxLinkDir::Backward);
+            stack.push((next, mid_next));
+            stack.push((mid, mid_prev));
+        }
+    }
 
-            let mut found = None;
-            let mut minlen = std::u32::MAX;
+    /// unpack path of search on `dir`, from source of the search to `key`, with sum of
+    /// secondary metric of the path. Link between consecutive nodes is the one whose cost
+    /// matches difference of their costs, which is the link used by the search.
+    fn decode_path<M>(
+        &self,
+        search: &dijkstra::Search<IdxNodeKey, M>,
+        key: IdxNodeKey,
+        dir: IdxLinkDir,
+    ) -> (Vec<IdxNodeKey>, u32)
+    where
+        M: dijkstra::CostMap<IdxNodeKey>,
+    {
+        let path = search.decode(key);
+        let cost = |k: &IdxNodeKey| search.get_cost(k).map(|c| c.cost).unwrap_or_default();
+
+        let mut decoded = vec![path[0]];
+        let mut secondary = 0u32;
+        for pair in path.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            let link_cost = cost(&to) - cost(&from);
+            let r = self.contractions.range(from.index());
+            let links = &self.contractions.flat_links()[r.clone()];
+            let pos = r.start
+                + links
+                    .iter()
+                    .position(|l| l.enode_idx == to && l.dir() == dir && l.cost() == link_cost)
+                    .expect("missing link of path");
+            let mid = self.mids[pos];
+            if let Some(s) = self.secondary.get(pos) {
+                secondary = secondary.saturating_add(*s);
+            }
 
-            // find contracted edge
             match dir {
-                IdxLinkDir::Forward => {
-                    let r = all_contractions.equal_range_by_key(&key2, |c| c.snode_idx);
-                    for c in &all_contractions[r] {
-                        if c.enode_idx == key1 && c.length < minlen {
-                            found = Some(c);
-                            minlen = c.length;
-                        }
-                    }
-                }
+                IdxLinkDir::Forward => self.unpack_link(from, to, mid, &mut decoded),
                 IdxLinkDir::Backward => {
-                    let r = all_contractions.equal_range_by_key(&key1, |c| c.snode_idx);
-                    for c in &all_contractions[r] {
-                        if c.enode_idx == key2 && c.length < minlen {
-                            found = Some(c);
-                            minlen = c.length;
-                        }
-                    }
+                    // unpack original direction `to -> from`, then reverse
+                    let mut sub = vec![to];
+                    self.unpack_link(to, from, mid, &mut sub);
+                    sub.pop();
+                    decoded.extend(sub.into_iter().rev());
                 }
             }
+        }
+        (decoded, secondary)
+    }
 
-            if let Some(c) = found {
-                path.push(c.mnode_idx);
-                path.push(key1);
-            } else {
-                decoded.push(key1);
+    fn search(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> (Option<(Vec<IdxNodeKey>, u32)>, SearchStats) {
+        let (result, stats) = self.search_in(
+            &mut dijkstra::Search::new(),
+            &mut dijkstra::Search::new(),
+            src,
+            dst,
+            &Default::default(),
+        );
+        (result.into_option(), stats)
+    }
+
+    fn search_bounded(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        options: &SearchOptions,
+    ) -> SearchResult {
+        self.search_in(
+            &mut dijkstra::Search::new(),
+            &mut dijkstra::Search::new(),
+            src,
+            dst,
+            options,
+        )
+        .0
+    }
+
+    fn search_with(
+        &self,
+        ctx: &mut QueryContext,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32)> {
+        let (search_f, search_b) = ctx.searches(self.order.len());
+        self.search_in(search_f, search_b, src, dst, &Default::default())
+            .0
+            .into_option()
+    }
+
+    /// same as `search_with`, with sum of secondary metric of the path
+    fn search_metrics(
+        &self,
+        ctx: &mut QueryContext,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32, u32)> {
+        let (search_f, search_b) = ctx.searches(self.order.len());
+        let (meet, _) = self.search_meet(search_f, search_b, src, dst, &Default::default(), true);
+        let (key, cost) = meet.ok()?;
+        let (path, secondary) = self.decode_meet(search_f, search_b, key);
+        Some((path, cost, secondary))
+    }
+
+    fn search_in<M>(
+        &self,
+        search_f: &mut dijkstra::Search<IdxNodeKey, M>,
+        search_b: &mut dijkstra::Search<IdxNodeKey, M>,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        options: &SearchOptions,
+    ) -> (SearchResult, SearchStats)
+    where
+        M: dijkstra::CostMap<IdxNodeKey>,
+    {
+        let (meet, stats) = self.search_meet(search_f, search_b, src, dst, options, true);
+        let result = match meet {
+            Ok((key, cost)) => {
+                let (path, _) = self.decode_meet(search_f, search_b, key);
+                SearchResult::Found(path, cost)
             }
-        }
-        decoded.reverse();
-        decoded
+            Err(result) => result,
+        };
+        (result, stats)
     }
 
-    pub fn search(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u32)> {
-        /*
-        let c0 = self.contractions[src.index()]
-            .iter()
-            .filter(|l| l.dir() == IdxLinkDir::Forward)
-            .collect::<Vec<_>>();
-        let c1 = self.contractions[dst.index()]
-            .iter()
-            .filter(|l| l.dir() == IdxLinkDir::Backward)
-            .collect::<Vec<_>>();
-        eprintln!("c0={:?}, c1={:?}", c0, c1);
-        */
+    /// run both upward searches, returning the meeting node of the shortest path with its
+    /// cost, or the result if no path is found within limits of `options`. Stall-on-demand is
+    /// used if `stall` is set, see `search_step_stall`.
+    fn search_meet<M>(
+        &self,
+        search_f: &mut dijkstra::Search<IdxNodeKey, M>,
+        search_b: &mut dijkstra::Search<IdxNodeKey, M>,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        options: &SearchOptions,
+        stall: bool,
+    ) -> (
+        std::result::Result<(IdxNodeKey, u32), SearchResult>,
+        SearchStats,
+    )
+    where
+        M: dijkstra::CostMap<IdxNodeKey>,
+    {
+        let mut stats = SearchStats::default();
 
-        let mut search_f = dijkstra::Search::new();
+        // costs of both upward searches are within the cost of the path, so the limit applies
+        // to each of them
+        search_f.cost_limit = options.cost_limit();
+        search_b.cost_limit = options.cost_limit();
         search_f.add_src(src);
-
-        let mut search_b = dijkstra::Search::new();
         search_b.add_src(dst);
 
-        let mut min_cost = std::u32::MAX;
+        let mut min_cost = u32::MAX;
         let mut min_key = IdxNodeKey::new(0);
+        // a meeting whose cost overflowed
+        let mut overflowed = false;
 
         loop {
-            // update threshold
-            search_f.cost_limit = min_cost;
-            search_b.cost_limit = min_cost;
-            let mut updated = false;
+            // a direction is done if every unvisited node costs more than the best meeting
+            let done_f = search_f.peek_cost().map(|c| c >= min_cost).unwrap_or(true);
+            let done_b = search_b.peek_cost().map(|c| c >= min_cost).unwrap_or(true);
+            if done_f && done_b {
+                break;
+            }
+            // best meeting so far is not proven to be the shortest
+            if stats.settled >= options.settled_limit() {
+                return (Err(SearchResult::OutOfRange), stats);
+            }
 
             macro_rules! step {
                 ($f:ident, $b:ident, $dir:expr) => {
-                    if let Some(entry) = self.search_step(&mut $f, $dir) {
-                        updated = true;
+                    if let Some(entry) = self.search_step_stall($f, $dir, stall, &mut stats) {
                         if let Some(cost) = $b.get_cost(&entry.key) {
-                            if !cost.visited {
-                                continue;
-                            }
-                            let cost = entry.cost + cost.cost;
-                            if cost < min_cost {
-                                min_cost = cost;
-                                min_key = entry.key;
+                            if cost.visited {
+                                let cost = entry.cost.saturating_add(cost.cost);
+                                overflowed |= cost == u32::MAX;
+                                if cost < min_cost {
+                                    min_cost = cost;
+                                    min_key = entry.key;
+                                }
                             }
                         }
                     }
                 };
             }
 
-            step!(search_f, search_b, IdxLinkDir::Forward);
-            step!(search_b, search_f, IdxLinkDir::Backward);
-
-            if !updated {
-                break;
+            if !done_f {
+                step!(search_f, search_b, IdxLinkDir::Forward);
+            }
+            if !done_b {
+                step!(search_b, search_f, IdxLinkDir::Backward);
             }
         }
-        if min_cost == std::u32::MAX {
-            return None;
+        if min_cost == u32::MAX || options.cost_limit() <= min_cost {
+            // a path found beyond the limit is out of range as well, as limits apply to each
+            // upward search, not to the sum
+            let overflowed = overflowed || search_f.overflowed() || search_b.overflowed();
+            let result = if options.max_cost.is_none() && overflowed {
+                SearchResult::Overflow
+            } else if min_cost != u32::MAX || search_f.limited() || search_b.limited() {
+                SearchResult::OutOfRange
+            } else {
+                SearchResult::Unreachable
+            };
+            return (Err(result), stats);
         }
 
-        let decoded = {
-            let path_f = search_f.decode(min_key);
-            let path_b = search_b.decode(min_key);
-
-            assert_eq!(path_f.last(), path_b.last());
-
-            let mut d_f = self.decode_contraction(path_f, IdxLinkDir::Forward);
-            let mut d_b = self.decode_contraction(path_b, IdxLinkDir::Backward);
+        (Ok((min_key, min_cost)), stats)
+    }
 
-            assert_eq!(d_f.last(), d_b.last());
+    /// unpack path of both searches meeting at `key`, with sum of secondary metric
+    fn decode_meet<M>(
+        &self,
+        search_f: &dijkstra::Search<IdxNodeKey, M>,
+        search_b: &dijkstra::Search<IdxNodeKey, M>,
+        key: IdxNodeKey,
+    ) -> (Vec<IdxNodeKey>, u32)
+    where
+        M: dijkstra::CostMap<IdxNodeKey>,
+    {
+        let (mut d_f, s_f) = self.decode_path(search_f, key, IdxLinkDir::Forward);
+        let (mut d_b, s_b) = self.decode_path(search_b, key, IdxLinkDir::Backward);
 
-            d_b.reverse();
-            d_f.pop();
-            d_f.append(&mut d_b);
-            d_f
-        };
+        assert_eq!(d_f.last(), d_b.last());
 
-        Some((decoded, min_cost))
+        d_b.reverse();
+        d_f.pop();
+        d_f.append(&mut d_b);
+        (d_f, s_f.saturating_add(s_b))
     }
 }
 
 const _CHECK_CHENTRY: [u8; 8] = [0; std::mem::size_of::<CHEntry>()];
-const _CHECK_CHCONTRACTION: [u8; 16] = [0; std::mem::size_of::<CHContraction>()];
+const _CHECK_CHCONTRACTION: [u8; 20] = [0; std::mem::size_of::<CHContraction>()];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn build(graph: &Graph, parallel: bool) -> CH<'_> {
+        let mut ch = CH::new(graph);
+        ch.build_with_config(CHBuildConfig {
+            parallel,
+            ..Default::default()
+        });
+        ch
+    }
+
+    #[test]
+    fn parallel_equals_sequential() {
+        for seed in [1, 2, 3] {
+            let graph = testutil::grid(8, seed);
+            let sequential = build(&graph, false);
+            let parallel = build(&graph, true);
+            for (src, dst) in testutil::all_pairs(graph.node_len) {
+                let cost = graph.search(src, dst).map(|r| r.1);
+                assert_eq!(sequential.search(src, dst).map(|r| r.1), cost);
+                assert_eq!(parallel.search(src, dst).map(|r| r.1), cost);
+            }
+        }
+    }
+
+    #[test]
+    fn build_is_deterministic() {
+        let graph = testutil::grid(8, 4);
+        for parallel in [false, true] {
+            let ch = build(&graph, parallel);
+            let other = build(&graph, parallel);
+            assert!(ch.is_identical(&other));
+
+            let path = testutil::temp_dir(&format!("ch-deterministic-{}", parallel));
+            let other_path = path.join("other");
+            std::fs::create_dir_all(&other_path).unwrap();
+            ch.write(&path).unwrap();
+            other.write(&other_path).unwrap();
+            assert_eq!(
+                std::fs::read(path.join(CH_FILE_NAME)).unwrap(),
+                std::fs::read(other_path.join(CH_FILE_NAME)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn stalling_settles_fewer_nodes() {
+        let graph = testutil::grid(10, 6);
+        let ch = build(&graph, false);
+        let hierarchy = ch.hierarchy();
+
+        let (mut with_stall, mut without_stall) = (SearchStats::default(), SearchStats::default());
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            let (result, stats) = ch.search_with_stats(src, dst);
+            let (meet, plain) = hierarchy.search_meet(
+                &mut dijkstra::Search::new(),
+                &mut dijkstra::Search::new(),
+                src,
+                dst,
+                &Default::default(),
+                false,
+            );
+            let cost = graph.search(src, dst).map(|r| r.1);
+            assert_eq!(result.map(|r| r.1), cost);
+            assert_eq!(meet.ok().map(|m| m.1), cost);
+            assert_eq!(plain.stalled, 0);
+
+            with_stall.settled += stats.settled;
+            with_stall.relaxed += stats.relaxed;
+            with_stall.stalled += stats.stalled;
+            without_stall.settled += plain.settled;
+            without_stall.relaxed += plain.relaxed;
+        }
+        assert!(with_stall.stalled > 0);
+        assert!(with_stall.settled < without_stall.settled);
+        assert!(with_stall.relaxed < without_stall.relaxed);
+    }
+
+    #[test]
+    fn search_continues_after_first_meeting() {
+        // 0 -> 2 -> 1 costs 4 and 0 -> 3 -> 4 -> 1 costs 3. Forward search settles 2 at 1 and
+        // backward search settles 2 at 3 before forward search reaches 4, so 2 is the first
+        // meeting node but not on the shortest path.
+        let graph = testutil::graph(5, &[(0, 2, 1), (2, 1, 3), (0, 3, 1), (3, 4, 1), (4, 1, 1)]);
+        let mut ch = CH::new(&graph);
+        ch.order = vec![1, 2, 4, 3, 5].into();
+        let (hierarchy_links, mids) = query_links(&graph.idx_links, &ch.order, &[]);
+        ch.hierarchy_links = hierarchy_links;
+        ch.mids = mids.into();
+
+        let keys = |v: &[usize]| v.iter().map(|i| IdxNodeKey::new(*i)).collect::<Vec<_>>();
+        let (src, dst) = (IdxNodeKey::new(0), IdxNodeKey::new(1));
+        let (result, stats) = ch.search_with_stats(src, dst);
+        assert_eq!(result, Some((keys(&[0, 3, 4, 1]), 3)));
+        // forward search settles all nodes but 1, backward search settles 1, 4 and 2
+        assert_eq!(stats.settled, 7);
+    }
+}
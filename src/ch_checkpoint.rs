@@ -0,0 +1,383 @@
+use anyhow::{bail, Result};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+
+use super::*;
+
+/// directory of checkpoint inside of checkpoint path
+const CHECKPOINT_DIR_NAME: &str = "checkpoint";
+/// previous checkpoint while replacing it, used by `CH::resume` if checkpoint is missing
+const CHECKPOINT_OLD_DIR_NAME: &str = "checkpoint.old";
+const CHECKPOINT_NODES_FILE_NAME: &str = "nodes.csv";
+const CHECKPOINT_COUNTS_FILE_NAME: &str = "original_counts.csv";
+const CHECKPOINT_META_FILE_NAME: &str = "meta.csv";
+
+/// progress of `CH::build_with_hooks`
+#[derive(Clone, Copy, Debug)]
+pub struct CHBuildProgress {
+    /// number of contracted nodes
+    pub order: usize,
+    pub node_len: usize,
+    /// number of contractions added so far
+    pub contractions: usize,
+    /// current hop limit of witness search
+    pub hop_limit: u16,
+}
+
+pub trait CHBuildObserver: Sync {
+    fn progress(&self, progress: &CHBuildProgress);
+}
+
+/// token to cancel build from other threads
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, atomic::Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(atomic::Ordering::Relaxed)
+    }
+
+    /// shared flag of the token, e.g. to be set by a signal handler
+    pub fn flag(&self) -> &Arc<AtomicBool> {
+        &self.0
+    }
+}
+
+/// hooks of `CH::build_with_hooks`
+#[derive(Default)]
+pub struct CHBuildHooks<'h> {
+    pub observer: Option<&'h dyn CHBuildObserver>,
+    pub cancel: Option<CancelToken>,
+    /// directory to write checkpoint, which can be resumed with `CH::resume`
+    pub checkpoint_path: Option<PathBuf>,
+    /// write checkpoint for every given number of contracted nodes, rounded up to multiple of
+    /// `CHBuildConfig::step`. Checkpoint is written only when cancelled if 0.
+    pub checkpoint_interval: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CheckpointNode {
+    deleted_count: u16,
+    level: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CheckpointCount {
+    snode_idx: IdxNodeKey,
+    enode_idx: IdxNodeKey,
+    count: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CheckpointMeta {
+    node_len: usize,
+    /// number of links of graph, with `fingerprint` to detect checkpoint of other graph
+    link_len: usize,
+    fingerprint: u64,
+    order: u32,
+    hop_limit: u16,
+    try_count: usize,
+}
+
+/// hash of nodes, links and costs of `links`, see `checksum`
+fn fingerprint(links: &Csr) -> u64 {
+    unsafe { checksum(&[as_bytes(links.offsets()), as_bytes(links.flat_links())]) }
+}
+
+/// flush files of `dir` and the directory itself, so a renamed checkpoint is complete on disk
+/// even if the machine stops
+fn sync_dir(dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        File::open(entry?.path())?.sync_all()?;
+    }
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+impl<'a> CH<'a> {
+    /// write intermediate state of build. Checkpoint is written to temporary directory first,
+    /// then previous checkpoint is renamed aside before renaming the new one, so one of them is
+    /// always kept if writing fails or the process stops.
+    pub(crate) fn write_checkpoint(
+        &self,
+        path: &Path,
+        priorities: &Priorities,
+        order: u32,
+        hop_limit: u16,
+        try_count: usize,
+    ) -> Result<()> {
+        let sw = Timer::new();
+
+        let tmp_path = path.join(format!("{}.tmp", CHECKPOINT_DIR_NAME));
+        if tmp_path.exists() {
+            std::fs::remove_dir_all(&tmp_path)?;
+        }
+        std::fs::create_dir_all(&tmp_path)?;
+
+        // links of remaining graph are not contractions for query, so have no middle nodes
+        let links = self.contractions.to_csr();
+        let mids = vec![IdxNodeKey::NONE; links.link_len()];
+        CHFile::write(
+            tmp_path.join(CH_FILE_NAME),
+            &self.order,
+            &links,
+            &mids,
+            &[],
+            &self.all_contractions,
+            &[],
+        )?;
+
+        let nodes = priorities
+            .deleted_count
+            .iter()
+            .zip(priorities.levels.iter())
+            .map(|(deleted_count, level)| CheckpointNode {
+                deleted_count: *deleted_count,
+                level: *level,
+            })
+            .collect::<Vec<_>>();
+        encode_csv(tmp_path.join(CHECKPOINT_NODES_FILE_NAME), &nodes)?;
+
+        let mut counts = priorities
+            .original_counts
+            .iter()
+            .map(|((snode_idx, enode_idx), count)| CheckpointCount {
+                snode_idx: *snode_idx,
+                enode_idx: *enode_idx,
+                count: *count,
+            })
+            .collect::<Vec<_>>();
+        counts.sort_unstable_by_key(|c| (c.snode_idx, c.enode_idx));
+        encode_csv(tmp_path.join(CHECKPOINT_COUNTS_FILE_NAME), &counts)?;
+
+        let meta = CheckpointMeta {
+            node_len: self.order.len(),
+            link_len: self.links.link_len(),
+            fingerprint: fingerprint(&self.links),
+            order,
+            hop_limit,
+            try_count,
+        };
+        encode_csv(tmp_path.join(CHECKPOINT_META_FILE_NAME), &[meta])?;
+
+        self.config.write(&tmp_path)?;
+
+        sync_dir(&tmp_path)?;
+
+        let checkpoint_path = path.join(CHECKPOINT_DIR_NAME);
+        let old_path = path.join(CHECKPOINT_OLD_DIR_NAME);
+        if checkpoint_path.exists() {
+            if old_path.exists() {
+                std::fs::remove_dir_all(&old_path)?;
+            }
+            std::fs::rename(&checkpoint_path, &old_path)?;
+        }
+        std::fs::rename(&tmp_path, &checkpoint_path)?;
+        File::open(path)?.sync_all()?;
+        if old_path.exists() {
+            std::fs::remove_dir_all(&old_path)?;
+        }
+
+        eprintln!(
+            "writing checkpoint took: {}, order={}, contractions={}",
+            sw.took(),
+            order,
+            self.all_contractions.len()
+        );
+        Ok(())
+    }
+
+    /// load checkpoint from `path`, written by `CH::build_with_hooks`. Call `build_with_hooks`
+    /// to continue build, with the config of checkpoint, see `CH::config`. Fails if `graph` has
+    /// other links or costs than the graph of checkpoint.
+    pub fn resume<P>(graph: &'a Graph, path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        // previous checkpoint is left aside if stopped while replacing it
+        let mut checkpoint_path = path.as_ref().join(CHECKPOINT_DIR_NAME);
+        if !checkpoint_path.exists() {
+            checkpoint_path = path.as_ref().join(CHECKPOINT_OLD_DIR_NAME);
+        }
+        let path = checkpoint_path;
+
+        let meta: Vec<CheckpointMeta> = decode_csv(path.join(CHECKPOINT_META_FILE_NAME))?;
+        let meta = match meta.into_iter().next() {
+            Some(meta) => meta,
+            None => bail!("invalid checkpoint: {:?}, empty meta", path),
+        };
+        if meta.node_len != graph.node_len {
+            bail!(
+                "invalid checkpoint: {:?}, {} != {}",
+                path,
+                meta.node_len,
+                graph.node_len
+            );
+        }
+        if meta.link_len != graph.idx_links.link_len()
+            || meta.fingerprint != fingerprint(&graph.idx_links)
+        {
+            bail!("invalid checkpoint: {:?}, written for other graph", path);
+        }
+
+        let ch_file = CHFile::open(path.join(CH_FILE_NAME), true)?;
+        if ch_file.node_len() != graph.node_len {
+            bail!("invalid checkpoint: {:?}, bad node count", path);
+        }
+
+        let nodes: Vec<CheckpointNode> = decode_csv(path.join(CHECKPOINT_NODES_FILE_NAME))?;
+        if nodes.len() != graph.node_len {
+            bail!("invalid checkpoint: {:?}, bad node priorities", path);
+        }
+        let counts: Vec<CheckpointCount> = decode_csv(path.join(CHECKPOINT_COUNTS_FILE_NAME))?;
+
+        let config = CHBuildConfig::from_file(&path)?;
+
+        let priorities = Priorities {
+            deleted_count: nodes.iter().map(|n| n.deleted_count).collect(),
+            levels: nodes.iter().map(|n| n.level).collect(),
+            original_counts: counts
+                .into_iter()
+                .map(|c| ((c.snode_idx, c.enode_idx), c.count))
+                .collect(),
+        };
+
+        let mut ch = Self::new(graph);
+        ch.order = ch_file.order().to_vec().into();
+        ch.contractions = DynCsr::from(&ch_file.csr());
+        ch.all_contractions = ch_file.contractions().to_vec().into();
+        ch.config = config;
+        ch.build_state = Some(BuildState {
+            priorities,
+            hop_limit: meta.hop_limit,
+            order: meta.order,
+            try_count: meta.try_count,
+        });
+        Ok(ch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    /// cancels build after given number of contracted nodes
+    struct CancelAfter(CancelToken, usize);
+
+    impl CHBuildObserver for CancelAfter {
+        fn progress(&self, progress: &CHBuildProgress) {
+            if progress.order >= self.1 {
+                self.0.cancel();
+            }
+        }
+    }
+
+    fn assert_same_costs(ch: &CH, graph: &Graph) {
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            assert_eq!(
+                ch.search(src, dst).map(|r| r.1),
+                graph.search(src, dst).map(|r| r.1)
+            );
+        }
+    }
+
+    #[test]
+    fn cancel_and_resume() {
+        let graph = testutil::grid(7, 5);
+        let path = testutil::temp_dir("checkpoint-resume");
+        let config = CHBuildConfig {
+            step: 4,
+            ..Default::default()
+        };
+        for parallel in [false, true] {
+            let config = CHBuildConfig {
+                parallel,
+                ..config.clone()
+            };
+            let cancel = CancelToken::new();
+            let observer = CancelAfter(cancel.clone(), 20);
+            let hooks = CHBuildHooks {
+                observer: Some(&observer),
+                cancel: Some(cancel),
+                checkpoint_path: Some(path.clone()),
+                checkpoint_interval: 8,
+            };
+            let mut ch = CH::new(&graph);
+            assert!(ch.build_with_hooks(config.clone(), &hooks).is_err());
+            assert!(path.join(CHECKPOINT_DIR_NAME).exists());
+            assert!(!path.join(CHECKPOINT_OLD_DIR_NAME).exists());
+
+            let mut ch = CH::resume(&graph, &path).unwrap();
+            assert!(ch.build_state.as_ref().unwrap().order >= 20);
+            ch.build_with_hooks(config.clone(), &Default::default())
+                .unwrap();
+            assert_same_costs(&ch, &graph);
+
+            // stopped after previous checkpoint is renamed aside
+            std::fs::rename(
+                path.join(CHECKPOINT_DIR_NAME),
+                path.join(CHECKPOINT_OLD_DIR_NAME),
+            )
+            .unwrap();
+            let mut ch = CH::resume(&graph, &path).unwrap();
+            ch.build_with_hooks(config, &Default::default()).unwrap();
+            assert_same_costs(&ch, &graph);
+            std::fs::remove_dir_all(path.join(CHECKPOINT_OLD_DIR_NAME)).unwrap();
+        }
+    }
+
+    #[test]
+    fn resume_keeps_config() {
+        let graph = testutil::grid(6, 11);
+        let path = testutil::temp_dir("checkpoint-config");
+        let config = CHBuildConfig {
+            step: 1,
+            hop_steppings: vec![(2.5, 4), (1.5, 3)],
+            heap_limit: 100,
+            ..Default::default()
+        };
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let hooks = CHBuildHooks {
+            cancel: Some(cancel),
+            checkpoint_path: Some(path.clone()),
+            ..Default::default()
+        };
+        let mut ch = CH::new(&graph);
+        assert!(ch.build_with_hooks(config.clone(), &hooks).is_err());
+
+        // same nodes and links with a cost changed
+        let mut links = graph
+            .forward_links()
+            .map(|(s, l)| (s.index(), l.enode_idx.index(), l.cost()))
+            .collect::<Vec<_>>();
+        links[0].2 += 1;
+        let other_graph = testutil::graph(graph.node_len, &links);
+        let err = CH::resume(&other_graph, &path).err().unwrap();
+        assert!(err.to_string().contains("other graph"), "{}", err);
+
+        let mut ch = CH::resume(&graph, &path).unwrap();
+        assert_eq!(ch.config(), &config);
+        let other = CHBuildConfig {
+            heap_limit: 1024,
+            ..config.clone()
+        };
+        assert!(ch.build_with_hooks(other, &Default::default()).is_err());
+
+        let mut ch = CH::resume(&graph, &path).unwrap();
+        let resumed = CHBuildConfig { step: 10, ..config };
+        ch.build_with_hooks(resumed, &Default::default()).unwrap();
+        assert_same_costs(&ch, &graph);
+    }
+}
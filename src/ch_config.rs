@@ -0,0 +1,179 @@
+use anyhow::{bail, Result};
+
+use super::*;
+
+/// file names of `CHBuildConfig::write`, except `hop_steppings` which are stored on separate file
+const CONFIG_FILE_NAME: &str = "config.csv";
+const HOP_STEPPINGS_FILE_NAME: &str = "hop_steppings.csv";
+
+/// weights of terms of node priority while building contraction hierarchies. Node with the
+/// highest score is contracted first, where score is
+/// `edge_difference * (removed links - added contractions) - deleted_neighbors * (contracted
+/// neighbors) - original_edges * (original links of added contractions) - level * (level)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CHPriority {
+    pub edge_difference: i32,
+    pub deleted_neighbors: i32,
+    pub original_edges: i32,
+    pub level: i32,
+}
+
+impl Default for CHPriority {
+    fn default() -> Self {
+        Self {
+            edge_difference: 1,
+            deleted_neighbors: 1,
+            original_edges: 0,
+            level: 0,
+        }
+    }
+}
+
+impl CHPriority {
+    pub(crate) fn score(
+        &self,
+        edge_difference: i32,
+        deleted_neighbors: u16,
+        original_edges: u32,
+        level: u32,
+    ) -> i32 {
+        self.edge_difference * edge_difference
+            - self.deleted_neighbors * deleted_neighbors as i32
+            - self.original_edges * original_edges as i32
+            - self.level * level as i32
+    }
+}
+
+/// parameters of `CH::build_with_config`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CHBuildConfig {
+    /// print progress for every `step` contracted nodes
+    pub step: usize,
+    /// re-calculated score of a node is accepted if it's not less than score on heap minus
+    /// `score_tolerance`, otherwise the node is pushed back to heap
+    pub score_tolerance: i32,
+    /// minimum number of tries between rebuilding heap
+    pub heap_min_interval: usize,
+    /// pairs of (average degree, hop limit). Hop limit of witness search is raised to given hop
+    /// limit if average degree of remaining graph exceeds given degree.
+    pub hop_steppings: Vec<(f32, u16)>,
+    /// maximum number of heap items of witness search
+    pub heap_limit: usize,
+    pub priority: CHPriority,
+    /// contract independent sets of nodes in parallel instead of one node at a time
+    pub parallel: bool,
+}
+
+impl Default for CHBuildConfig {
+    fn default() -> Self {
+        const STEP: usize = 10000;
+        Self {
+            step: STEP,
+            score_tolerance: 2,
+            heap_min_interval: STEP * 2,
+            hop_steppings: vec![(8., 5), (5., 3), (3.3, 2)],
+            heap_limit: 1024,
+            priority: Default::default(),
+            parallel: false,
+        }
+    }
+}
+
+impl CHBuildConfig {
+    /// largest hop limit of witness search
+    pub fn max_hop_limit(&self) -> u16 {
+        self.hop_steppings
+            .iter()
+            .map(|(_, hop)| *hop)
+            .fold(1, std::cmp::max)
+    }
+}
+
+/// `CHBuildConfig` except `hop_steppings`
+#[derive(Serialize, Deserialize)]
+struct ConfigRecord {
+    step: usize,
+    score_tolerance: i32,
+    heap_min_interval: usize,
+    heap_limit: usize,
+    parallel: bool,
+    edge_difference: i32,
+    deleted_neighbors: i32,
+    original_edges: i32,
+    level: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HopSteppingRecord {
+    degree: f32,
+    hop_limit: u16,
+}
+
+impl CHBuildConfig {
+    /// write config to `path` directory as `config.csv` and `hop_steppings.csv`
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let record = ConfigRecord {
+            step: self.step,
+            score_tolerance: self.score_tolerance,
+            heap_min_interval: self.heap_min_interval,
+            heap_limit: self.heap_limit,
+            parallel: self.parallel,
+            edge_difference: self.priority.edge_difference,
+            deleted_neighbors: self.priority.deleted_neighbors,
+            original_edges: self.priority.original_edges,
+            level: self.priority.level,
+        };
+        encode_csv(path.join(CONFIG_FILE_NAME), &[record])?;
+        let hop_steppings = self
+            .hop_steppings
+            .iter()
+            .map(|&(degree, hop_limit)| HopSteppingRecord { degree, hop_limit })
+            .collect::<Vec<_>>();
+        encode_csv(path.join(HOP_STEPPINGS_FILE_NAME), &hop_steppings)
+    }
+
+    /// load config written by `CHBuildConfig::write`
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let records: Vec<ConfigRecord> = decode_csv(path.join(CONFIG_FILE_NAME))?;
+        let record = match records.into_iter().next() {
+            Some(record) => record,
+            None => bail!("invalid config: {:?}, empty config", path),
+        };
+        let hop_steppings: Vec<HopSteppingRecord> = decode_csv(path.join(HOP_STEPPINGS_FILE_NAME))?;
+        Ok(Self {
+            step: record.step,
+            score_tolerance: record.score_tolerance,
+            heap_min_interval: record.heap_min_interval,
+            hop_steppings: hop_steppings
+                .into_iter()
+                .map(|h| (h.degree, h.hop_limit))
+                .collect(),
+            heap_limit: record.heap_limit,
+            priority: CHPriority {
+                edge_difference: record.edge_difference,
+                deleted_neighbors: record.deleted_neighbors,
+                original_edges: record.original_edges,
+                level: record.level,
+            },
+            parallel: record.parallel,
+        })
+    }
+
+    /// config written next to hierarchy by `CH::write`, default if missing e.g. for files
+    /// written by older versions
+    pub(crate) fn from_dir_or_default(path: &Path) -> Result<Self> {
+        if path.join(CONFIG_FILE_NAME).exists() {
+            Self::from_file(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+}
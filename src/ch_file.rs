@@ -0,0 +1,647 @@
+use anyhow::{bail, Context, Result};
+use memmap::{Mmap, MmapOptions};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use super::*;
+
+/// file name of binary contraction hierarchies inside of output directory
+pub const CH_FILE_NAME: &str = "ch.bin";
+
+const MAGIC: [u8; 8] = *b"CHBIN\0\0\0";
+const VERSION: u32 = 6;
+/// marker to detect files written on machines with different endianness
+const ENDIAN_MARK: u32 = 0x0102_0304;
+
+/// On-disk header. Payload follows right after the header:
+///
+/// - `order`: `[u32; node_len]`
+/// - `link_offsets`: `[u32; node_len + 1]`, links of node `i` are in
+///   `links[link_offsets[i]..link_offsets[i + 1]]`
+/// - `backward_offsets`: `[u32; node_len]`, backward links of node `i` start from
+///   `links[backward_offsets[i]]`
+/// - `links`: `[IdxLink; link_len]`, upward/downward links for query
+/// - `mids`: `[IdxNodeKey; link_len]`, middle node of each link, `u32::MAX` for original links
+/// - `secondary`: `[u32; secondary_len]`, secondary metric of each link, `secondary_len` is
+///   either `link_len` or 0 if there is no secondary metric
+/// - `contractions`: `[CHContraction; contraction_len]`, sorted by `snode_idx`
+/// - `old_keys`: `[IdxNodeKey; renumber_len]`, original node of each node if the hierarchy is
+///   renumbered, see `Renumbering`. `renumber_len` is either `node_len` or 0.
+///
+/// All values are stored in native endianness, so the payload can be used directly from the
+/// memory map without decoding.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    endian: u32,
+    node_len: u64,
+    link_len: u64,
+    secondary_len: u64,
+    contraction_len: u64,
+    renumber_len: u64,
+    /// checksum of payload
+    checksum: u64,
+}
+
+const HEADER_LEN: usize = std::mem::size_of::<Header>();
+
+pub(crate) unsafe fn as_bytes<T>(v: &[T]) -> &[u8] {
+    std::slice::from_raw_parts(v.as_ptr() as *const u8, std::mem::size_of_val(v))
+}
+
+/// 64-bit FNV-1a over 8-byte words, trailing bytes are zero-padded.
+pub(crate) fn checksum(chunks: &[&[u8]]) -> u64 {
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for bytes in chunks {
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(word);
+            hash ^= u64::from_ne_bytes(buf);
+            hash = hash.wrapping_mul(PRIME);
+        }
+        let rem = words.remainder();
+        if !rem.is_empty() {
+            let mut buf = [0u8; 8];
+            buf[..rem.len()].copy_from_slice(rem);
+            hash ^= u64::from_ne_bytes(buf);
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    hash
+}
+
+/// slice owned in memory, or borrowed from memory-mapped `CHFile` without copying. Mapped
+/// slice is copied on first mutable access, like `Cow`.
+pub(crate) struct Section<T> {
+    repr: SectionRepr<T>,
+}
+
+enum SectionRepr<T> {
+    Owned(Vec<T>),
+    Mapped {
+        mmap: Arc<Mmap>,
+        offset: usize,
+        len: usize,
+    },
+}
+
+impl<T: Copy> Section<T> {
+    /// `len` items from `offset` of `mmap`, which should be aligned for `T`
+    fn mapped(mmap: &Arc<Mmap>, offset: usize, len: usize) -> Self {
+        assert!(offset + len * std::mem::size_of::<T>() <= mmap.len());
+        assert_eq!(
+            mmap[offset..].as_ptr() as usize % std::mem::align_of::<T>(),
+            0
+        );
+        Self {
+            repr: SectionRepr::Mapped {
+                mmap: mmap.clone(),
+                offset,
+                len,
+            },
+        }
+    }
+
+    pub(crate) fn is_mapped(&self) -> bool {
+        matches!(self.repr, SectionRepr::Mapped { .. })
+    }
+
+    /// owned items, copied from the memory map if mapped
+    pub(crate) fn to_mut(&mut self) -> &mut Vec<T> {
+        if let SectionRepr::Mapped { .. } = self.repr {
+            self.repr = SectionRepr::Owned(self.deref().to_vec());
+        }
+        match self.repr {
+            SectionRepr::Owned(ref mut v) => v,
+            SectionRepr::Mapped { .. } => unreachable!(),
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for Section<T> {
+    fn from(v: Vec<T>) -> Self {
+        Self {
+            repr: SectionRepr::Owned(v),
+        }
+    }
+}
+
+impl<T> Default for Section<T> {
+    fn default() -> Self {
+        Self::from(Vec::new())
+    }
+}
+
+impl<T> Deref for Section<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self.repr {
+            SectionRepr::Owned(ref v) => v,
+            SectionRepr::Mapped {
+                ref mmap,
+                offset,
+                len,
+            } => {
+                let ptr = mmap[offset..].as_ptr() as *const T;
+                unsafe { std::slice::from_raw_parts(ptr, len) }
+            }
+        }
+    }
+}
+
+impl<T: Copy> DerefMut for Section<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.to_mut()
+    }
+}
+
+impl<T: Clone> Clone for Section<T> {
+    fn clone(&self) -> Self {
+        let repr = match self.repr {
+            SectionRepr::Owned(ref v) => SectionRepr::Owned(v.clone()),
+            SectionRepr::Mapped {
+                ref mmap,
+                offset,
+                len,
+            } => SectionRepr::Mapped {
+                mmap: mmap.clone(),
+                offset,
+                len,
+            },
+        };
+        Self { repr }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Section<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.deref().fmt(f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for Section<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.deref() == other.deref()
+    }
+}
+
+impl<T: Eq> Eq for Section<T> {}
+
+/// byte offset of each section of payload
+struct Layout {
+    order: usize,
+    link_offsets: usize,
+    backward_offsets: usize,
+    links: usize,
+    mids: usize,
+    secondary: usize,
+    contractions: usize,
+    old_keys: usize,
+    end: usize,
+}
+
+impl Layout {
+    /// fails if sections do not fit in `usize`, e.g. lengths of a corrupted header
+    fn new(
+        node_len: usize,
+        link_len: usize,
+        secondary_len: usize,
+        contraction_len: usize,
+        renumber_len: usize,
+    ) -> Result<Self> {
+        // offset of section after `len` items of `T` from `offset`
+        fn next<T>(offset: usize, len: usize) -> Option<usize> {
+            offset.checked_add(len.checked_mul(std::mem::size_of::<T>())?)
+        }
+
+        let layout = || {
+            let order = HEADER_LEN;
+            let link_offsets = next::<u32>(order, node_len)?;
+            let backward_offsets = next::<u32>(link_offsets, node_len.checked_add(1)?)?;
+            let links = next::<u32>(backward_offsets, node_len)?;
+            let mids = next::<IdxLink>(links, link_len)?;
+            let secondary = next::<IdxNodeKey>(mids, link_len)?;
+            let contractions = next::<u32>(secondary, secondary_len)?;
+            let old_keys = next::<CHContraction>(contractions, contraction_len)?;
+            let end = next::<IdxNodeKey>(old_keys, renumber_len)?;
+            Some(Self {
+                order,
+                link_offsets,
+                backward_offsets,
+                links,
+                mids,
+                secondary,
+                contractions,
+                old_keys,
+                end,
+            })
+        };
+        layout().context("sections overflow")
+    }
+}
+
+/// memory-mapped binary contraction hierarchies file
+pub struct CHFile {
+    mmap: Arc<Mmap>,
+    layout: Layout,
+    node_len: usize,
+    link_len: usize,
+    secondary_len: usize,
+    contraction_len: usize,
+    renumber_len: usize,
+}
+
+impl CHFile {
+    /// write hierarchy to `path`. `old_keys` is original node of each node if renumbered, see
+    /// `Renumbering::old_keys`, or empty.
+    pub fn write<P>(
+        path: P,
+        order: &[u32],
+        links: &Csr,
+        mids: &[IdxNodeKey],
+        secondary: &[u32],
+        contractions: &[CHContraction],
+        old_keys: &[IdxNodeKey],
+    ) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        if order.len() != links.node_len() {
+            bail!("invalid links: {} != {}", links.node_len(), order.len());
+        }
+        if mids.len() != links.link_len() {
+            bail!("invalid mids: {} != {}", mids.len(), links.link_len());
+        }
+        if !secondary.is_empty() && secondary.len() != links.link_len() {
+            bail!(
+                "invalid secondary: {} != {}",
+                secondary.len(),
+                links.link_len()
+            );
+        }
+        if !old_keys.is_empty() && old_keys.len() != order.len() {
+            bail!("invalid old keys: {} != {}", old_keys.len(), order.len());
+        }
+
+        let order_bytes = unsafe { as_bytes(order) };
+        let offset_bytes = unsafe { as_bytes(links.offsets()) };
+        let backward_offset_bytes = unsafe { as_bytes(links.backward_offsets()) };
+        let link_bytes = unsafe { as_bytes(links.flat_links()) };
+        let mid_bytes = unsafe { as_bytes(mids) };
+        let secondary_bytes = unsafe { as_bytes(secondary) };
+        let contraction_bytes = unsafe { as_bytes(contractions) };
+        let old_key_bytes = unsafe { as_bytes(old_keys) };
+
+        let header = Header {
+            magic: MAGIC,
+            version: VERSION,
+            endian: ENDIAN_MARK,
+            node_len: order.len() as u64,
+            link_len: links.link_len() as u64,
+            secondary_len: secondary.len() as u64,
+            contraction_len: contractions.len() as u64,
+            renumber_len: old_keys.len() as u64,
+            checksum: checksum(&[
+                order_bytes,
+                offset_bytes,
+                backward_offset_bytes,
+                link_bytes,
+                mid_bytes,
+                secondary_bytes,
+                contraction_bytes,
+                old_key_bytes,
+            ]),
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(unsafe { as_bytes(std::slice::from_ref(&header)) })?;
+        for bytes in [
+            order_bytes,
+            offset_bytes,
+            backward_offset_bytes,
+            link_bytes,
+            mid_bytes,
+            secondary_bytes,
+            contraction_bytes,
+            old_key_bytes,
+        ]
+        .iter()
+        {
+            writer.write_all(bytes)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// open and validate given file. Offsets and nodes of links and contractions are always
+    /// validated, checksum is verified only if `verify` is set.
+    pub fn open<P>(path: P, verify: bool) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let mmap = unsafe { MmapOptions::new().map(&file)? };
+
+        if mmap.len() < HEADER_LEN {
+            bail!("invalid ch file: {:?}, too short", path);
+        }
+        let header = unsafe { std::ptr::read_unaligned(mmap.as_ptr() as *const Header) };
+        if header.magic != MAGIC {
+            bail!("invalid ch file: {:?}, bad magic", path);
+        }
+        if header.endian != ENDIAN_MARK {
+            bail!("invalid ch file: {:?}, endianness mismatch", path);
+        }
+        if header.version != VERSION {
+            bail!(
+                "invalid ch file: {:?}, unsupported version {}",
+                path,
+                header.version
+            );
+        }
+
+        let node_len = header.node_len as usize;
+        let link_len = header.link_len as usize;
+        let secondary_len = header.secondary_len as usize;
+        let contraction_len = header.contraction_len as usize;
+        let renumber_len = header.renumber_len as usize;
+        if secondary_len != 0 && secondary_len != link_len {
+            bail!("invalid ch file: {:?}, bad secondary metric", path);
+        }
+        if renumber_len != 0 && renumber_len != node_len {
+            bail!("invalid ch file: {:?}, bad renumbering", path);
+        }
+        let layout = Layout::new(
+            node_len,
+            link_len,
+            secondary_len,
+            contraction_len,
+            renumber_len,
+        )
+        .with_context(|| format!("invalid ch file: {:?}", path))?;
+        let expected_len = layout.end;
+        if mmap.len() != expected_len {
+            bail!(
+                "invalid ch file: {:?}, expected {} bytes, found {}",
+                path,
+                expected_len,
+                mmap.len()
+            );
+        }
+
+        let ch_file = Self {
+            mmap: Arc::new(mmap),
+            layout,
+            node_len,
+            link_len,
+            secondary_len,
+            contraction_len,
+            renumber_len,
+        };
+
+        let offsets = ch_file.link_offsets();
+        let backward_offsets = ch_file.backward_offsets();
+        if offsets[node_len] as usize != link_len
+            || offsets.windows(2).any(|w| w[0] > w[1])
+            || (0..node_len)
+                .any(|i| backward_offsets[i] < offsets[i] || backward_offsets[i] > offsets[i + 1])
+        {
+            bail!("invalid ch file: {:?}, bad link offsets", path);
+        }
+
+        // searches index arrays by nodes of links and contractions without further checks
+        let is_node = |key: &IdxNodeKey| key.index() < node_len;
+        let links = ch_file.flat_links();
+        if !links.iter().all(|l| is_node(&l.enode_idx))
+            || !(0..node_len).all(|i| {
+                let (start, mid, end) = (
+                    offsets[i] as usize,
+                    backward_offsets[i] as usize,
+                    offsets[i + 1] as usize,
+                );
+                links[start..mid]
+                    .iter()
+                    .all(|l| l.has_dir(IdxLinkDir::Forward))
+                    && links[mid..end]
+                        .iter()
+                        .all(|l| l.has_dir(IdxLinkDir::Backward))
+            })
+        {
+            bail!("invalid ch file: {:?}, bad links", path);
+        }
+        if !ch_file
+            .flat_mids()
+            .iter()
+            .all(|k| *k == IdxNodeKey::NONE || is_node(k))
+        {
+            bail!("invalid ch file: {:?}, bad middle nodes", path);
+        }
+        if !ch_file
+            .contractions()
+            .iter()
+            .all(|c| is_node(&c.snode_idx) && is_node(&c.enode_idx) && is_node(&c.mnode_idx))
+        {
+            bail!("invalid ch file: {:?}, bad contractions", path);
+        }
+
+        // renumbering should be a permutation, see `Renumbering::from_old_keys`
+        let mut seen = vec![false; renumber_len];
+        for key in ch_file.old_keys() {
+            match seen.get_mut(key.index()) {
+                Some(seen) if !*seen => *seen = true,
+                _ => bail!("invalid ch file: {:?}, bad renumbering", path),
+            }
+        }
+
+        if verify {
+            let sum = unsafe {
+                checksum(&[
+                    as_bytes(ch_file.order()),
+                    as_bytes(ch_file.link_offsets()),
+                    as_bytes(ch_file.backward_offsets()),
+                    as_bytes(ch_file.flat_links()),
+                    as_bytes(ch_file.flat_mids()),
+                    as_bytes(ch_file.flat_secondary()),
+                    as_bytes(ch_file.contractions()),
+                    as_bytes(ch_file.old_keys()),
+                ])
+            };
+            if sum != header.checksum {
+                bail!("invalid ch file: {:?}, checksum mismatch", path);
+            }
+        }
+
+        Ok(ch_file)
+    }
+
+    pub fn node_len(&self) -> usize {
+        self.node_len
+    }
+
+    fn slice<T>(&self, offset: usize, len: usize) -> &[T] {
+        let ptr = self.mmap[offset..].as_ptr() as *const T;
+        unsafe { std::slice::from_raw_parts(ptr, len) }
+    }
+
+    /// order of each node, see `CH::order`
+    pub fn order(&self) -> &[u32] {
+        self.slice(self.layout.order, self.node_len)
+    }
+
+    /// offsets of each node on `flat_links`, with `node_len + 1` items
+    pub fn link_offsets(&self) -> &[u32] {
+        self.slice(self.layout.link_offsets, self.node_len + 1)
+    }
+
+    /// offsets of backward links of each node on `flat_links`
+    pub fn backward_offsets(&self) -> &[u32] {
+        self.slice(self.layout.backward_offsets, self.node_len)
+    }
+
+    /// upward/downward links of all nodes, see `link_offsets`
+    pub fn flat_links(&self) -> &[IdxLink] {
+        self.slice(self.layout.links, self.link_len)
+    }
+
+    /// links as `Csr` borrowing the memory map
+    pub fn csr(&self) -> Csr {
+        Csr::from_sections(
+            Section::mapped(&self.mmap, self.layout.link_offsets, self.node_len + 1),
+            Section::mapped(&self.mmap, self.layout.backward_offsets, self.node_len),
+            Section::mapped(&self.mmap, self.layout.links, self.link_len),
+        )
+    }
+
+    /// copy links to per-node vectors
+    pub fn links(&self) -> Vec<Vec<IdxLink>> {
+        let flat_links = self.flat_links();
+        self.link_offsets()
+            .windows(2)
+            .map(|w| flat_links[w[0] as usize..w[1] as usize].to_vec())
+            .collect()
+    }
+
+    /// middle node of each link of `flat_links`
+    pub fn flat_mids(&self) -> &[IdxNodeKey] {
+        self.slice(self.layout.mids, self.link_len)
+    }
+
+    /// secondary metric of each link of `flat_links`, empty if there is no secondary metric
+    pub fn flat_secondary(&self) -> &[u32] {
+        self.slice(self.layout.secondary, self.secondary_len)
+    }
+
+    /// all contractions, sorted by `snode_idx`
+    pub fn contractions(&self) -> &[CHContraction] {
+        self.slice(self.layout.contractions, self.contraction_len)
+    }
+
+    /// original node of each node if the hierarchy is renumbered, otherwise empty
+    pub fn old_keys(&self) -> &[IdxNodeKey] {
+        self.slice(self.layout.old_keys, self.renumber_len)
+    }
+
+    pub(crate) fn order_section(&self) -> Section<u32> {
+        Section::mapped(&self.mmap, self.layout.order, self.node_len)
+    }
+
+    pub(crate) fn mids_section(&self) -> Section<IdxNodeKey> {
+        Section::mapped(&self.mmap, self.layout.mids, self.link_len)
+    }
+
+    pub(crate) fn secondary_section(&self) -> Section<u32> {
+        Section::mapped(&self.mmap, self.layout.secondary, self.secondary_len)
+    }
+
+    pub(crate) fn contractions_section(&self) -> Section<CHContraction> {
+        Section::mapped(&self.mmap, self.layout.contractions, self.contraction_len)
+    }
+}
+
+const _CHECK_HEADER: [u8; 64] = [0; HEADER_LEN];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn query_is_mapped() {
+        let graph = testutil::grid(6, 7);
+        let mut ch = CH::new(&graph);
+        ch.build();
+        let path = testutil::temp_dir("ch-file-mapped");
+        ch.write(&path).unwrap();
+
+        let query = CHQuery::from_file(&path).unwrap();
+        let hierarchy = query.hierarchy();
+        assert!(query.is_mapped());
+        assert_eq!(hierarchy.order, &ch.order[..]);
+        assert_eq!(hierarchy.all_contractions, &ch.all_contractions[..]);
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            assert_eq!(
+                query.search(src, dst).map(|r| r.1),
+                graph.search(src, dst).map(|r| r.1)
+            );
+        }
+
+        let mut file = std::fs::read(path.join(CH_FILE_NAME)).unwrap();
+        let last = file.len() - 1;
+        file[last] ^= 1;
+        std::fs::write(path.join(CH_FILE_NAME), file).unwrap();
+        assert!(CHQuery::from_file(&path).is_err());
+        // secondary metric of the last contraction is valid without checksum
+        assert!(CHQuery::open(&path, false).is_ok());
+    }
+
+    #[test]
+    fn open_rejects_bad_sections() {
+        let graph = testutil::grid(5, 2);
+        let mut ch = CH::new(&graph);
+        ch.build();
+        let path = testutil::temp_dir("ch-file-bad");
+        ch.write(&path).unwrap();
+        let file = std::fs::read(path.join(CH_FILE_NAME)).unwrap();
+        let ch_file = CHFile::open(path.join(CH_FILE_NAME), true).unwrap();
+        let layout = &ch_file.layout;
+        let node_len = graph.node_len as u32;
+
+        // position of the first shortcut, whose middle node is set
+        let shortcut = ch_file
+            .flat_mids()
+            .iter()
+            .position(|k| *k != IdxNodeKey::NONE)
+            .unwrap();
+        let u32_at = |offset: usize, v: u32| (offset, v.to_ne_bytes().to_vec());
+        for (name, (offset, bytes)) in [
+            ("bad links", u32_at(layout.links, node_len)),
+            ("bad links", u32_at(layout.links + 4, 3 << 30)),
+            (
+                "bad middle nodes",
+                u32_at(layout.mids + shortcut * 4, node_len),
+            ),
+            (
+                "bad contractions",
+                u32_at(layout.contractions + 8, node_len),
+            ),
+            (
+                "sections overflow",
+                (16, (u64::MAX / 4).to_ne_bytes().to_vec()),
+            ),
+        ] {
+            let mut file = file.clone();
+            file[offset..offset + bytes.len()].copy_from_slice(&bytes);
+            std::fs::write(path.join(CH_FILE_NAME), file).unwrap();
+            let err = CHQuery::open(&path, false).err().expect(name);
+            assert!(format!("{:#}", err).contains(name), "{:#}", err);
+        }
+    }
+}
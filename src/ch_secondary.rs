@@ -0,0 +1,184 @@
+use fnv::FnvHashMap;
+use rayon::prelude::*;
+
+use super::*;
+
+/// compute secondary metric of each link of hierarchy `links` with middle nodes `mids`, and
+/// of each contraction, from `secondary` of original `graph_links`. Returns empty if
+/// `secondary` is empty.
+///
+/// A shortcut carries sum of secondary metric of its two halves. Half `snode -> mnode` is the
+/// cheapest link between them, ties are broken by smaller middle node, the same link which is
+/// unpacked by query. Links are visited by ascending order of middle nodes, so halves are
+/// computed before shortcuts using them.
+pub(crate) fn carry_secondary(
+    graph_links: &Csr,
+    secondary: &[u32],
+    order: &[u32],
+    links: &Csr,
+    mids: &[IdxNodeKey],
+    all_contractions: &mut [CHContraction],
+) -> Vec<u32> {
+    if secondary.is_empty() {
+        all_contractions
+            .par_iter_mut()
+            .for_each(|c| c.secondary = 0);
+        return Vec::new();
+    }
+    assert_eq!(secondary.len(), graph_links.link_len());
+
+    // cheapest original link of each pair, ties are broken by smaller secondary metric
+    let mut originals = FnvHashMap::<(IdxNodeKey, IdxNodeKey), (u32, u32)>::default();
+    for i in 0..graph_links.node_len() {
+        let snode_idx = IdxNodeKey::new(i);
+        let r = graph_links.range(i);
+        for (link, s) in graph_links.flat_links()[r.clone()]
+            .iter()
+            .zip(secondary[r].iter())
+        {
+            if link.dir() != IdxLinkDir::Forward {
+                break;
+            }
+            let entry = originals
+                .entry((snode_idx, link.enode_idx))
+                .or_insert((u32::MAX, 0));
+            *entry = std::cmp::min(*entry, (link.cost(), *s));
+        }
+    }
+
+    // hierarchy links on original direction, as (snode_idx, enode_idx, position)
+    let mut ends = Vec::with_capacity(links.link_len());
+    for i in 0..links.node_len() {
+        let key = IdxNodeKey::new(i);
+        let r = links.range(i);
+        for (pos, link) in r.clone().zip(links.flat_links()[r].iter()) {
+            match link.dir() {
+                IdxLinkDir::Forward => ends.push((key, link.enode_idx, pos)),
+                IdxLinkDir::Backward => ends.push((link.enode_idx, key, pos)),
+            }
+        }
+    }
+    let mid_order = |mid: IdxNodeKey| {
+        if mid == IdxNodeKey::NONE {
+            0
+        } else {
+            order[mid.index()]
+        }
+    };
+    ends.par_sort_unstable_by_key(|&(_, _, pos)| (mid_order(mids[pos]), pos));
+
+    // cheapest link of each pair as (cost, middle node, secondary metric)
+    let mut best = FnvHashMap::<(IdxNodeKey, IdxNodeKey), (u32, IdxNodeKey, u32)>::default();
+    let half = |best: &FnvHashMap<_, (u32, IdxNodeKey, u32)>, from, to| match best.get(&(from, to))
+    {
+        Some(&(_, _, s)) => s,
+        None => panic!("missing link of contraction: {:?} -> {:?}", from, to),
+    };
+
+    let mut link_secondary = vec![0u32; links.link_len()];
+    for (snode_idx, enode_idx, pos) in ends.into_iter() {
+        let mid = mids[pos];
+        let s = if mid == IdxNodeKey::NONE {
+            match originals.get(&(snode_idx, enode_idx)) {
+                Some(&(_, s)) => s,
+                None => panic!("missing original link: {:?} -> {:?}", snode_idx, enode_idx),
+            }
+        } else {
+            half(&best, snode_idx, mid).saturating_add(half(&best, mid, enode_idx))
+        };
+        link_secondary[pos] = s;
+
+        let item = (links.flat_links()[pos].cost(), mid, s);
+        let entry = best
+            .entry((snode_idx, enode_idx))
+            .or_insert((u32::MAX, IdxNodeKey::NONE, 0));
+        *entry = std::cmp::min(*entry, item);
+    }
+
+    for c in all_contractions.iter_mut() {
+        let first = half(&best, c.snode_idx, c.mnode_idx);
+        let second = half(&best, c.mnode_idx, c.enode_idx);
+        c.secondary = first.saturating_add(second);
+    }
+    link_secondary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn secondary_of_dijkstra_path() {
+        // costs hardly tie, so the path of CH is the one of dijkstra, see below for ties
+        let graph = testutil::road_grid(7, 11);
+        let mut ch = CH::new(&graph);
+        ch.build();
+        let query = ch.to_query();
+
+        let mut ctx = QueryContext::new(graph.node_len);
+        let mut found = 0;
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            let expected = graph
+                .search(src, dst)
+                .map(|(path, cost)| (cost, path_secondary(&graph, &path).unwrap()));
+            let actual = ch
+                .search_metrics(&mut ctx, src, dst)
+                .map(|(_, cost, secondary)| (cost, secondary));
+            assert_eq!(actual, expected, "{:?} -> {:?}", src, dst);
+            let actual = query
+                .search_metrics(&mut ctx, src, dst)
+                .map(|(_, cost, secondary)| (cost, secondary));
+            assert_eq!(actual, expected, "{:?} -> {:?}", src, dst);
+            found += expected.is_some() as usize;
+        }
+        assert!(found > graph.node_len);
+    }
+
+    #[test]
+    fn secondary_of_tied_shortcuts() {
+        // 0 -> 1 -> 3 and 0 -> 2 -> 3 cost the same with different lengths, and 1 and 2 are
+        // contracted first with a shortcut each, so both shortcuts 0 -> 3 are kept
+        for (via_1, via_2) in [(10, 3), (3, 10)] {
+            let graph = testutil::graph_with_secondary(
+                4,
+                &[
+                    (0, 1, 1, via_1),
+                    (1, 3, 1, via_1),
+                    (0, 2, 1, via_2),
+                    (2, 3, 1, via_2),
+                ],
+            );
+            let mut ch = CH::new(&graph);
+            ch.order = vec![3, 1, 2, 4].into();
+            let contraction = |mid| CHContraction {
+                snode_idx: IdxNodeKey::new(0),
+                enode_idx: IdxNodeKey::new(3),
+                mnode_idx: IdxNodeKey::new(mid),
+                length: 2,
+                secondary: 0,
+            };
+            let mut all_contractions = vec![contraction(2), contraction(1)];
+            sort_contractions(&mut all_contractions);
+            ch.all_contractions = all_contractions.into();
+            let (hierarchy_links, mids) =
+                query_links(&graph.idx_links, &ch.order, &ch.all_contractions);
+            ch.hierarchy_links = hierarchy_links;
+            ch.mids = mids.into();
+            ch.carry_secondary();
+
+            let mut ctx = QueryContext::new(graph.node_len);
+            let (src, dst) = (IdxNodeKey::new(0), IdxNodeKey::new(3));
+            let (path, cost, secondary) = ch.search_metrics(&mut ctx, src, dst).unwrap();
+            assert_eq!(cost, 2);
+            assert_eq!(path.len(), 3);
+            assert_eq!(Some(secondary), path_secondary(&graph, &path));
+            let lengths = ch
+                .all_contractions
+                .iter()
+                .map(|c| (c.mnode_idx.index(), c.secondary))
+                .collect::<Vec<_>>();
+            assert_eq!(lengths, vec![(1, via_1 * 2), (2, via_2 * 2)]);
+        }
+    }
+}
@@ -0,0 +1,768 @@
+use anyhow::{bail, Result};
+use fnv::{FnvHashMap, FnvHashSet};
+use ordslice::Ext;
+
+use super::*;
+
+/// marker for original links on `Edge::contraction`
+const ORIGINAL: u32 = u32::MAX;
+
+/// edge of hierarchy, which is either an original link or a contraction
+#[derive(Clone, Copy)]
+struct Edge {
+    key: IdxNodeKey,
+    /// cost of original link, unused for contraction
+    cost: u32,
+    /// cost of original link before update, unused for contraction
+    old_cost: u32,
+    /// index of contraction, `ORIGINAL` for original link
+    contraction: u32,
+}
+
+/// re-contracts affected nodes in existing order. A node is affected if costs of its neighbors
+/// are changed, or if its witness search may have used a link which became more expensive.
+/// Existing contractions are never removed, as a contraction with the cost of its path is always
+/// valid, so only changed costs and new contractions are propagated.
+struct Updater<'a> {
+    order: &'a [u32],
+    /// limits of witness search, same as the ones used while building
+    hop_limit: u16,
+    heap_limit: usize,
+
+    contractions: Vec<CHContraction>,
+    /// lengths of contractions before update, `u32::MAX` for new contractions
+    old_lengths: Vec<u32>,
+    contractions_by_mid: FnvHashMap<IdxNodeKey, Vec<u32>>,
+
+    /// all edges, including edges to lower-ordered nodes. Edges are sorted by order of the other
+    /// node in descending order, see `higher_edges`.
+    out_edges: DynCsr<Edge>,
+    in_edges: DynCsr<Edge>,
+
+    queue: BinaryHeap<Reverse<(u32, IdxNodeKey)>>,
+    queued: FnvHashSet<IdxNodeKey>,
+}
+
+impl<'a> Updater<'a> {
+    fn new(
+        config: &CHBuildConfig,
+        order: &'a [u32],
+        links: &Csr,
+        all_contractions: &[CHContraction],
+    ) -> Self {
+        let node_len = order.len();
+        let mut out_edges = vec![Vec::new(); node_len];
+        let mut in_edges = vec![Vec::new(); node_len];
+
+        for (key, link) in forward_links(links) {
+            let edge = Edge {
+                key: link.enode_idx,
+                cost: link.cost(),
+                old_cost: link.cost(),
+                contraction: ORIGINAL,
+            };
+            out_edges[key.index()].push(edge);
+            in_edges[link.enode_idx.index()].push(Edge { key, ..edge });
+        }
+        for edges in out_edges.iter_mut().chain(in_edges.iter_mut()) {
+            edges.sort_by_key(|e| Reverse(order[e.key.index()]));
+        }
+
+        let mut updater = Self {
+            order,
+            hop_limit: config.max_hop_limit(),
+            heap_limit: config.heap_limit,
+            contractions: Vec::with_capacity(all_contractions.len()),
+            old_lengths: Vec::with_capacity(all_contractions.len()),
+            contractions_by_mid: Default::default(),
+            out_edges: out_edges.into(),
+            in_edges: in_edges.into(),
+            queue: Default::default(),
+            queued: Default::default(),
+        };
+
+        // `CH::build` may add the same contraction more than once
+        let mut all_contractions = all_contractions.to_vec();
+        all_contractions
+            .sort_unstable_by_key(|c| (c.mnode_idx, c.snode_idx, c.enode_idx, c.length));
+        all_contractions.dedup_by_key(|c| (c.mnode_idx, c.snode_idx, c.enode_idx));
+        for c in all_contractions.into_iter() {
+            updater.add_contraction(c, c.length);
+        }
+        updater
+    }
+
+    /// set cost before update of original link from `snode_idx` to `enode_idx`
+    fn set_old_cost(
+        &mut self,
+        snode_idx: IdxNodeKey,
+        enode_idx: IdxNodeKey,
+        cost: u32,
+        old_cost: u32,
+    ) {
+        let matches = |e: &&mut Edge, key: IdxNodeKey| {
+            e.key == key && e.contraction == ORIGINAL && e.cost == cost && e.old_cost == cost
+        };
+        if let Some(edge) = self
+            .out_edges
+            .links_mut(snode_idx.index())
+            .iter_mut()
+            .find(|e| matches(e, enode_idx))
+        {
+            edge.old_cost = old_cost;
+        }
+        if let Some(edge) = self
+            .in_edges
+            .links_mut(enode_idx.index())
+            .iter_mut()
+            .find(|e| matches(e, snode_idx))
+        {
+            edge.old_cost = old_cost;
+        }
+    }
+
+    fn add_contraction(&mut self, c: CHContraction, old_length: u32) {
+        let idx = self.contractions.len() as u32;
+        let edge = Edge {
+            key: c.enode_idx,
+            cost: 0,
+            old_cost: 0,
+            contraction: idx,
+        };
+
+        let order = self.order;
+        let insert = |edges: &mut DynCsr<Edge>, idx: usize, edge: Edge| {
+            let o = order[edge.key.index()];
+            let pos = edges
+                .links(idx)
+                .partition_point(|e| order[e.key.index()] >= o);
+            edges.insert(idx, pos, edge);
+        };
+        insert(&mut self.out_edges, c.snode_idx.index(), edge);
+        insert(
+            &mut self.in_edges,
+            c.enode_idx.index(),
+            Edge {
+                key: c.snode_idx,
+                ..edge
+            },
+        );
+
+        self.contractions_by_mid
+            .entry(c.mnode_idx)
+            .or_default()
+            .push(idx);
+        self.contractions.push(c);
+        self.old_lengths.push(old_length);
+    }
+
+    /// edges to nodes with higher order than `order`
+    fn higher_edges<'b>(&self, edges: &'b [Edge], order: u32) -> &'b [Edge] {
+        let len = edges.partition_point(|e| self.order[e.key.index()] > order);
+        &edges[..len]
+    }
+
+    /// cost of `edge` in remaining graph when a node of `order` is contracted
+    fn edge_cost(&self, edge: &Edge, order: u32) -> Option<u32> {
+        if edge.contraction == ORIGINAL {
+            return Some(edge.cost);
+        }
+        let c = &self.contractions[edge.contraction as usize];
+        if self.order[c.mnode_idx.index()] >= order {
+            return None;
+        }
+        Some(c.length)
+    }
+
+    /// cost of `edge` before update, `None` if `edge` is added while update
+    fn old_cost(&self, edge: &Edge) -> Option<u32> {
+        if edge.contraction == ORIGINAL {
+            return Some(edge.old_cost);
+        }
+        match self.old_lengths[edge.contraction as usize] {
+            u32::MAX => None,
+            length => Some(length),
+        }
+    }
+
+    fn push(&mut self, key: IdxNodeKey) {
+        if self.queued.insert(key) {
+            self.queue.push(Reverse((self.order[key.index()], key)));
+        }
+    }
+
+    /// neighbors with higher order and minimum costs
+    fn neighbors(&self, key: IdxNodeKey, edges: &[Edge]) -> Vec<(IdxNodeKey, u32)> {
+        let order = self.order[key.index()];
+        let mut neighbors = self
+            .higher_edges(edges, order)
+            .iter()
+            .filter_map(|e| self.edge_cost(e, order).map(|cost| (e.key, cost)))
+            .collect::<Vec<_>>();
+        neighbors.sort_unstable();
+        neighbors.dedup_by_key(|(key, _)| *key);
+        neighbors
+    }
+
+    /// contract `key` on remaining graph, which consists of nodes with higher order. Returns
+    /// contractions in `prev`, which is a map from `(snode_idx, enode_idx)`, with updated costs,
+    /// and new contractions without witness path.
+    fn contract(
+        &self,
+        key: IdxNodeKey,
+        prev: &FnvHashMap<(IdxNodeKey, IdxNodeKey), u32>,
+    ) -> Vec<CHContraction> {
+        let order = self.order[key.index()];
+        let idx = key.index();
+        let ins = self.neighbors(key, self.in_edges.links(idx));
+        let outs = self.neighbors(key, self.out_edges.links(idx));
+
+        let mut contractions = Vec::new();
+        let max_cost = match outs.iter().map(|(_, cost)| *cost).max() {
+            Some(cost) => cost,
+            None => return contractions,
+        };
+
+        for &(src, src_cost) in ins.iter() {
+            let mut search = dijkstra::Search::with_capacity(128);
+            let needs_witness = outs
+                .iter()
+                .any(|(dst, _)| *dst != src && !prev.contains_key(&(src, *dst)));
+            if needs_witness {
+                search.heap_limit = self.heap_limit;
+                search.hop_limit = self.hop_limit;
+                search.cost_limit = src_cost.saturating_add(max_cost).saturating_add(1);
+                search.add_src(src);
+
+                while let Some(entry) = search.next() {
+                    let edges = self.out_edges.links(entry.key.index());
+                    for edge in self.higher_edges(edges, order).iter() {
+                        if edge.key == key {
+                            continue;
+                        }
+                        if let Some(cost) = self.edge_cost(edge, order) {
+                            search.update(&entry, edge.key, entry.cost.saturating_add(cost));
+                        }
+                    }
+                }
+            }
+
+            for &(dst, dst_cost) in outs.iter() {
+                if dst == src {
+                    continue;
+                }
+                let via_cost = src_cost + dst_cost;
+                if !prev.contains_key(&(src, dst)) {
+                    let cost = search.get_cost(&dst).map(|c| c.cost).unwrap_or(u32::MAX);
+                    if cost <= via_cost {
+                        continue;
+                    }
+                }
+                contractions.push(CHContraction {
+                    snode_idx: src,
+                    enode_idx: dst,
+                    mnode_idx: key,
+                    length: via_cost,
+                    secondary: 0,
+                });
+            }
+        }
+        contractions
+    }
+
+    /// minimum costs before update from `src` within `hop_limit - 1` hops, on nodes with
+    /// higher order than `min_order`.
+    fn old_costs(
+        &self,
+        src: IdxNodeKey,
+        min_order: u32,
+        edges: &DynCsr<Edge>,
+    ) -> FnvHashMap<IdxNodeKey, u32> {
+        let mut costs = FnvHashMap::default();
+        costs.insert(src, 0u32);
+        let mut frontier = vec![src];
+        for _ in 1..self.hop_limit {
+            let mut next = Vec::new();
+            for key in frontier.into_iter() {
+                let cost = costs[&key];
+                for edge in self
+                    .higher_edges(edges.links(key.index()), min_order)
+                    .iter()
+                {
+                    let next_cost = match self.old_cost(edge) {
+                        Some(c) => cost.saturating_add(c),
+                        None => continue,
+                    };
+                    match costs.get(&edge.key) {
+                        Some(prev_cost) if *prev_cost <= next_cost => continue,
+                        _ => {}
+                    }
+                    costs.insert(edge.key, next_cost);
+                    next.push(edge.key);
+                }
+            }
+            next.sort_unstable();
+            next.dedup();
+            frontier = next;
+        }
+        costs
+    }
+
+    /// `cost(a -> c) - cost(a -> .. -> src)` before update for each edge `a -> c`, where `a` is
+    /// reachable backward from `src` within `hop_limit - 1` hops.
+    fn backward_slacks(
+        &self,
+        src: IdxNodeKey,
+        min_order: u32,
+    ) -> FnvHashMap<IdxNodeKey, Vec<(IdxNodeKey, i64)>> {
+        let mut slacks: FnvHashMap<_, Vec<_>> = Default::default();
+        for (key, back_cost) in self.old_costs(src, min_order, &self.in_edges) {
+            for edge in self.higher_edges(self.out_edges.links(key.index()), min_order) {
+                if let Some(cost) = self.old_cost(edge) {
+                    let slack = cost as i64 - back_cost as i64;
+                    slacks.entry(edge.key).or_default().push((key, slack));
+                }
+            }
+        }
+        slacks
+    }
+
+    /// costs before update from `dst` within `hop_limit - 1` hops, and max of
+    /// `cost(c -> b) - cost(dst -> .. -> b)` for each `c`.
+    fn forward_slacks(
+        &self,
+        dst: IdxNodeKey,
+        min_order: u32,
+    ) -> (FnvHashMap<IdxNodeKey, u32>, FnvHashMap<IdxNodeKey, i64>) {
+        let costs = self.old_costs(dst, min_order, &self.out_edges);
+        let mut slacks: FnvHashMap<_, i64> = Default::default();
+        for (key, forward_cost) in costs.iter() {
+            for edge in self.higher_edges(self.in_edges.links(key.index()), min_order) {
+                if let Some(cost) = self.old_cost(edge) {
+                    let slack = cost as i64 - *forward_cost as i64;
+                    let v = slacks.entry(edge.key).or_insert(i64::MIN);
+                    *v = std::cmp::max(*v, slack);
+                }
+            }
+        }
+        (costs, slacks)
+    }
+
+    /// push nodes higher than `min_order`, which may have used one of `increases`, an edge from
+    /// `snode_idx` to `enode_idx` with cost before update, as a witness while building. A node `c`
+    /// may have used the edge if there is a path `a -> .. -> snode_idx -> enode_idx -> .. -> b` not
+    /// longer than `a -> c -> b` with costs before update. It's conservative, as witness paths are
+    /// not recorded while building.
+    fn push_witness_users(&mut self, increases: &[(IdxNodeKey, IdxNodeKey, u32)], min_order: u32) {
+        let mut backwards = FnvHashMap::default();
+        let mut forwards = FnvHashMap::default();
+        let mut keys = Vec::new();
+
+        for &(snode_idx, enode_idx, old_cost) in increases.iter() {
+            let backward = backwards
+                .entry(snode_idx)
+                .or_insert_with(|| self.backward_slacks(snode_idx, min_order));
+            let (forward_costs, forward) = forwards
+                .entry(enode_idx)
+                .or_insert_with(|| self.forward_slacks(enode_idx, min_order));
+
+            // `c` is contracted before all nodes on the witness path
+            let max_order =
+                std::cmp::min(self.order[snode_idx.index()], self.order[enode_idx.index()]);
+            for (key, back_slacks) in backward.iter() {
+                if self.order[key.index()] >= max_order {
+                    continue;
+                }
+                let forward_slack = match forward.get(key) {
+                    Some(slack) => *slack,
+                    None => continue,
+                };
+                let back_slack = back_slacks.iter().map(|(_, slack)| *slack).max().unwrap();
+                if back_slack + forward_slack >= old_cost as i64 {
+                    keys.push(*key);
+                }
+            }
+
+            // `CH::build` also removes redundant edges with witness search, so lower end of the
+            // edge which may have been removed should be contracted again.
+            for (key, forward_cost) in forward_costs.iter() {
+                for (from, slack) in backward.get(key).into_iter().flatten() {
+                    if slack - *forward_cost as i64 >= old_cost as i64 {
+                        keys.push(std::cmp::min_by_key(*from, *key, |k| self.order[k.index()]));
+                    }
+                }
+            }
+        }
+
+        for key in keys {
+            self.push(key);
+        }
+    }
+
+    /// re-contract queued nodes in order, returns number of contracted nodes
+    fn run(&mut self) -> usize {
+        let mut count = 0;
+        while let Some(Reverse((order, key))) = self.queue.pop() {
+            count += 1;
+
+            let prev = self
+                .contractions_by_mid
+                .get(&key)
+                .map(|v| {
+                    v.iter()
+                        .map(|idx| {
+                            let c = &self.contractions[*idx as usize];
+                            ((c.snode_idx, c.enode_idx), *idx)
+                        })
+                        .collect::<FnvHashMap<_, _>>()
+                })
+                .unwrap_or_default();
+
+            // (snode, enode, cost before update if increased)
+            let mut changes = Vec::new();
+            for c in self.contract(key, &prev).into_iter() {
+                match prev.get(&(c.snode_idx, c.enode_idx)) {
+                    Some(idx) => {
+                        let idx = *idx as usize;
+                        if self.contractions[idx].length == c.length {
+                            continue;
+                        }
+                        let increased = match self.old_lengths[idx] {
+                            old_length if old_length != u32::MAX && c.length > old_length => {
+                                Some(old_length)
+                            }
+                            _ => None,
+                        };
+                        changes.push((c.snode_idx, c.enode_idx, increased));
+                        self.contractions[idx].length = c.length;
+                    }
+                    None => {
+                        changes.push((c.snode_idx, c.enode_idx, None));
+                        self.add_contraction(c, u32::MAX);
+                    }
+                }
+            }
+
+            let mut increases = Vec::new();
+            for (snode_idx, enode_idx, increased) in changes.into_iter() {
+                self.push(snode_idx);
+                self.push(enode_idx);
+                if let Some(old_cost) = increased {
+                    increases.push((snode_idx, enode_idx, old_cost));
+                }
+            }
+            self.push_witness_users(&increases, order);
+        }
+        count
+    }
+
+    /// fails if cost of a contraction exceeds `MAX_COST`
+    fn into_contractions(self) -> Result<Vec<CHContraction>> {
+        let mut all_contractions = self.contractions;
+        if let Some(c) = all_contractions.iter().find(|c| c.length > MAX_COST) {
+            bail!(
+                "cost overflow: contraction {:?} -> {:?} via {:?} costs {}, exceeds {}",
+                c.snode_idx,
+                c.enode_idx,
+                c.mnode_idx,
+                c.length,
+                MAX_COST
+            );
+        }
+        all_contractions.sort_unstable_by_key(|c| (c.snode_idx, c.enode_idx, c.mnode_idx));
+        Ok(all_contractions)
+    }
+}
+
+impl<'a> CH<'a> {
+    /// update costs of original links, where each update is a pair of link index (see
+    /// `Graph::forward_links`) and new cost. Only nodes affected by updates are contracted again
+    /// with existing order. Returns number of re-contracted nodes. Fails if a cost exceeds
+    /// `MAX_COST`, leaving hierarchy invalid if a contraction does, so it should be rebuilt.
+    ///
+    /// Witness searches use hop and heap limits of `CH::config`, i.e. the ones of the build.
+    ///
+    /// Borrowed `Graph` is not modified and keeps costs before update, so compare against
+    /// `CH::to_graph` instead, e.g. for `verify`.
+    pub fn update_costs(&mut self, updates: &[(usize, u32)]) -> Result<usize> {
+        let sw = Timer::new();
+
+        // link index of first forward link for each node
+        let node_len = self.links.node_len();
+        let mut offsets = Vec::with_capacity(node_len + 1);
+        offsets.push(0);
+        for i in 0..node_len {
+            let count = self.links.dir_links(i, IdxLinkDir::Forward).len();
+            offsets.push(offsets.last().unwrap() + count);
+        }
+        let link_len = *offsets.last().unwrap();
+
+        // the last update wins if a link is updated more than once
+        let mut costs = FnvHashMap::default();
+        for &(link_idx, cost) in updates.iter() {
+            if link_idx >= link_len {
+                bail!("invalid link index: {}", link_idx);
+            }
+            if cost > MAX_COST {
+                bail!(
+                    "cost overflow: link {} costs {}, exceeds {}",
+                    link_idx,
+                    cost,
+                    MAX_COST
+                );
+            }
+            costs.insert(link_idx, cost);
+        }
+        let mut costs = costs.into_iter().collect::<Vec<_>>();
+        costs.sort_unstable();
+
+        // (snode, enode, cost, cost before update)
+        let mut changes = Vec::new();
+        let links = self.links.to_mut();
+        for (link_idx, cost) in costs.into_iter() {
+            let snode_idx = offsets.upper_bound(&link_idx) - 1;
+            let pos = link_idx - offsets[snode_idx];
+
+            // forward links come first
+            let link = &mut links.links_mut(snode_idx)[pos];
+            let old_cost = link.cost();
+            if old_cost == cost {
+                continue;
+            }
+            link.set_cost(cost);
+
+            let snode_idx = IdxNodeKey::new(snode_idx);
+            let enode_idx = link.enode_idx;
+            if let Some(link_rev) = links.links_mut(enode_idx.index()).iter_mut().find(|l| {
+                l.dir() == IdxLinkDir::Backward && l.enode_idx == snode_idx && l.cost() == old_cost
+            }) {
+                link_rev.set_cost(cost);
+            }
+            changes.push((snode_idx, enode_idx, cost, old_cost));
+        }
+        if changes.is_empty() {
+            return Ok(0);
+        }
+
+        let mut updater = Updater::new(
+            &self.config,
+            &self.order,
+            &self.links,
+            &self.all_contractions,
+        );
+        for &(snode_idx, enode_idx, cost, old_cost) in changes.iter() {
+            updater.set_old_cost(snode_idx, enode_idx, cost, old_cost);
+        }
+        let mut increases = Vec::new();
+        for (snode_idx, enode_idx, cost, old_cost) in changes.into_iter() {
+            updater.push(snode_idx);
+            updater.push(enode_idx);
+            if cost > old_cost {
+                increases.push((snode_idx, enode_idx, old_cost));
+            }
+        }
+        updater.push_witness_users(&increases, 0);
+        let count = updater.run();
+
+        self.all_contractions = updater.into_contractions()?.into();
+        let (hierarchy_links, mids) = query_links(&self.links, &self.order, &self.all_contractions);
+        self.hierarchy_links = hierarchy_links;
+        self.mids = mids.into();
+        self.carry_secondary();
+
+        eprintln!(
+            "updating costs took: {}, updates={}, contracted={}, contractions={}",
+            sw.took(),
+            updates.len(),
+            count,
+            self.all_contractions.len()
+        );
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    /// link index of forward link `snode_idx -> enode_idx`, see `Graph::forward_links`
+    fn link_index(graph: &Graph, snode_idx: IdxNodeKey, enode_idx: IdxNodeKey) -> Option<usize> {
+        let before = (0..snode_idx.index())
+            .map(|i| graph.idx_links.dir_links(i, IdxLinkDir::Forward).len())
+            .sum::<usize>();
+        graph
+            .idx_links
+            .dir_links(snode_idx.index(), IdxLinkDir::Forward)
+            .iter()
+            .position(|l| l.enode_idx == enode_idx)
+            .map(|pos| before + pos)
+    }
+
+    fn assert_same_costs(ch: &CH, graph: &Graph) {
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            assert_eq!(
+                ch.search(src, dst).map(|r| r.1),
+                graph.search(src, dst).map(|r| r.1),
+                "{:?} -> {:?}",
+                src,
+                dst
+            );
+        }
+    }
+
+    #[test]
+    fn update_matches_dijkstra() {
+        let graph = testutil::grid(6, 3);
+        let mut ch = CH::new(&graph);
+        ch.build();
+
+        // halves of shortcuts which are original links
+        let mut halves = Vec::new();
+        for c in ch.all_contractions.iter() {
+            halves.extend(link_index(&graph, c.snode_idx, c.mnode_idx));
+            halves.extend(link_index(&graph, c.mnode_idx, c.enode_idx));
+        }
+        halves.sort_unstable();
+        halves.dedup();
+        assert!(!halves.is_empty());
+
+        let link_len = graph.forward_links().count();
+        let costs = graph
+            .forward_links()
+            .map(|l| l.1.cost())
+            .collect::<Vec<_>>();
+        let rounds: Vec<Vec<(usize, u32)>> = vec![
+            // increase halves of shortcuts
+            halves
+                .iter()
+                .step_by(3)
+                .map(|&i| (i, costs[i] * 5))
+                .collect(),
+            // decrease halves of shortcuts and other links
+            halves
+                .iter()
+                .skip(1)
+                .step_by(4)
+                .map(|&i| (i, 1))
+                .chain((0..link_len).step_by(7).map(|i| (i, costs[i] / 2)))
+                .collect(),
+            // mixed, including zero cost and revert of earlier updates
+            (0..link_len)
+                .step_by(5)
+                .map(|i| (i, if i % 2 == 0 { costs[i] } else { costs[i] * 3 }))
+                .chain(std::iter::once((halves[0], 0)))
+                .collect(),
+        ];
+        for updates in rounds {
+            let count = ch.update_costs(&updates).unwrap();
+            assert!(count > 0);
+            let updated = ch.to_graph();
+            let (idx, cost) = *updates.last().unwrap();
+            assert_eq!(updated.forward_links().nth(idx).unwrap().1.cost(), cost);
+            assert_same_costs(&ch, &updated);
+        }
+
+        // original graph is not modified
+        assert_eq!(
+            graph
+                .forward_links()
+                .map(|l| l.1.cost())
+                .collect::<Vec<_>>(),
+            costs
+        );
+    }
+
+    #[test]
+    fn update_when_witness_disappears() {
+        // two paths of same cost from 0 to 2, via 1 and via 3. Tails of 0 and 2 make them
+        // expensive to contract.
+        let graph = testutil::graph(
+            10,
+            &[
+                (0, 1, 1),
+                (1, 2, 1),
+                (0, 3, 1),
+                (3, 2, 1),
+                (4, 0, 1),
+                (6, 0, 1),
+                (7, 0, 1),
+                (2, 5, 1),
+                (2, 8, 1),
+                (2, 9, 1),
+            ],
+        );
+        let mut ch = CH::new(&graph);
+        ch.build();
+
+        // u -> v -> w where `v` is lower than `u` and `w`, but the shortcut is skipped as
+        // there is a witness
+        let has_shortcut = |ch: &CH, (u, v, w): (IdxNodeKey, IdxNodeKey, IdxNodeKey)| {
+            ch.all_contractions
+                .iter()
+                .any(|c| c.snode_idx == u && c.mnode_idx == v && c.enode_idx == w)
+        };
+        let order = ch.order();
+        let skipped = graph
+            .forward_links()
+            .flat_map(|(u, l1)| {
+                let v = l1.enode_idx;
+                graph
+                    .idx_links
+                    .dir_links(v.index(), IdxLinkDir::Forward)
+                    .iter()
+                    .map(move |l2| (u, v, l2.enode_idx))
+            })
+            .filter(|&(u, v, w)| {
+                u != w && order[v.index()] < order[u.index()] && order[v.index()] < order[w.index()]
+            })
+            .find(|t| !has_shortcut(&ch, *t))
+            .expect("no skipped shortcut");
+        let (u, v, w) = skipped;
+
+        // other links from `u` become expensive, so the witness disappears
+        let updates = graph
+            .forward_links()
+            .enumerate()
+            .filter(|(_, (snode_idx, l))| *snode_idx == u && l.enode_idx != v)
+            .map(|(i, _)| (i, 50))
+            .collect::<Vec<_>>();
+        assert!(!updates.is_empty());
+        assert!(ch.update_costs(&updates).unwrap() > 0);
+        assert!(has_shortcut(&ch, skipped));
+        assert_eq!(ch.search(u, w).map(|r| r.1), Some(2));
+        assert_same_costs(&ch, &ch.to_graph());
+
+        // then the shortcut itself becomes expensive
+        let idx = link_index(&graph, u, v).unwrap();
+        ch.update_costs(&[(idx, 60)]).unwrap();
+        assert_eq!(ch.search(u, w).map(|r| r.1), Some(51));
+        assert_same_costs(&ch, &ch.to_graph());
+    }
+
+    #[test]
+    fn update_uses_config_of_build() {
+        let graph = testutil::grid(6, 5);
+        let config = CHBuildConfig {
+            hop_steppings: vec![(2., 1)],
+            heap_limit: 16,
+            ..Default::default()
+        };
+        let mut ch = CH::new(&graph);
+        ch.build_with_config(config.clone());
+        let path = testutil::temp_dir("update-config");
+        ch.write(&path).unwrap();
+
+        let mut loaded = CH::from_file(&graph, &path).unwrap();
+        assert_eq!(loaded.config(), &config);
+
+        let updates = (0..graph.forward_links().count())
+            .step_by(4)
+            .map(|i| (i, 300))
+            .collect::<Vec<_>>();
+        ch.update_costs(&updates).unwrap();
+        loaded.update_costs(&updates).unwrap();
+        assert!(loaded.is_identical(&ch));
+        assert_same_costs(&loaded, &loaded.to_graph());
+    }
+}
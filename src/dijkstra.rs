@@ -1,11 +1,8 @@
-use std;
 use std::cmp::*;
 use std::collections::hash_map::*;
 use std::collections::BinaryHeap;
 use std::hash::Hash;
 
-use fnv;
-
 type Map<K, V> = fnv::FnvHashMap<K, V>;
 
 // for dijkstra search
@@ -23,7 +20,7 @@ impl<K> PartialEq for HeapEntry<K> {
 impl<K> Eq for HeapEntry<K> {}
 impl<K> PartialOrd for HeapEntry<K> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        other.cost.partial_cmp(&self.cost)
+        Some(self.cmp(other))
     }
 }
 impl<K> Ord for HeapEntry<K> {
@@ -32,18 +29,165 @@ impl<K> Ord for HeapEntry<K> {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct Cost<K> {
     pub prev_key: K,
     pub cost: u32,
     pub visited: bool,
 }
 
-pub struct Search<K>
+/// storage of costs of `Search`
+pub trait CostMap<K> {
+    fn get(&self, k: &K) -> Option<&Cost<K>>;
+    fn get_mut(&mut self, k: &K) -> Option<&mut Cost<K>>;
+    fn insert(&mut self, k: K, cost: Cost<K>);
+    /// set `cost` if `k` is not visited yet and `cost` is lower than current one
+    fn relax(&mut self, k: K, cost: Cost<K>) -> bool;
+    fn len(&self) -> usize;
+    fn clear(&mut self);
+}
+
+impl<K> CostMap<K> for Map<K, Cost<K>>
 where
     K: Hash + Eq,
 {
+    #[inline]
+    fn get(&self, k: &K) -> Option<&Cost<K>> {
+        HashMap::get(self, k)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, k: &K) -> Option<&mut Cost<K>> {
+        HashMap::get_mut(self, k)
+    }
+
+    #[inline]
+    fn insert(&mut self, k: K, cost: Cost<K>) {
+        HashMap::insert(self, k, cost);
+    }
+
+    #[inline]
+    fn relax(&mut self, k: K, cost: Cost<K>) -> bool {
+        match self.entry(k) {
+            Entry::Occupied(mut o) => {
+                let m = o.get_mut();
+                if m.visited || m.cost <= cost.cost {
+                    return false;
+                }
+                *m = cost;
+            }
+            Entry::Vacant(o) => {
+                o.insert(cost);
+            }
+        }
+        true
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self)
+    }
+}
+
+/// key of dense nodes `0..node_len`, which can be stored in flat arrays, see `ArrayCosts`
+pub trait Index: Copy {
+    /// placeholder key of entries which are not set
+    const NONE: Self;
+    fn index(&self) -> usize;
+}
+
+/// costs in flat arrays indexed by `Index`. Entries written before the last `clear` have older
+/// generation and are treated as absent, so `clear` does not touch the arrays.
+pub struct ArrayCosts<K> {
+    costs: Vec<Cost<K>>,
+    generations: Vec<u32>,
+    generation: u32,
+    len: usize,
+}
+
+impl<K: Index> ArrayCosts<K> {
+    pub fn new(node_len: usize) -> Self {
+        let empty = Cost {
+            prev_key: K::NONE,
+            cost: u32::MAX,
+            visited: false,
+        };
+        Self {
+            costs: vec![empty; node_len],
+            generations: vec![0; node_len],
+            generation: 1,
+            len: 0,
+        }
+    }
+
+    pub fn node_len(&self) -> usize {
+        self.costs.len()
+    }
+}
+
+impl<K: Index> CostMap<K> for ArrayCosts<K> {
+    #[inline]
+    fn get(&self, k: &K) -> Option<&Cost<K>> {
+        let idx = k.index();
+        if self.generations[idx] == self.generation {
+            Some(&self.costs[idx])
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn get_mut(&mut self, k: &K) -> Option<&mut Cost<K>> {
+        let idx = k.index();
+        if self.generations[idx] == self.generation {
+            Some(&mut self.costs[idx])
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn insert(&mut self, k: K, cost: Cost<K>) {
+        let idx = k.index();
+        if self.generations[idx] != self.generation {
+            self.generations[idx] = self.generation;
+            self.len += 1;
+        }
+        self.costs[idx] = cost;
+    }
+
+    #[inline]
+    fn relax(&mut self, k: K, cost: Cost<K>) -> bool {
+        if let Some(m) = CostMap::get(self, &k) {
+            if m.visited || m.cost <= cost.cost {
+                return false;
+            }
+        }
+        self.insert(k, cost);
+        true
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.generation = self.generation.wrapping_add(1);
+        if self.generation == 0 {
+            // generations of all entries may collide after wrapping around
+            self.generations.iter_mut().for_each(|g| *g = 0);
+            self.generation = 1;
+        }
+    }
+}
+
+pub struct Search<K, M = Map<K, Cost<K>>> {
     heap: BinaryHeap<HeapEntry<K>>,
-    min_costs: Map<K, Cost<K>>,
+    min_costs: M,
 
     // constraint
     /// do not find path which cost is greater than or *equal* to cost_limit.
@@ -51,6 +195,11 @@ where
     /// limit maximum # of heap items.
     pub heap_limit: usize,
     pub hop_limit: u16,
+    /// set if a node is not added or settled because of limits above
+    limited: bool,
+    /// set if a node is not added because its cost reaches `u32::MAX`, i.e. costs summed with
+    /// `saturating_add` overflowed
+    overflowed: bool,
 
     // metrics
     added_count: usize,
@@ -67,37 +216,69 @@ where
     pub fn with_capacity(capacity: usize) -> Self {
         let mut min_costs = Map::default();
         min_costs.reserve(capacity);
+        Self::with_costs(min_costs)
+    }
+}
+
+impl<K> Search<K, ArrayCosts<K>>
+where
+    K: Index + Eq,
+{
+    /// search backed by flat arrays for nodes `0..node_len`, which is cheap to clear
+    pub fn with_node_len(node_len: usize) -> Self {
+        Self::with_costs(ArrayCosts::new(node_len))
+    }
+
+    pub fn node_len(&self) -> usize {
+        self.min_costs.node_len()
+    }
+}
+
+impl<K, M> Search<K, M>
+where
+    K: Eq + Copy,
+    M: CostMap<K>,
+{
+    fn with_costs(min_costs: M) -> Self {
         Self {
             heap: Default::default(),
             min_costs,
 
-            cost_limit: std::u32::MAX,
-            heap_limit: std::usize::MAX,
-            hop_limit: std::u16::MAX,
+            cost_limit: u32::MAX,
+            heap_limit: usize::MAX,
+            hop_limit: u16::MAX,
+            limited: false,
+            overflowed: false,
 
             added_count: 0,
         }
     }
 
-    #[allow(unused)]
     pub fn clear(&mut self) {
         self.heap.clear();
         self.min_costs.clear();
+        self.limited = false;
+        self.overflowed = false;
         self.added_count = 0;
     }
 
     pub fn add_src(&mut self, src: K) {
+        self.add_src_with_cost(src, 0);
+    }
+
+    /// add source with initial cost, e.g. estimated cost of A* search
+    pub fn add_src_with_cost(&mut self, src: K, cost: u32) {
         // initial condition
         self.heap.push(HeapEntry {
             key: src,
             hop: 0,
-            cost: 0,
+            cost,
         });
         self.min_costs.insert(
             src,
             Cost {
                 prev_key: src,
-                cost: 0,
+                cost,
                 visited: false,
             },
         );
@@ -108,6 +289,7 @@ where
         while let Some(entry) = self.heap.pop() {
             if self.cost_limit <= entry.cost {
                 //TODO: maybe peek?
+                self.limited = true;
                 return None;
             }
 
@@ -125,13 +307,12 @@ where
 
     #[inline]
     pub fn update(&mut self, entry: &HeapEntry<K>, next_key: K, next_cost: u32) -> bool {
-        if self.heap_limit <= self.added_count {
-            return false;
-        }
-        if self.hop_limit <= entry.hop {
-            return false;
-        }
-        if self.cost_limit <= next_cost {
+        if self.heap_limit <= self.added_count
+            || self.hop_limit <= entry.hop
+            || self.cost_limit <= next_cost
+        {
+            self.limited = true;
+            self.overflowed |= next_cost == u32::MAX;
             return false;
         }
 
@@ -141,17 +322,8 @@ where
             cost: next_cost,
             visited: false,
         };
-        match self.min_costs.entry(next_key) {
-            Entry::Occupied(mut o) => {
-                let m = o.get_mut();
-                if m.visited || m.cost <= next_cost {
-                    return false;
-                }
-                *m = cost_item;
-            }
-            Entry::Vacant(o) => {
-                o.insert(cost_item);
-            }
+        if !self.min_costs.relax(next_key, cost_item) {
+            return false;
         }
 
         self.added_count += 1;
@@ -164,7 +336,7 @@ where
     }
 
     #[allow(unused)]
-    pub fn decode(&mut self, key: K) -> Vec<K> {
+    pub fn decode(&self, key: K) -> Vec<K> {
         let mut path = vec![key];
         let mut cur_key = key;
         while let Some(prev_cost) = self.min_costs.get(&cur_key) {
@@ -180,6 +352,22 @@ where
         path
     }
 
+    /// minimum cost in heap, which is a lower bound of costs of unvisited nodes
+    pub fn peek_cost(&self) -> Option<u32> {
+        self.heap.peek().map(|e| e.cost)
+    }
+
+    /// whether the search skipped any node because of limits, so nodes may be reachable
+    /// beyond them
+    pub fn limited(&self) -> bool {
+        self.limited
+    }
+
+    /// whether the search skipped any node because its cost overflowed, see `limited`
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
     pub fn visited_len(&self) -> usize {
         self.min_costs.len()
     }
@@ -188,3 +376,56 @@ where
         self.min_costs.get(k)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Index for usize {
+        const NONE: Self = usize::MAX;
+
+        fn index(&self) -> usize {
+            *self
+        }
+    }
+
+    /// clear `search` and run it from `src` until exhausted, returning cost of each node on
+    /// `0 -> 1 -> 2 -> 3` with shortcut `0 -> 2` and `4 -> 0`
+    fn run<M: CostMap<usize>>(search: &mut Search<usize, M>, src: usize) -> Vec<Option<u32>> {
+        let links: [&[(usize, u32)]; 5] = [&[(1, 2), (2, 5)], &[(2, 1)], &[(3, 1)], &[], &[(0, 1)]];
+        search.clear();
+        search.add_src(src);
+        while let Some(entry) = search.next() {
+            for (next, cost) in links[entry.key].iter() {
+                search.update(&entry, *next, entry.cost + cost);
+            }
+        }
+        (0..links.len())
+            .map(|k| search.get_cost(&k).map(|c| c.cost))
+            .collect()
+    }
+
+    #[test]
+    fn array_costs_after_generation_wraps() {
+        let mut search = Search::with_node_len(5);
+        // nodes 0..4 are labeled with generation 1
+        search.min_costs.generation = 0;
+        assert_eq!(
+            run(&mut search, 0),
+            [Some(0), Some(2), Some(3), Some(4), None]
+        );
+
+        // nodes 2 and 3 are labeled with the last generation, then generation wraps around to 1,
+        // where labels of nodes 0 and 1 from the first query should not be seen
+        search.min_costs.generation = u32::MAX - 1;
+        assert_eq!(run(&mut search, 2), [None, None, Some(0), Some(1), None]);
+        assert_eq!(search.min_costs.generation, u32::MAX);
+        assert_eq!(run(&mut search, 3), [None, None, None, Some(0), None]);
+        assert_eq!(search.min_costs.generation, 1);
+        assert_eq!(search.visited_len(), 1);
+
+        for src in 0..5 {
+            assert_eq!(run(&mut search, src), run(&mut Search::new(), src));
+        }
+    }
+}
@@ -8,12 +8,38 @@ use std::path::Path;
 
 use took::Timer;
 
+mod alt;
+mod cch;
 mod ch;
+mod ch_checkpoint;
+mod ch_config;
+mod ch_file;
+mod ch_secondary;
+mod ch_update;
 mod dijkstra;
+mod many_to_many;
 mod network;
 pub mod partition;
-
+mod phast;
+mod query;
+mod renumber;
+mod td_cch;
+#[cfg(test)]
+mod testutil;
+mod verify;
+
+pub use crate::alt::*;
+pub use crate::cch::{CCH, CCH_FILE_NAME};
 pub use crate::ch::*;
+pub use crate::ch_checkpoint::*;
+pub use crate::ch_config::*;
+pub use crate::ch_file::*;
+pub(crate) use crate::ch_secondary::carry_secondary;
+pub use crate::phast::Phast;
+pub use crate::query::*;
+pub use crate::renumber::*;
+pub use crate::td_cch::TdCCH;
+pub use crate::verify::*;
 pub use network::*;
 
 fn decode_csv_noheader<T, P>(p: P) -> Result<Vec<T>>
@@ -0,0 +1,108 @@
+use ordslice::Ext;
+use rayon::prelude::*;
+
+use super::*;
+
+/// entry of bucket: backward search from `dst` reached the node with `cost`
+struct BucketEntry {
+    key: IdxNodeKey,
+    dst: u32,
+    cost: u32,
+}
+
+impl<'a> Hierarchy<'a> {
+    /// run upward search from `src` until exhausted, and returns all settled nodes with costs
+    pub(crate) fn upward_costs(&self, src: IdxNodeKey, dir: IdxLinkDir) -> Vec<(IdxNodeKey, u32)> {
+        let mut search = dijkstra::Search::new();
+        search.add_src(src);
+
+        let mut out = Vec::new();
+        while let Some(entry) = self.search_step(&mut search, dir) {
+            out.push((entry.key, entry.cost));
+        }
+        out
+    }
+
+    /// bucket-based many-to-many search. Backward upward searches from `dsts` fill per-node
+    /// buckets, and forward upward searches from `srcs` scan them.
+    pub(crate) fn many_to_many(&self, srcs: &[IdxNodeKey], dsts: &[IdxNodeKey]) -> Vec<Vec<u32>> {
+        let mut buckets = dsts
+            .par_iter()
+            .enumerate()
+            .flat_map_iter(|(i, dst)| {
+                self.upward_costs(*dst, IdxLinkDir::Backward)
+                    .into_iter()
+                    .map(move |(key, cost)| BucketEntry {
+                        key,
+                        dst: i as u32,
+                        cost,
+                    })
+            })
+            .collect::<Vec<_>>();
+        buckets.par_sort_unstable_by_key(|e| e.key);
+
+        srcs.par_iter()
+            .map(|src| {
+                let mut costs = vec![u32::MAX; dsts.len()];
+                for (key, cost) in self.upward_costs(*src, IdxLinkDir::Forward) {
+                    let r = buckets.equal_range_by_key(&key, |e| e.key);
+                    for e in &buckets[r] {
+                        let c = &mut costs[e.dst as usize];
+                        *c = std::cmp::min(*c, cost.saturating_add(e.cost));
+                    }
+                }
+                costs
+            })
+            .collect()
+    }
+}
+
+impl<'a> CH<'a> {
+    /// returns `srcs.len() x dsts.len()` cost matrix, where `u32::MAX` means unreachable.
+    pub fn many_to_many(&self, srcs: &[IdxNodeKey], dsts: &[IdxNodeKey]) -> Vec<Vec<u32>> {
+        self.hierarchy().many_to_many(srcs, dsts)
+    }
+}
+
+impl CHQuery {
+    /// returns `srcs.len() x dsts.len()` cost matrix, where `u32::MAX` means unreachable.
+    pub fn many_to_many(&self, srcs: &[IdxNodeKey], dsts: &[IdxNodeKey]) -> Vec<Vec<u32>> {
+        let inner =
+            |keys: &[IdxNodeKey]| keys.iter().map(|k| self.inner_key(*k)).collect::<Vec<_>>();
+        self.hierarchy().many_to_many(&inner(srcs), &inner(dsts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn many_to_many_matches_search() {
+        let graph = testutil::grid_with_dead_end(6, 5);
+        let mut ch = CH::new(&graph);
+        ch.build();
+
+        // duplicates, the dead end node 36, and sources equal to targets
+        let keys = |v: &[usize]| v.iter().map(|i| IdxNodeKey::new(*i)).collect::<Vec<_>>();
+        let srcs = keys(&[0, 7, 7, 36, 20, 35]);
+        let dsts = keys(&[36, 0, 20, 20, 7, 14, 35]);
+
+        let matrix = ch.many_to_many(&srcs, &dsts);
+        let path = testutil::temp_dir("many-to-many");
+        ch.write(&path).unwrap();
+        let query = CHQuery::from_file(&path).unwrap();
+        assert_eq!(query.many_to_many(&srcs, &dsts), matrix);
+        for (i, src) in srcs.iter().enumerate() {
+            for (j, dst) in dsts.iter().enumerate() {
+                let expected = ch.search(*src, *dst).map_or(u32::MAX, |r| r.1);
+                assert_eq!(matrix[i][j], expected, "{:?} -> {:?}", src, dst);
+                assert_eq!(query.search(*src, *dst).map_or(u32::MAX, |r| r.1), expected);
+                assert_eq!(graph.search(*src, *dst).map_or(u32::MAX, |r| r.1), expected);
+            }
+        }
+        assert_eq!(matrix[3][0], 0);
+        assert_eq!(matrix[3][1], u32::MAX);
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::{bail, Result};
+
+use super::*;
+
+/// max speed in km/h of road links without speed limit
+pub const DEFAULT_MAX_SPEED: u8 = 40;
+
+/// names of built-in cost models, see `cost_model`
+pub const COST_MODELS: [&str; 3] = ["fastest", "shortest", "truck"];
+
+/// cost of road links, used to build `Graph` from `road::Network`. Links denied by `pass_code`
+/// or `k_control` are dropped by the network before any model is asked.
+pub trait CostModel: Sync {
+    /// cost of passing `link`, None if the link is not allowed
+    fn cost(&self, link: &road::Link) -> Option<u32>;
+
+    /// cost of a turn with `penalty` seconds, see `road::Turn`
+    fn turn_cost(&self, penalty: u32) -> u32 {
+        penalty
+    }
+}
+
+/// travel time in seconds with speed limit of links
+#[derive(Clone, Copy, Debug)]
+pub struct Fastest {
+    /// speed in km/h for links without speed limit
+    pub default_speed: u8,
+}
+
+impl Default for Fastest {
+    fn default() -> Self {
+        Self {
+            default_speed: DEFAULT_MAX_SPEED,
+        }
+    }
+}
+
+impl CostModel for Fastest {
+    fn cost(&self, link: &road::Link) -> Option<u32> {
+        let speed = link.speed_limit().unwrap_or(self.default_speed);
+        Some(travel_time(link.length(), speed))
+    }
+}
+
+/// length in meters, ignoring turn penalties
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Shortest;
+
+impl CostModel for Shortest {
+    fn cost(&self, link: &road::Link) -> Option<u32> {
+        Some(link.length())
+    }
+
+    fn turn_cost(&self, _penalty: u32) -> u32 {
+        0
+    }
+}
+
+/// travel time in seconds with speed capped for trucks. Links of `denied_classes` are not
+/// allowed. Road networks carry no truck restrictions such as height or weight limits, so the
+/// default, used by `--profile truck`, denies no class and is only a speed cap on `Fastest`.
+#[derive(Clone, Debug)]
+pub struct Truck {
+    /// speed cap in km/h
+    pub max_speed: u8,
+    /// speed in km/h for links without speed limit
+    pub default_speed: u8,
+    /// `road_class` values of links not allowed, links without class are always allowed
+    pub denied_classes: Vec<u8>,
+}
+
+impl Default for Truck {
+    fn default() -> Self {
+        Self {
+            max_speed: 80,
+            default_speed: DEFAULT_MAX_SPEED,
+            denied_classes: Vec::new(),
+        }
+    }
+}
+
+impl CostModel for Truck {
+    fn cost(&self, link: &road::Link) -> Option<u32> {
+        if let Some(class) = link.road_class() {
+            if self.denied_classes.contains(&class) {
+                return None;
+            }
+        }
+        let speed = link.speed_limit().unwrap_or(self.default_speed);
+        let speed = std::cmp::min(speed, self.max_speed);
+        Some(travel_time(link.length(), speed))
+    }
+}
+
+/// built-in cost model by name, one of `COST_MODELS`
+pub fn cost_model(name: &str) -> Result<Box<dyn CostModel>> {
+    Ok(match name {
+        "fastest" => Box::new(Fastest::default()),
+        "shortest" => Box::new(Shortest),
+        "truck" => Box::new(Truck::default()),
+        _ => bail!("unknown cost model: {}", name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use road::LinkKey;
+
+    /// one-way links 10: 1 -> 2, 11: 2 -> 3, 12: 3 -> 4, 13: 2 -> 4 and 14: 4 -> 5, where 10 and
+    /// 13 are fast links of class 1, and turn from 10 to 11 has 20 seconds of penalty
+    fn network() -> road::Network {
+        let path = testutil::network_dir(
+            "cost-model",
+            &[
+                (
+                    "link.csv",
+                    "mid,mesh,link_id,snode_id,enode_id,link_l,max_speed,pass_code,k_control,\
+                     road_class\n\
+                     1,1,10,1,2,1000,100,1,3,1\n\
+                     2,1,11,2,3,1000,50,1,3,2\n\
+                     3,1,12,3,4,500,0,1,3,3\n\
+                     4,1,13,2,4,2000,120,1,3,1\n\
+                     5,1,14,4,5,100,36,1,3,2\n",
+                ),
+                (
+                    "node.csv",
+                    "mid,mesh,node_id,edge_mesh,edge_node\n\
+                     1,1,1,0,0\n2,1,2,0,0\n3,1,3,0,0\n4,1,4,0,0\n5,1,5,0,0\n",
+                ),
+                (
+                    "turn.csv",
+                    "mesh,from_link,via_node,to_link,kind,penalty\n1,10,2,11,0,20\n",
+                ),
+            ],
+        );
+        road::Network::from_path(&path).unwrap()
+    }
+
+    fn key(link_id: u32) -> LinkKey {
+        let snode_id = [1, 2, 3, 2, 4][(link_id - 10) as usize];
+        LinkKey::new(1, link_id, snode_id)
+    }
+
+    /// costs of entering 11, 12, 13 and 14 from the previous link
+    fn link_costs(network: &road::Network, graph: &Graph) -> Vec<Option<u32>> {
+        [(10, 11), (11, 12), (10, 13), (13, 14)]
+            .iter()
+            .map(|&(from, to)| {
+                let from = network.link_key_to_idx(key(from));
+                graph.link_cost(from, network.link_key_to_idx(key(to)))
+            })
+            .collect()
+    }
+
+    /// cost and links of the route from 10 to 14
+    fn route(network: &road::Network, graph: &Graph) -> (u32, Vec<LinkKey>) {
+        let route = network
+            .search_route(graph, key(10), key(14))
+            .unwrap()
+            .unwrap();
+        let keys = route.items.iter().map(|item| item.key).collect();
+        (route.cost, keys)
+    }
+
+    #[test]
+    fn models_on_graph() {
+        let network = network();
+        let through_11 = vec![key(10), key(11), key(12), key(14)];
+        let through_13 = vec![key(10), key(13), key(14)];
+
+        // 12 has no speed limit, so it takes the default speed
+        let fastest = network.to_graph(&Fastest::default());
+        let costs = vec![Some(72 + 20), Some(45), Some(60), Some(10)];
+        assert_eq!(link_costs(&network, &fastest), costs);
+        assert_eq!(route(&network, &fastest), (70, through_13.clone()));
+
+        // lengths without turn penalty
+        let shortest = network.to_graph(&Shortest);
+        let costs = vec![Some(1000), Some(500), Some(2000), Some(100)];
+        assert_eq!(link_costs(&network, &shortest), costs);
+        assert_eq!(route(&network, &shortest), (1600, through_11.clone()));
+
+        // speed cap of 80 km/h only slows down 13, which is the only fast link entered
+        let truck = network.to_graph(&Truck::default());
+        let costs = vec![Some(72 + 20), Some(45), Some(90), Some(10)];
+        assert_eq!(link_costs(&network, &truck), costs);
+        assert_eq!(route(&network, &truck), (100, through_13));
+
+        // links entering class 1 are dropped, so 13 can not be entered but 10 still starts
+        // the route
+        let model = Truck {
+            denied_classes: vec![1],
+            ..Default::default()
+        };
+        let denied = network.to_graph(&model);
+        let costs = vec![Some(72 + 20), Some(45), None, Some(10)];
+        assert_eq!(link_costs(&network, &denied), costs);
+        assert_eq!(route(&network, &denied), (147, through_11));
+        assert_eq!(
+            denied.forward_links().count() + 1,
+            truck.forward_links().count()
+        );
+    }
+
+    #[test]
+    fn models_by_name() {
+        let network = network();
+        let expected = [
+            network.to_graph(&Fastest::default()),
+            network.to_graph(&Shortest),
+            network.to_graph(&Truck::default()),
+        ];
+        for (name, expected) in COST_MODELS.iter().zip(expected.iter()) {
+            let model = cost_model(name).unwrap();
+            let graph = network.to_graph(model.as_ref());
+            assert_eq!(graph.idx_links, expected.idx_links, "{}", name);
+        }
+        assert!(cost_model("walk").is_err());
+    }
+}
@@ -0,0 +1,390 @@
+use rayon::prelude::*;
+use std::ops::Range;
+
+use super::*;
+
+/// compressed sparse row adjacency. Links of node `i` are stored contiguously in
+/// `links[offsets[i]..offsets[i + 1]]`, forward links first, then backward links starting from
+/// `backward_offsets[i]`, so links on a direction are iterated without checking `dir()`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Csr {
+    offsets: Section<u32>,
+    backward_offsets: Section<u32>,
+    links: Section<IdxLink>,
+}
+
+impl Csr {
+    /// build from offsets with `node_len + 1` items and links of all nodes. Links of each node
+    /// should be sorted by direction, forward links first.
+    pub fn from_parts(offsets: Vec<u32>, links: Vec<IdxLink>) -> Self {
+        assert_eq!(offsets.last().map(|o| *o as usize), Some(links.len()));
+        let backward_offsets = offsets
+            .par_windows(2)
+            .map(|w| {
+                let v = &links[w[0] as usize..w[1] as usize];
+                debug_assert!(v.windows(2).all(|pair| pair[0].dir() <= pair[1].dir()));
+                w[0] + v.partition_point(|l| l.dir() == IdxLinkDir::Forward) as u32
+            })
+            .collect::<Vec<_>>();
+        Self {
+            offsets: offsets.into(),
+            backward_offsets: backward_offsets.into(),
+            links: links.into(),
+        }
+    }
+
+    /// build from sections which are already validated, e.g. of `CHFile`
+    pub(crate) fn from_sections(
+        offsets: Section<u32>,
+        backward_offsets: Section<u32>,
+        links: Section<IdxLink>,
+    ) -> Self {
+        Self {
+            offsets,
+            backward_offsets,
+            links,
+        }
+    }
+
+    /// whether links are borrowed from memory map of `CHFile`
+    pub(crate) fn is_mapped(&self) -> bool {
+        self.links.is_mapped()
+    }
+
+    pub fn node_len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    pub fn link_len(&self) -> usize {
+        self.links.len()
+    }
+
+    /// offsets of each node on `flat_links`, with `node_len + 1` items
+    pub fn offsets(&self) -> &[u32] {
+        &self.offsets
+    }
+
+    /// offsets of backward links of each node on `flat_links`
+    pub fn backward_offsets(&self) -> &[u32] {
+        &self.backward_offsets
+    }
+
+    pub fn flat_links(&self) -> &[IdxLink] {
+        &self.links
+    }
+
+    /// range of links of `idx` on `flat_links`
+    #[inline]
+    pub fn range(&self, idx: usize) -> Range<usize> {
+        self.offsets[idx] as usize..self.offsets[idx + 1] as usize
+    }
+
+    /// all links of `idx`, forward links first
+    #[inline]
+    pub fn links(&self, idx: usize) -> &[IdxLink] {
+        &self.links[self.range(idx)]
+    }
+
+    #[inline]
+    pub fn links_mut(&mut self, idx: usize) -> &mut [IdxLink] {
+        let r = self.range(idx);
+        &mut self.links[r]
+    }
+
+    /// links of `idx` on given direction
+    #[inline]
+    pub fn dir_links(&self, idx: usize, dir: IdxLinkDir) -> &[IdxLink] {
+        let mid = self.backward_offsets[idx] as usize;
+        match dir {
+            IdxLinkDir::Forward => &self.links[self.offsets[idx] as usize..mid],
+            IdxLinkDir::Backward => &self.links[mid..self.offsets[idx + 1] as usize],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[IdxLink]> + '_ {
+        self.offsets
+            .windows(2)
+            .map(move |w| &self.links[w[0] as usize..w[1] as usize])
+    }
+
+    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = &[IdxLink]> + '_ {
+        self.offsets
+            .par_windows(2)
+            .map(move |w| &self.links[w[0] as usize..w[1] as usize])
+    }
+
+    /// copy links to per-node vectors
+    pub fn to_vecs(&self) -> Vec<Vec<IdxLink>> {
+        self.par_iter().map(|v| v.to_vec()).collect()
+    }
+}
+
+impl From<&[Vec<IdxLink>]> for Csr {
+    fn from(links: &[Vec<IdxLink>]) -> Self {
+        let mut offsets = Vec::with_capacity(links.len() + 1);
+        let mut offset = 0usize;
+        offsets.push(0u32);
+        for v in links {
+            offset += v.len();
+            assert!(offset <= u32::MAX as usize, "too many links: {}", offset);
+            offsets.push(offset as u32);
+        }
+
+        let mut flat_links = Vec::with_capacity(offset);
+        for v in links {
+            let start = flat_links.len();
+            flat_links.extend_from_slice(v);
+            // stable sort keeps order of links on each direction
+            flat_links[start..].sort_by_key(|l| l.dir());
+        }
+        Self::from_parts(offsets, flat_links)
+    }
+}
+
+impl From<Vec<Vec<IdxLink>>> for Csr {
+    fn from(links: Vec<Vec<IdxLink>>) -> Self {
+        Self::from(links.as_slice())
+    }
+}
+
+/// adjacency like `Csr` which allows inserting and removing items of each node, for graphs
+/// changing while building. Items of each node are stored contiguously with spare capacity, and
+/// moved to the end of `items` when they outgrow it.
+#[derive(Clone, Debug)]
+pub(crate) struct DynCsr<T> {
+    offsets: Vec<usize>,
+    lens: Vec<u32>,
+    capacities: Vec<u32>,
+    items: Vec<T>,
+    /// number of items of all nodes
+    len: usize,
+}
+
+impl<T> Default for DynCsr<T> {
+    fn default() -> Self {
+        Self {
+            offsets: Vec::new(),
+            lens: Vec::new(),
+            capacities: Vec::new(),
+            items: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T: Copy> DynCsr<T> {
+    /// `node_len` nodes without items
+    pub(crate) fn new(node_len: usize) -> Self {
+        Self {
+            offsets: vec![0; node_len],
+            lens: vec![0; node_len],
+            capacities: vec![0; node_len],
+            items: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub(crate) fn node_len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// number of items of all nodes
+    pub(crate) fn link_len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub(crate) fn links(&self, idx: usize) -> &[T] {
+        let offset = self.offsets[idx];
+        &self.items[offset..offset + self.lens[idx] as usize]
+    }
+
+    #[inline]
+    pub(crate) fn links_mut(&mut self, idx: usize) -> &mut [T] {
+        let offset = self.offsets[idx];
+        &mut self.items[offset..offset + self.lens[idx] as usize]
+    }
+
+    /// insert `item` at `pos` of items of `idx`, shifting following items
+    pub(crate) fn insert(&mut self, idx: usize, pos: usize, item: T) {
+        let len = self.lens[idx] as usize;
+        assert!(pos <= len);
+        self.reserve(idx, len + 1, item);
+        let offset = self.offsets[idx];
+        self.items[offset + len] = item;
+        self.items[offset + pos..offset + len + 1].rotate_right(1);
+        self.lens[idx] += 1;
+        self.len += 1;
+    }
+
+    /// remove item at `pos` of items of `idx`, shifting following items
+    pub(crate) fn remove(&mut self, idx: usize, pos: usize) -> T {
+        let len = self.lens[idx] as usize;
+        assert!(pos < len);
+        let offset = self.offsets[idx];
+        let item = self.items[offset + pos];
+        self.items[offset + pos..offset + len].rotate_left(1);
+        self.lens[idx] -= 1;
+        self.len -= 1;
+        item
+    }
+
+    /// remove and return all items of `idx`, keeping its capacity
+    pub(crate) fn take(&mut self, idx: usize) -> Vec<T> {
+        let items = self.links(idx).to_vec();
+        self.len -= items.len();
+        self.lens[idx] = 0;
+        items
+    }
+
+    /// replace items of `idx` with `items`
+    pub(crate) fn set(&mut self, idx: usize, items: &[T]) {
+        if let Some(item) = items.first() {
+            self.reserve(idx, items.len(), *item);
+        }
+        let offset = self.offsets[idx];
+        self.items[offset..offset + items.len()].copy_from_slice(items);
+        self.len = self.len - self.lens[idx] as usize + items.len();
+        self.lens[idx] = items.len() as u32;
+    }
+
+    /// make room for `len` items of `idx`, where spare capacity is filled with `fill`
+    fn reserve(&mut self, idx: usize, len: usize, fill: T) {
+        if len <= self.capacities[idx] as usize {
+            return;
+        }
+        // free space of moved nodes is reclaimed once it dominates
+        if self.items.len() > 2 * self.len + 1024 {
+            self.compact();
+        }
+        let capacity = std::cmp::max(len * 2, 4);
+        let offset = self.items.len();
+        let old_offset = self.offsets[idx];
+        self.items
+            .extend_from_within(old_offset..old_offset + self.lens[idx] as usize);
+        self.items.resize(offset + capacity, fill);
+        self.offsets[idx] = offset;
+        self.capacities[idx] = capacity as u32;
+    }
+
+    /// move items of all nodes to the front, leaving no spare capacity
+    fn compact(&mut self) {
+        let mut items = Vec::with_capacity(self.len);
+        for idx in 0..self.node_len() {
+            let offset = items.len();
+            items.extend_from_slice(self.links(idx));
+            self.offsets[idx] = offset;
+            self.capacities[idx] = self.lens[idx];
+        }
+        self.items = items;
+    }
+}
+
+impl<T: Copy> From<Vec<Vec<T>>> for DynCsr<T> {
+    fn from(vecs: Vec<Vec<T>>) -> Self {
+        let mut csr = Self::new(vecs.len());
+        for (idx, v) in vecs.iter().enumerate() {
+            csr.offsets[idx] = csr.items.len();
+            csr.lens[idx] = v.len() as u32;
+            csr.capacities[idx] = v.len() as u32;
+            csr.items.extend_from_slice(v);
+        }
+        csr.len = csr.items.len();
+        csr
+    }
+}
+
+impl DynCsr<IdxLink> {
+    /// range of links of `idx` on given direction, relative to `links(idx)`. Links of each node
+    /// should be sorted by direction, forward links first.
+    #[inline]
+    pub(crate) fn dir_range(&self, idx: usize, dir: IdxLinkDir) -> Range<usize> {
+        let links = self.links(idx);
+        let mid = links.partition_point(|l| l.dir() == IdxLinkDir::Forward);
+        match dir {
+            IdxLinkDir::Forward => 0..mid,
+            IdxLinkDir::Backward => mid..links.len(),
+        }
+    }
+
+    /// links of `idx` on given direction
+    #[inline]
+    pub(crate) fn dir_links(&self, idx: usize, dir: IdxLinkDir) -> &[IdxLink] {
+        &self.links(idx)[self.dir_range(idx, dir)]
+    }
+
+    pub(crate) fn to_csr(&self) -> Csr {
+        let mut offsets = Vec::with_capacity(self.node_len() + 1);
+        offsets.push(0u32);
+        let mut links = Vec::with_capacity(self.len);
+        for idx in 0..self.node_len() {
+            links.extend_from_slice(self.links(idx));
+            assert!(links.len() <= u32::MAX as usize, "too many links");
+            offsets.push(links.len() as u32);
+        }
+        Csr::from_parts(offsets, links)
+    }
+}
+
+impl From<&Csr> for DynCsr<IdxLink> {
+    fn from(csr: &Csr) -> Self {
+        let node_len = csr.node_len();
+        Self {
+            offsets: csr.offsets()[..node_len]
+                .iter()
+                .map(|o| *o as usize)
+                .collect(),
+            lens: csr.offsets().windows(2).map(|w| w[1] - w[0]).collect(),
+            capacities: csr.offsets().windows(2).map(|w| w[1] - w[0]).collect(),
+            items: csr.flat_links().to_vec(),
+            len: csr.link_len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn dyn_csr_matches_vecs() {
+        let node_len = 50;
+        let mut expected = vec![Vec::new(); node_len];
+        let mut csr = DynCsr::<u32>::new(node_len);
+        let mut state = 7u64;
+        for i in 0..20000u32 {
+            let state = testutil::xorshift(&mut state);
+            let idx = (state % node_len as u64) as usize;
+            let v: &mut Vec<u32> = &mut expected[idx];
+            match state % 7 {
+                0 if !v.is_empty() => {
+                    let pos = (state >> 8) as usize % v.len();
+                    assert_eq!(csr.remove(idx, pos), v.remove(pos));
+                }
+                1 => {
+                    assert_eq!(csr.take(idx), std::mem::take(v));
+                }
+                2 => {
+                    let items = (0..(state >> 8) % 20)
+                        .map(|j| i + j as u32)
+                        .collect::<Vec<_>>();
+                    csr.set(idx, &items);
+                    *v = items;
+                }
+                _ => {
+                    let pos = (state >> 8) as usize % (v.len() + 1);
+                    csr.insert(idx, pos, i);
+                    v.insert(pos, i);
+                }
+            }
+        }
+        for (idx, v) in expected.iter().enumerate() {
+            assert_eq!(csr.links(idx), v.as_slice());
+        }
+        assert_eq!(
+            csr.link_len(),
+            expected.iter().map(|v| v.len()).sum::<usize>()
+        );
+    }
+}
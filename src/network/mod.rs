@@ -1,10 +1,24 @@
 use super::*;
 use rayon::prelude::*;
 
+mod cost;
+mod csr;
+mod profile;
 pub mod road;
+mod route;
 pub mod shp;
+mod td;
 pub mod walk;
+mod wide;
 
+pub use cost::*;
+pub use csr::*;
+pub use profile::*;
+pub use route::*;
+pub use td::*;
+pub use wide::*;
+
+#[repr(transparent)]
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct IdxNodeKey(u32);
 impl std::hash::Hash for IdxNodeKey {
@@ -17,6 +31,9 @@ impl std::hash::Hash for IdxNodeKey {
     }
 }
 impl IdxNodeKey {
+    /// placeholder for absent node, e.g. middle node of links which are not contractions
+    pub(crate) const NONE: IdxNodeKey = IdxNodeKey(u32::MAX);
+
     #[inline]
     pub fn new(val: usize) -> Self {
         IdxNodeKey(val as u32)
@@ -27,6 +44,15 @@ impl IdxNodeKey {
     }
 }
 
+impl dijkstra::Index for IdxNodeKey {
+    const NONE: Self = IdxNodeKey::NONE;
+
+    #[inline]
+    fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum IdxLinkDir {
     Forward = 1,
@@ -40,7 +66,7 @@ impl IdxLinkDir {
             _ => panic!("invalid val for IdxLinkDir: {}", val),
         }
     }
-    fn rev(&self) -> Self {
+    pub(crate) fn rev(&self) -> Self {
         match self {
             IdxLinkDir::Forward => IdxLinkDir::Backward,
             IdxLinkDir::Backward => IdxLinkDir::Forward,
@@ -48,6 +74,29 @@ impl IdxLinkDir {
     }
 }
 
+/// maximum cost of a link, which is stored in lower 30 bits of `IdxLink`. Costs beyond it are
+/// saturated, see `IdxLink::new`.
+pub const MAX_COST: u32 = (1 << 30) - 1;
+
+/// number of `costs` exceeding `MAX_COST`, which are saturated by `IdxLink::new`
+pub fn overflow_len<I>(costs: I) -> usize
+where
+    I: IntoIterator<Item = u32>,
+{
+    costs.into_iter().filter(|c| *c > MAX_COST).count()
+}
+
+/// report links saturated while building a graph from a network
+pub(crate) fn report_overflow(overflow_len: usize) {
+    if overflow_len > 0 {
+        eprintln!(
+            "cost overflow: {} links are saturated to {}, use WideGraph",
+            overflow_len, MAX_COST
+        );
+    }
+}
+
+#[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct IdxLink {
     pub enode_idx: IdxNodeKey,
@@ -55,30 +104,44 @@ pub struct IdxLink {
     cost: u32,
 }
 impl IdxLink {
+    /// link with `cost` saturated to `MAX_COST`. Use `checked_new` to detect overflow.
     pub fn new(enode_idx: IdxNodeKey, cost: u32, dir: IdxLinkDir) -> Self {
         let dir = (dir as u32) << 30;
-        let cost = dir | (cost & ((1 << 30) - 1));
+        let cost = dir | std::cmp::min(cost, MAX_COST);
         Self { enode_idx, cost }
     }
+    /// None if `cost` exceeds `MAX_COST`
+    pub fn checked_new(enode_idx: IdxNodeKey, cost: u32, dir: IdxLinkDir) -> Option<Self> {
+        if cost > MAX_COST {
+            None
+        } else {
+            Some(Self::new(enode_idx, cost, dir))
+        }
+    }
     #[inline]
     pub fn dir(&self) -> IdxLinkDir {
         IdxLinkDir::from_u32(self.cost >> 30)
     }
     #[inline]
     pub fn cost(&self) -> u32 {
-        self.cost & ((1 << 30) - 1)
+        self.cost & MAX_COST
     }
+    /// whether direction bits are `dir`, false if they are invalid
+    pub(crate) fn has_dir(&self, dir: IdxLinkDir) -> bool {
+        self.cost >> 30 == dir as u32
+    }
+    /// set `cost` saturated to `MAX_COST`
     #[inline]
     pub fn set_cost(&mut self, cost: u32) {
         let dir = self.cost & (0b11 << 30);
-        let cost = cost & ((1 << 30) - 1);
+        let cost = std::cmp::min(cost, MAX_COST);
         self.cost = dir | cost;
     }
 }
 impl PartialOrd for IdxLink {
     #[inline]
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.dir().partial_cmp(&other.dir())
+        Some(self.cmp(other))
     }
 }
 impl Ord for IdxLink {
@@ -90,7 +153,11 @@ impl Ord for IdxLink {
 
 pub struct Graph {
     pub node_len: usize,
-    pub idx_links: Vec<Vec<IdxLink>>,
+    /// links of each node, forward links first then reversed links
+    pub idx_links: Csr,
+    /// secondary metric of each link of `idx_links`, e.g. length of a graph built on time.
+    /// Empty if the graph has no secondary metric.
+    pub secondary: Vec<u32>,
 }
 
 pub fn map_rev(list: &[Vec<IdxLink>]) -> Vec<Vec<IdxLink>> {
@@ -107,7 +174,22 @@ pub fn map_rev(list: &[Vec<IdxLink>]) -> Vec<Vec<IdxLink>> {
     rev
 }
 
+pub(crate) fn forward_links(links: &Csr) -> impl Iterator<Item = (IdxNodeKey, &IdxLink)> {
+    (0..links.node_len()).flat_map(move |i| {
+        links
+            .dir_links(i, IdxLinkDir::Forward)
+            .iter()
+            .map(move |l| (IdxNodeKey::new(i), l))
+    })
+}
+
 impl Graph {
+    /// forward links with their start node. Position of a link in this iterator is its link
+    /// index, which is used to update costs of links.
+    pub fn forward_links(&self) -> impl Iterator<Item = (IdxNodeKey, &IdxLink)> {
+        forward_links(&self.idx_links)
+    }
+
     fn from_links(mut idx_links: Vec<Vec<IdxLink>>) -> Self {
         let len = idx_links.len();
         let mut idx_links_rev = map_rev(idx_links.as_slice());
@@ -136,24 +218,51 @@ impl Graph {
 
         Self {
             node_len: len,
-            idx_links,
+            idx_links: Csr::from(idx_links),
+            secondary: Vec::new(),
         }
     }
 
-    fn search_step(
+    /// same as `from_links`, with secondary metric of each link
+    pub(crate) fn from_links_with_secondary(links: Vec<Vec<(IdxLink, u32)>>) -> Self {
+        let mut secondary = links
+            .iter()
+            .map(|v| v.iter().map(|(_, s)| *s).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        // reversed links are appended in the same order as `map_rev`
+        let mut secondary_rev = vec![Vec::new(); links.len()];
+        for v in links.iter() {
+            for (link, s) in v.iter() {
+                secondary_rev[link.enode_idx.index()].push(*s);
+            }
+        }
+        for (i, v) in secondary.iter_mut().enumerate() {
+            v.append(&mut secondary_rev[i]);
+        }
+
+        let idx_links = links
+            .into_iter()
+            .map(|v| v.into_iter().map(|(l, _)| l).collect())
+            .collect();
+        let mut graph = Self::from_links(idx_links);
+        graph.secondary = secondary.concat();
+        graph
+    }
+
+    pub(crate) fn search_step<M>(
         &self,
-        search: &mut dijkstra::Search<IdxNodeKey>,
+        search: &mut dijkstra::Search<IdxNodeKey, M>,
         dir: IdxLinkDir,
-    ) -> Option<dijkstra::HeapEntry<IdxNodeKey>> {
+    ) -> Option<dijkstra::HeapEntry<IdxNodeKey>>
+    where
+        M: dijkstra::CostMap<IdxNodeKey>,
+    {
         if let Some(entry) = search.next() {
             let key = entry.key;
 
-            for idx_link in self.idx_links[key.index()].iter() {
-                if idx_link.dir() != dir {
-                    continue;
-                }
+            for idx_link in self.idx_links.dir_links(key.index(), dir) {
                 let next_key = idx_link.enode_idx;
-                let next_cost = entry.cost + idx_link.cost();
+                let next_cost = entry.cost.saturating_add(idx_link.cost());
                 search.update(&entry, next_key, next_cost);
             }
             return Some(entry);
@@ -161,68 +270,268 @@ impl Graph {
         None
     }
 
+    /// shortest path from `src` to `dst` and its cost. None if unreachable or if the cost of the
+    /// path overflows, use `search_bounded` to tell them apart.
     pub fn search(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u32)> {
-        let mut search = dijkstra::Search::new();
+        self.search_in(&mut dijkstra::Search::new(), src, dst, &Default::default())
+            .into_option()
+    }
+
+    /// same as `search` within limits of `options`
+    pub fn search_bounded(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        options: &SearchOptions,
+    ) -> SearchResult {
+        self.search_in(&mut dijkstra::Search::new(), src, dst, options)
+    }
+
+    /// same as `search`, reusing `ctx` instead of allocating search state
+    pub fn search_with(
+        &self,
+        ctx: &mut QueryContext,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32)> {
+        let (search, _) = ctx.searches(self.node_len);
+        self.search_in(search, src, dst, &Default::default())
+            .into_option()
+    }
+
+    fn search_in<M>(
+        &self,
+        search: &mut dijkstra::Search<IdxNodeKey, M>,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        options: &SearchOptions,
+    ) -> SearchResult
+    where
+        M: dijkstra::CostMap<IdxNodeKey>,
+    {
+        search.cost_limit = options.cost_limit();
         search.add_src(src);
 
-        while let Some(entry) = self.search_step(&mut search, IdxLinkDir::Forward) {
+        let mut settled = 0;
+        while let Some(entry) = self.search_step(search, IdxLinkDir::Forward) {
             if entry.key == dst {
                 let decoded = search.decode(dst);
-                return Some((decoded, entry.cost));
+                return SearchResult::Found(decoded, entry.cost);
+            }
+            settled += 1;
+            if settled >= options.settled_limit() {
+                return SearchResult::OutOfRange;
             }
         }
-        None
+        if options.max_cost.is_none() && search.overflowed() {
+            SearchResult::Overflow
+        } else if search.limited() {
+            SearchResult::OutOfRange
+        } else {
+            SearchResult::Unreachable
+        }
     }
 
     pub fn search_bidir(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u32)> {
-        let mut search_f = dijkstra::Search::new();
-        search_f.add_src(src);
+        self.search_bidir_in(
+            &mut dijkstra::Search::new(),
+            &mut dijkstra::Search::new(),
+            src,
+            dst,
+        )
+    }
 
-        let mut search_b = dijkstra::Search::new();
+    /// same as `search_bidir`, reusing `ctx` instead of allocating search state
+    pub fn search_bidir_with(
+        &self,
+        ctx: &mut QueryContext,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32)> {
+        let (search_f, search_b) = ctx.searches(self.node_len);
+        self.search_bidir_in(search_f, search_b, src, dst)
+    }
+
+    fn search_bidir_in<M>(
+        &self,
+        search_f: &mut dijkstra::Search<IdxNodeKey, M>,
+        search_b: &mut dijkstra::Search<IdxNodeKey, M>,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32)>
+    where
+        M: dijkstra::CostMap<IdxNodeKey>,
+    {
+        search_f.add_src(src);
         search_b.add_src(dst);
 
-        let mut costs = Vec::new();
-        costs.resize(self.node_len, 0);
+        // best meeting node and cost of path through it, which is an upper bound of the shortest
+        // path. Every node whose label changes is checked against labels of the other direction.
+        let mut meet = (src, if src == dst { 0 } else { u32::MAX });
 
-        let entry = 'outer: loop {
-            let mut updated = false;
-            if let Some(entry) = self.search_step(&mut search_f, IdxLinkDir::Forward) {
-                updated = true;
+        // unvisited nodes of each direction cost at least the minimum of its queue, so a path
+        // shorter than `meet` should cost at least the sum of minimums
+        while let (Some(min_f), Some(min_b)) = (search_f.peek_cost(), search_b.peek_cost()) {
+            if min_f.saturating_add(min_b) >= meet.1 {
+                break;
+            }
 
-                let idx = entry.key.0 as usize;
-                if costs[idx] != 0 {
-                    break 'outer Some(entry);
-                }
-                costs[idx] = entry.cost;
+            if search_f.visited_len() <= search_b.visited_len() {
+                self.search_step_meet(search_f, search_b, IdxLinkDir::Forward, &mut meet);
+            } else {
+                self.search_step_meet(search_b, search_f, IdxLinkDir::Backward, &mut meet);
             }
+        }
+
+        let (key, cost) = meet;
+        if cost == u32::MAX {
+            return None;
+        }
 
-            while search_b.visited_len() < search_f.visited_len() {
-                if let Some(entry) = self.search_step(&mut search_b, IdxLinkDir::Backward) {
-                    updated = true;
+        let mut path_f = search_f.decode(key);
+        let mut path_b = search_b.decode(key);
+        path_b.reverse();
+        path_f.pop();
+        path_f.append(&mut path_b);
+        Some((path_f, cost))
+    }
 
-                    let idx = entry.key.0 as usize;
-                    if costs[idx] != 0 {
-                        break 'outer Some(entry);
-                    }
-                    costs[idx] = entry.cost;
-                } else {
-                    break;
+    /// expand a node of `search` on `dir`, and update `meet` with nodes labeled by both
+    /// `search` and `other`
+    fn search_step_meet<M>(
+        &self,
+        search: &mut dijkstra::Search<IdxNodeKey, M>,
+        other: &dijkstra::Search<IdxNodeKey, M>,
+        dir: IdxLinkDir,
+        meet: &mut (IdxNodeKey, u32),
+    ) where
+        M: dijkstra::CostMap<IdxNodeKey>,
+    {
+        let entry = match search.next() {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        for idx_link in self.idx_links.dir_links(entry.key.index(), dir) {
+            let next_key = idx_link.enode_idx;
+            let next_cost = entry.cost.saturating_add(idx_link.cost());
+            if !search.update(&entry, next_key, next_cost) {
+                continue;
+            }
+            if let Some(c) = other.get_cost(&next_key) {
+                let cost = next_cost.saturating_add(c.cost);
+                if cost < meet.1 {
+                    *meet = (next_key, cost);
                 }
             }
-            if !updated {
-                break None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    /// compare `search_bidir` with `search` on all pairs, checking costs of returned paths
+    fn assert_bidir(graph: &Graph) {
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            let expected = graph.search(src, dst);
+            let actual = graph.search_bidir(src, dst);
+            assert_eq!(
+                actual.as_ref().map(|r| r.1),
+                expected.as_ref().map(|r| r.1),
+                "{:?} -> {:?}",
+                src,
+                dst
+            );
+            if let Some((path, cost)) = actual {
+                assert_eq!(path.first(), Some(&src));
+                assert_eq!(path.last(), Some(&dst));
+                assert_eq!(path_cost(graph, &path), Some(cost));
             }
-        };
+        }
+    }
+
+    #[test]
+    fn bidir_first_meeting_node_off_path() {
+        // node 1 is the first node settled on both directions, while the shortest path is
+        // 0 -> 2 -> 3 -> 4
+        let graph = testutil::graph(5, &[(0, 1, 5), (1, 4, 5), (0, 2, 3), (2, 3, 3), (3, 4, 3)]);
+        let (path, cost) = graph.search_bidir(IdxNodeKey(0), IdxNodeKey(4)).unwrap();
+        assert_eq!(cost, 9);
+        assert_eq!(
+            path,
+            vec![IdxNodeKey(0), IdxNodeKey(2), IdxNodeKey(3), IdxNodeKey(4)]
+        );
+        assert_bidir(&graph);
+    }
+
+    #[test]
+    fn bidir_zero_costs() {
+        let graph = testutil::graph(
+            6,
+            &[
+                (0, 1, 0),
+                (1, 2, 0),
+                (2, 3, 4),
+                (0, 3, 4),
+                (3, 4, 0),
+                (4, 3, 0),
+                (4, 5, 0),
+                (1, 5, 7),
+            ],
+        );
+        assert_bidir(&graph);
+        assert_bidir(&testutil::grid(6, 17));
+    }
+
+    #[test]
+    fn bidir_same_node_and_unreachable() {
+        // node 3 is isolated and node 2 has no outgoing link
+        let graph = testutil::graph(4, &[(0, 1, 2), (1, 2, 2), (1, 0, 2)]);
+        for idx in 0..graph.node_len {
+            let key = IdxNodeKey::new(idx);
+            assert_eq!(graph.search_bidir(key, key).map(|r| r.1), Some(0));
+        }
+        assert_eq!(graph.search_bidir(IdxNodeKey(2), IdxNodeKey(0)), None);
+        assert_eq!(graph.search_bidir(IdxNodeKey(0), IdxNodeKey(3)), None);
+        assert_bidir(&graph);
+    }
+
+    #[test]
+    fn search_reports_overflow() {
+        // every link costs `MAX_COST`, so paths of 4 or more links overflow `u32`
+        let links = (0..5).map(|i| (i, i + 1, MAX_COST)).collect::<Vec<_>>();
+        let graph = testutil::graph(6, &links);
+        assert_eq!(
+            overflow_len(graph.forward_links().map(|(_, l)| l.cost())),
+            0
+        );
+        assert_eq!(overflow_len([MAX_COST, MAX_COST + 1, u32::MAX]), 2);
 
-        entry.map(move |entry| {
-            let mut path_f = search_f.decode(entry.key);
-            let mut path_b = search_b.decode(entry.key);
-            path_b.reverse();
-            path_f.pop();
-            path_f.append(&mut path_b);
+        let unbounded = SearchOptions::default();
+        let found = graph.search_bounded(IdxNodeKey(0), IdxNodeKey(3), &unbounded);
+        assert_eq!(found.into_option().map(|r| r.1), Some(3 * MAX_COST));
+        assert_eq!(
+            graph.search_bounded(IdxNodeKey(0), IdxNodeKey(5), &unbounded),
+            SearchResult::Overflow
+        );
+        assert_eq!(
+            graph.search_bounded(
+                IdxNodeKey(0),
+                IdxNodeKey(5),
+                &SearchOptions::with_max_cost(10)
+            ),
+            SearchResult::OutOfRange
+        );
+        assert_eq!(
+            graph.search_bounded(IdxNodeKey(5), IdxNodeKey(0), &unbounded),
+            SearchResult::Unreachable
+        );
 
-            let idx = entry.key.0 as usize;
-            (path_f, entry.cost + costs[idx])
-        })
+        let wide = WideGraph::from(&graph);
+        let (_, cost) = wide.search(IdxNodeKey(0), IdxNodeKey(5)).unwrap();
+        assert_eq!(cost, 5 * MAX_COST as u64);
     }
 }
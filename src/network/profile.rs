@@ -0,0 +1,299 @@
+use anyhow::{bail, Result};
+
+/// length of the period of `TravelTimeProfile` in seconds
+pub const DAY_SECONDS: f64 = 86400.0;
+
+/// knots closer than this are merged, and values within this are treated as equal
+const EPSILON: f64 = 1e-6;
+
+/// periodic piecewise-linear travel time by departure time, repeated every `DAY_SECONDS`.
+/// Travel time between knots is interpolated linearly, and between the last knot and the first
+/// knot of the next day. Profiles satisfy FIFO property, i.e. departing later never arrives
+/// earlier, so time-dependent Dijkstra is exact.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TravelTimeProfile {
+    /// (departure time of day, travel time) in seconds, sorted by departure time within
+    /// `0..DAY_SECONDS`
+    knots: Vec<(f64, f64)>,
+}
+
+impl TravelTimeProfile {
+    /// same travel time for all departure times
+    pub fn constant(travel_time: f64) -> Self {
+        Self {
+            knots: vec![(0.0, travel_time)],
+        }
+    }
+
+    /// build from (departure time of day, travel time) in seconds. Fails if the profile is
+    /// empty, times are out of a day or duplicated, or it violates FIFO property.
+    pub fn from_knots(mut knots: Vec<(f64, f64)>) -> Result<Self> {
+        if knots.is_empty() {
+            bail!("empty profile");
+        }
+        knots.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (t, v) in knots.iter() {
+            if !(0.0..DAY_SECONDS).contains(t) || !v.is_finite() || *v < 0.0 {
+                bail!("invalid knot of profile: ({}, {})", t, v);
+            }
+        }
+        if knots.windows(2).any(|w| w[1].0 - w[0].0 < EPSILON) {
+            bail!("duplicated departure time of profile: {:?}", knots);
+        }
+
+        let profile = Self { knots };
+        if !profile.is_fifo() {
+            bail!("profile violates FIFO property: {:?}", profile.knots);
+        }
+        Ok(profile)
+    }
+
+    pub fn knots(&self) -> &[(f64, f64)] {
+        &self.knots
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.knots.len() == 1
+    }
+
+    /// travel time when departing at `departure` seconds, which may be beyond a day
+    pub fn eval(&self, departure: f64) -> f64 {
+        let t = departure.rem_euclid(DAY_SECONDS);
+        let knots = &self.knots;
+        let i = knots.partition_point(|k| k.0 <= t);
+        let (t0, v0) = if i == 0 {
+            let (t, v) = knots[knots.len() - 1];
+            (t - DAY_SECONDS, v)
+        } else {
+            knots[i - 1]
+        };
+        let (t1, v1) = if i == knots.len() {
+            let (t, v) = knots[0];
+            (t + DAY_SECONDS, v)
+        } else {
+            knots[i]
+        };
+        if t1 - t0 < EPSILON {
+            return v0;
+        }
+        v0 + (v1 - v0) * (t - t0) / (t1 - t0)
+    }
+
+    /// minimum travel time, which is a lower bound for all departure times
+    pub fn min(&self) -> f64 {
+        self.knots.iter().map(|k| k.1).fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.knots.iter().map(|k| k.1).fold(0.0, f64::max)
+    }
+
+    /// knots over a closed day `0..=DAY_SECONDS`, so each pair of consecutive knots is a linear
+    /// piece without wrapping
+    fn day_knots(&self) -> Vec<(f64, f64)> {
+        let mut knots = Vec::with_capacity(self.knots.len() + 2);
+        if self.knots[0].0 > 0.0 {
+            knots.push((0.0, self.eval(0.0)));
+        }
+        knots.extend_from_slice(&self.knots);
+        knots.push((DAY_SECONDS, self.eval(0.0)));
+        knots
+    }
+
+    fn is_fifo(&self) -> bool {
+        self.day_knots()
+            .windows(2)
+            .all(|w| w[1].0 + w[1].1 >= w[0].0 + w[0].1 - EPSILON)
+    }
+
+    /// profile of traveling this and then `next`, i.e. `f(t) + next(t + f(t))`
+    pub fn link(&self, next: &TravelTimeProfile) -> TravelTimeProfile {
+        if self.is_constant() && next.is_constant() {
+            return Self::constant(self.knots[0].1 + next.knots[0].1);
+        }
+
+        // the result is linear between knots of `self` and departure times arriving at knots of
+        // `next`, as arrival time is monotone by FIFO property
+        let day_knots = self.day_knots();
+        let mut times = day_knots.iter().map(|k| k.0).collect::<Vec<_>>();
+        if !next.is_constant() {
+            for w in day_knots.windows(2) {
+                let (t0, a0) = (w[0].0, w[0].0 + w[0].1);
+                let (t1, a1) = (w[1].0, w[1].0 + w[1].1);
+                if a1 - a0 < EPSILON {
+                    continue;
+                }
+                let first_day = (a0 / DAY_SECONDS).floor() as i64;
+                let last_day = (a1 / DAY_SECONDS).floor() as i64;
+                for day in first_day..=last_day {
+                    let offset = day as f64 * DAY_SECONDS;
+                    let start = next.knots.partition_point(|k| k.0 + offset <= a0);
+                    for (k, _) in next.knots[start..].iter() {
+                        let a = k + offset;
+                        if a >= a1 {
+                            break;
+                        }
+                        times.push(t0 + (a - a0) * (t1 - t0) / (a1 - a0));
+                    }
+                }
+            }
+        }
+        Self::sample(times, |t| {
+            let v = self.eval(t);
+            v + next.eval(t + v)
+        })
+    }
+
+    /// lower envelope of this and `other`
+    pub fn merge_min(&self, other: &TravelTimeProfile) -> TravelTimeProfile {
+        if self.min() >= other.max() {
+            return other.clone();
+        }
+        if other.min() >= self.max() {
+            return self.clone();
+        }
+
+        // both are linear between union of knots, so they cross at most once there
+        let mut times = self
+            .day_knots()
+            .into_iter()
+            .chain(other.day_knots())
+            .map(|k| k.0)
+            .collect::<Vec<_>>();
+        times.sort_by(|a, b| a.total_cmp(b));
+        times.dedup_by(|b, a| *b - *a < EPSILON);
+
+        let values = times
+            .iter()
+            .map(|&t| (t, self.eval(t), other.eval(t)))
+            .collect::<Vec<_>>();
+        let mut knots = Vec::with_capacity(values.len() * 2);
+        for (i, &(t0, a0, b0)) in values.iter().enumerate() {
+            knots.push((t0, f64::min(a0, b0)));
+            if let Some(&(t1, a1, b1)) = values.get(i + 1) {
+                let (d0, d1) = (a0 - b0, a1 - b1);
+                if (d0 < -EPSILON && d1 > EPSILON) || (d0 > EPSILON && d1 < -EPSILON) {
+                    let r = d0 / (d0 - d1);
+                    knots.push((t0 + r * (t1 - t0), a0 + r * (a1 - a0)));
+                }
+            }
+        }
+        Self::simplify(knots)
+    }
+
+    /// profile with knots at `times` within a closed day
+    fn sample<F>(mut times: Vec<f64>, f: F) -> TravelTimeProfile
+    where
+        F: Fn(f64) -> f64,
+    {
+        times.sort_by(|a, b| a.total_cmp(b));
+        times.dedup_by(|b, a| *b - *a < EPSILON);
+        Self::simplify(times.into_iter().map(|t| (t, f(t))).collect())
+    }
+
+    /// profile from knots sorted by time within a closed day, dropping knots on a line
+    fn simplify(mut knots: Vec<(f64, f64)>) -> TravelTimeProfile {
+        knots.retain(|k| (0.0..DAY_SECONDS).contains(&k.0));
+        let len = knots.len();
+        let mut simplified = Vec::with_capacity(len);
+        for (i, &(t, v)) in knots.iter().enumerate() {
+            let (t0, v0) = match simplified.last() {
+                Some(&k) => k,
+                None => {
+                    simplified.push((t, v));
+                    continue;
+                }
+            };
+            if t - t0 < EPSILON {
+                continue;
+            }
+            let (t1, v1) = if i + 1 < len {
+                knots[i + 1]
+            } else {
+                (knots[0].0 + DAY_SECONDS, knots[0].1)
+            };
+            let expected = v0 + (v1 - v0) * (t - t0) / (t1 - t0);
+            if (expected - v).abs() > EPSILON {
+                simplified.push((t, v));
+            }
+        }
+        if simplified
+            .iter()
+            .all(|k| (k.1 - simplified[0].1).abs() < EPSILON)
+        {
+            return Self::constant(simplified[0].1);
+        }
+        Self { knots: simplified }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, t: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "t={}: {} != {}",
+            t,
+            actual,
+            expected
+        );
+    }
+
+    /// departure times over two days, including knots of `profiles`
+    fn sample_times(profiles: &[&TravelTimeProfile]) -> Vec<f64> {
+        let mut times = (0..2000).map(|i| i as f64 * 86.4).collect::<Vec<_>>();
+        for p in profiles {
+            times.extend(p.knots().iter().flat_map(|k| [k.0, k.0 + DAY_SECONDS]));
+        }
+        times
+    }
+
+    #[test]
+    fn rejects_non_fifo() {
+        assert!(TravelTimeProfile::from_knots(vec![(0.0, 100.0), (10.0, 10.0)]).is_err());
+        // violated between the last knot and the first knot of the next day
+        assert!(TravelTimeProfile::from_knots(vec![(100.0, 10.0), (86000.0, 1000.0)]).is_err());
+        assert!(TravelTimeProfile::from_knots(vec![(0.0, 100.0), (100.0, 10.0)]).is_ok());
+    }
+
+    #[test]
+    fn link_matches_pointwise() {
+        // `f` crosses midnight with long travel times, so `g` is evaluated on the next day
+        let f =
+            TravelTimeProfile::from_knots(vec![(0.0, 600.0), (80000.0, 3000.0), (85000.0, 1200.0)])
+                .unwrap();
+        let g = TravelTimeProfile::from_knots(vec![(0.0, 100.0), (3600.0, 900.0), (7200.0, 100.0)])
+            .unwrap();
+        let c = TravelTimeProfile::constant(30.0);
+
+        for (first, next) in [(&f, &g), (&g, &f), (&f, &c), (&c, &g)] {
+            let linked = first.link(next);
+            for t in sample_times(&[first, next, &linked]) {
+                let v = first.eval(t);
+                assert_close(linked.eval(t), v + next.eval(t + v), t);
+            }
+        }
+    }
+
+    #[test]
+    fn merge_min_crossing() {
+        // `a` rises from 100 to 300 until noon and falls back, so it crosses `b` at 6h and 18h
+        let a = TravelTimeProfile::from_knots(vec![(0.0, 100.0), (43200.0, 300.0)]).unwrap();
+        let b = TravelTimeProfile::constant(200.0);
+        let merged = a.merge_min(&b);
+        assert_eq!(merged, b.merge_min(&a));
+        for t in [21600.0, 64800.0] {
+            assert!(merged.knots().iter().any(|k| (k.0 - t).abs() < 1e-6));
+        }
+        for t in sample_times(&[&a, &b, &merged]) {
+            assert_close(merged.eval(t), f64::min(a.eval(t), b.eval(t)), t);
+        }
+
+        // no crossing, one side is taken as it is
+        let c = TravelTimeProfile::constant(50.0);
+        assert_eq!(a.merge_min(&c), c);
+        assert_eq!(c.merge_min(&a), c);
+    }
+}
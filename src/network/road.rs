@@ -1,4 +1,6 @@
+use anyhow::{bail, Context};
 use rayon::prelude::*;
+use std::sync::atomic::{self, AtomicUsize};
 
 use super::*;
 
@@ -59,8 +61,16 @@ pub struct Link {
     #[serde(rename = "k_control")]
     control: u8,
 
+    /// road class, may be missing
+    #[serde(default)]
+    road_class: Option<u8>,
+
     #[serde(skip)]
     reversed: bool,
+
+    /// travel time by departure time, loaded from `profile.csv` if exists
+    #[serde(skip)]
+    profile: Option<TravelTimeProfile>,
 }
 
 impl Link {
@@ -95,7 +105,11 @@ impl Link {
             pass_code: self.pass_code,
             control: self.control,
 
+            road_class: self.road_class,
+
             reversed: !self.reversed,
+
+            profile: self.profile.clone(),
         })
     }
 
@@ -111,6 +125,47 @@ impl Link {
         NodeKey::new(self.mesh_id, self.snode_id)
     }
 
+    /// max speed in km/h, 40 if unknown
+    fn max_speed(&self) -> u8 {
+        self.speed_limit().unwrap_or(DEFAULT_MAX_SPEED)
+    }
+
+    /// seconds to pass the link
+    fn time(&self) -> u32 {
+        travel_time(self.length, self.max_speed())
+    }
+
+    /// length in meters
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// max speed in km/h, None if unknown
+    pub fn speed_limit(&self) -> Option<u8> {
+        match self.max_speed {
+            0 => None,
+            speed => Some(speed),
+        }
+    }
+
+    pub fn pass_code(&self) -> u8 {
+        self.pass_code
+    }
+
+    /// `k_control` after normalized, see fields of `Link`
+    pub fn control(&self) -> u8 {
+        self.control
+    }
+
+    pub fn road_class(&self) -> Option<u8> {
+        self.road_class
+    }
+
+    /// travel time by departure time, None if the link has no profile in `profile.csv`
+    pub fn profile(&self) -> Option<&TravelTimeProfile> {
+        self.profile.as_ref()
+    }
+
     fn link_key(&self) -> LinkKey {
         LinkKey {
             mesh_id: self.mesh_id,
@@ -138,6 +193,110 @@ impl Node {
     }
 }
 
+/// turn from `from_link` to `to_link` at `via_node`. `from_link` and `via_node` are in `mesh`,
+/// while `to_link` may be in the adjacent mesh of an edge node. A U-turn is allowed unless it is
+/// prohibited with `from_link` equal to `to_link`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Turn {
+    #[serde(rename = "mesh")]
+    mesh_id: u32,
+    from_link: u32,
+    via_node: u32,
+    to_link: u32,
+    /// mesh of `to_link`, same as `mesh` if empty
+    #[serde(default)]
+    to_mesh: Option<u32>,
+    // 0 (penalty only), 1 (prohibited), 2 (mandatory, other turns from `from_link` are denied)
+    kind: u8,
+    /// seconds added to cost of the turn, may be empty
+    #[serde(default)]
+    penalty: Option<u32>,
+}
+
+/// a knot of travel time profile of a link, keyed by `mid`, or by `mesh` and `link_id`. Without
+/// `node_id` the profile applies to both directions of the link, otherwise to the direction
+/// starting from `node_id`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProfileKnot {
+    #[serde(default)]
+    mid: Option<u64>,
+    #[serde(default, rename = "mesh")]
+    mesh_id: Option<u32>,
+    #[serde(default)]
+    link_id: Option<u32>,
+    #[serde(default)]
+    node_id: Option<u32>,
+    /// departure time of day in seconds
+    time: f64,
+    /// travel time of the link in seconds
+    travel_time: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum ProfileKey {
+    Mid(u64),
+    Link(u32, u32, Option<u32>),
+}
+
+impl ProfileKnot {
+    fn key(&self) -> Result<ProfileKey> {
+        match (self.mesh_id, self.link_id, self.mid) {
+            (Some(mesh_id), Some(link_id), _) => {
+                Ok(ProfileKey::Link(mesh_id, link_id, self.node_id))
+            }
+            (None, None, Some(mid)) => Ok(ProfileKey::Mid(mid)),
+            _ => bail!("invalid key of profile: {:?}", self),
+        }
+    }
+}
+
+/// load profiles from `path` and set them to `links`. A profile for a direction takes
+/// precedence over one for the link.
+fn apply_profiles(links: &mut [Link], path: &Path) -> Result<usize> {
+    let knots: Vec<ProfileKnot> = decode_csv(path)?;
+    let mut grouped: HashMap<ProfileKey, Vec<(f64, f64)>> = HashMap::new();
+    for knot in knots.into_iter() {
+        grouped
+            .entry(knot.key()?)
+            .or_default()
+            .push((knot.time, knot.travel_time));
+    }
+    let mut profiles = HashMap::with_capacity(grouped.len());
+    for (key, knots) in grouped.into_iter() {
+        let profile = TravelTimeProfile::from_knots(knots)
+            .with_context(|| format!("invalid profile of {:?}", key))?;
+        profiles.insert(key, profile);
+    }
+
+    let mut count = 0;
+    for link in links.iter_mut() {
+        let keys = [
+            ProfileKey::Link(link.mesh_id, link.link_id, Some(link.snode_id)),
+            ProfileKey::Link(link.mesh_id, link.link_id, None),
+            ProfileKey::Mid(link.mid),
+        ];
+        if let Some(profile) = keys.iter().find_map(|k| profiles.get(k)) {
+            link.profile = Some(profile.clone());
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+const TURN_PENALTY: u8 = 0;
+const TURN_PROHIBITED: u8 = 1;
+const TURN_MANDATORY: u8 = 2;
+
+impl Turn {
+    fn via_key(&self) -> NodeKey {
+        NodeKey::new(self.mesh_id, self.via_node)
+    }
+
+    fn matches(&self, link: &Link) -> bool {
+        self.to_mesh.unwrap_or(self.mesh_id) == link.mesh_id && self.to_link == link.link_id
+    }
+}
+
 pub struct Network {
     pub links: Vec<Link>,
     pub nodes: Vec<Node>,
@@ -147,6 +306,8 @@ pub struct Network {
     pub node_map: HashMap<NodeKey, usize>,
     pub link_map: HashMap<LinkKey, usize>,
     pub links_map: HashMap<NodeKey, Range<usize>>,
+    /// turns by via node and `from_link`, loaded from `turn.csv` if exists
+    pub turns: HashMap<(NodeKey, u32), Vec<Turn>>,
 }
 
 type Neighbor<'a> = &'a Link;
@@ -214,7 +375,7 @@ impl Network {
             let mut edge_nodes = nodes
                 .iter()
                 .filter(|node| node.edge_mesh_id != 0)
-                .map(|node| node.clone())
+                .cloned()
                 .collect::<Vec<_>>();
             edge_nodes
                 .as_mut_slice()
@@ -222,11 +383,37 @@ impl Network {
             edge_nodes
         };
 
+        let profile_path = path.join("profile.csv");
+        let profiles = if profile_path.exists() {
+            apply_profiles(&mut links, &profile_path)?
+        } else {
+            0
+        };
+
+        let turn_path = path.join("turn.csv");
+        let turn_list: Vec<Turn> = if turn_path.exists() {
+            decode_csv(turn_path)?
+        } else {
+            Vec::new()
+        };
+        let mut turns: HashMap<(NodeKey, u32), Vec<Turn>> = HashMap::new();
+        for turn in turn_list.into_iter() {
+            if turn.kind > TURN_MANDATORY {
+                bail!("invalid turn kind: {:?}", turn);
+            }
+            turns
+                .entry((turn.via_key(), turn.from_link))
+                .or_default()
+                .push(turn);
+        }
+
         eprintln!(
-            "links: {}, nodes: {}, edge_nodes:{}",
+            "links: {}, nodes: {}, edge_nodes:{}, turns: {}, profiles: {}",
             links.len(),
             nodes.len(),
             edge_nodes.len(),
+            turns.values().map(Vec::len).sum::<usize>(),
+            profiles,
         );
 
         //TODO: validate uniqueness
@@ -238,6 +425,7 @@ impl Network {
             node_map,
             link_map,
             links_map,
+            turns,
         })
     }
 
@@ -248,9 +436,101 @@ impl Network {
         }
     }
 
-    /// find all connected nodes
-    fn next_links(&self, src: NodeKey, link_id: u32) -> Vec<Neighbor> {
-        let mut nodes = self.mesh_next_links(src, link_id);
+    /// map path of links found by `search` to route, see `RouteItem` for times and costs. None
+    /// if consecutive links of `path` are not connected on this network or on `search`.
+    pub fn route<S>(&self, search: &S, path: &[IdxNodeKey], cost: u32) -> Option<Route<LinkKey>>
+    where
+        S: ShortestPath + ?Sized,
+    {
+        let costs = path_link_costs(search, path)?;
+        let mut time = 0;
+        let mut items = Vec::with_capacity(path.len());
+        for (i, idx) in path.iter().enumerate() {
+            let link = &self.links[idx.index()];
+            let mut item_cost = 0;
+            if i > 0 {
+                let prev = &self.links[path[i - 1].index()];
+                let key = NodeKey {
+                    mesh_id: prev.mesh_id,
+                    node_id: prev.enode_id,
+                };
+                let (_, penalty) = self
+                    .next_links(key, prev.link_id)
+                    .into_iter()
+                    .find(|(next, _)| next.link_key() == link.link_key())?;
+                time += penalty + link.time();
+                item_cost = costs[i - 1];
+            }
+            items.push(RouteItem {
+                key: link.link_key(),
+                length: link.length,
+                max_speed: link.max_speed(),
+                time,
+                cost: item_cost,
+            });
+        }
+        Some(Route { items, cost })
+    }
+
+    /// same as `route` for a path of time-dependent search on `graph`, built by `to_td_graph`.
+    /// Times follow `TdGraph::search`, where the first link is left at `departure`, and costs
+    /// are times of each link. None if `path` is not a path of `graph`.
+    pub fn route_at(
+        &self,
+        graph: &TdGraph,
+        path: &[IdxNodeKey],
+        departure: f64,
+    ) -> Option<Route<LinkKey>> {
+        let times = graph.path_times(path, departure)?;
+        let mut prev_time = 0;
+        let items = path
+            .iter()
+            .zip(times.iter())
+            .map(|(idx, time)| {
+                let link = &self.links[idx.index()];
+                let time = (time - departure).round() as u32;
+                let cost = time - prev_time;
+                prev_time = time;
+                RouteItem {
+                    key: link.link_key(),
+                    length: link.length,
+                    max_speed: link.max_speed(),
+                    time,
+                    cost,
+                }
+            })
+            .collect::<Vec<_>>();
+        let cost = items.last().map_or(0, |item| item.time);
+        Some(Route { items, cost })
+    }
+
+    /// search route from `src` link to `dst` link with `search`, which is built from this network
+    pub fn search_route<S>(
+        &self,
+        search: &S,
+        src: LinkKey,
+        dst: LinkKey,
+    ) -> Result<Option<Route<LinkKey>>>
+    where
+        S: ShortestPath + ?Sized,
+    {
+        let (src, dst) = match (self.link_map.get(&src), self.link_map.get(&dst)) {
+            (Some(src), Some(dst)) => (IdxNodeKey::new(*src), IdxNodeKey::new(*dst)),
+            (None, _) => bail!("unknown link_key: {:?}", src),
+            (_, None) => bail!("unknown link_key: {:?}", dst),
+        };
+        match search.shortest_path(src, dst) {
+            Some((path, cost)) => match self.route(search, &path, cost) {
+                Some(route) => Ok(Some(route)),
+                None => bail!("path is not on network, search should be built from it"),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// find all connected links from `link_id` link ending at `src`, with turn penalties
+    fn next_links(&self, src: NodeKey, link_id: u32) -> Vec<(Neighbor<'_>, u32)> {
+        let mut nodes = self.mesh_next_links(src);
 
         // find nodes on other meshes
         let mesh_node = self
@@ -261,16 +541,39 @@ impl Network {
             let node = &self.edge_nodes[idx];
             //TODO: move to Node
             let other_src = NodeKey::new(node.edge_mesh_id, node.edge_node_id);
-            //TODO: link_id?
-            let mut other_nodes = self.mesh_next_links(other_src, std::u32::MAX);
+            let mut other_nodes = self.mesh_next_links(other_src);
             nodes.append(&mut other_nodes);
         }
-        nodes
+        self.apply_turns(src, link_id, nodes)
+    }
+
+    /// filter links following `link_id` link at `via` by turns, and add penalties of turns
+    fn apply_turns<'a>(
+        &'a self,
+        via: NodeKey,
+        link_id: u32,
+        links: Vec<Neighbor<'a>>,
+    ) -> Vec<(Neighbor<'a>, u32)> {
+        let turns = match self.turns.get(&(via, link_id)) {
+            Some(turns) => turns,
+            None => return links.into_iter().map(|link| (link, 0)).collect(),
+        };
+        let mandatory = turns.iter().any(|t| t.kind == TURN_MANDATORY);
+
+        links
+            .into_iter()
+            .filter_map(|link| match turns.iter().find(|t| t.matches(link)) {
+                Some(turn) if turn.kind == TURN_PROHIBITED => None,
+                Some(turn) if mandatory && turn.kind == TURN_PENALTY => None,
+                Some(turn) => Some((link, turn.penalty.unwrap_or(0))),
+                None if mandatory => None,
+                None => Some((link, 0)),
+            })
+            .collect()
     }
 
-    /// find all connected links within same mesh, with `link_id` link starting from `src` as
-    /// `snode_id`
-    fn mesh_next_links(&self, src: NodeKey, _link_id: u32) -> Vec<Neighbor> {
+    /// find all passable links within same mesh starting from `src` as `snode_id`
+    fn mesh_next_links(&self, src: NodeKey) -> Vec<Neighbor<'_>> {
         let links = self.links.as_slice();
         let range = self.links_map.get(&src).cloned().unwrap_or(0..0);
 
@@ -279,7 +582,6 @@ impl Network {
         for link in links {
             assert!(link.mesh_id == src.mesh_id);
             assert!(link.snode_id == src.node_id);
-            //TODO: use link_id
             if !link.passable() {
                 continue;
             }
@@ -290,9 +592,11 @@ impl Network {
     }
 }
 
-impl<'a> From<&'a Network> for Graph {
-    fn from(network: &'a Network) -> Self {
-        let links = network.links.as_slice();
+impl Network {
+    /// build link-based graph, where cost of a link is the cost of moving to the link
+    pub fn to_graph(&self, model: &dyn CostModel) -> Graph {
+        let links = self.links.as_slice();
+        let overflows = AtomicUsize::new(0);
 
         let idx_links = links
             .par_iter()
@@ -303,26 +607,241 @@ impl<'a> From<&'a Network> for Graph {
                     node_id: link.enode_id,
                 };
 
-                let next_links = network.next_links(key, link.link_id);
+                let next_links = self.next_links(key, link.link_id);
                 next_links
                     .into_iter()
-                    .map(|nearby_link| {
+                    .filter_map(|(nearby_link, penalty)| {
+                        let cost = model.cost(nearby_link)?;
                         let nearby_link_key = nearby_link.link_key();
-                        let idx = network.link_key_to_idx(nearby_link_key);
+                        let idx = self.link_key_to_idx(nearby_link_key);
+                        let cost = cost.saturating_add(model.turn_cost(penalty));
+                        if cost > MAX_COST {
+                            overflows.fetch_add(1, atomic::Ordering::Relaxed);
+                        }
+                        let link = IdxLink::new(idx, cost, IdxLinkDir::Forward);
+                        Some((link, nearby_link.length))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        report_overflow(overflows.into_inner());
+        // length is carried as secondary metric, so routes report distance with any cost model
+        Graph::from_links_with_secondary(idx_links)
+    }
+
+    /// build time-dependent link-based graph. Links with profile take travel time from the
+    /// profile, others from `model`, so `model` should be in seconds, e.g. `Fastest`. Turn
+    /// penalties are added before entering the link.
+    pub fn to_td_graph(&self, model: &dyn CostModel) -> TdGraph {
+        let links = self
+            .links
+            .par_iter()
+            .map(|link| {
+                let key = NodeKey {
+                    mesh_id: link.mesh_id,
+                    node_id: link.enode_id,
+                };
 
-                        let len = nearby_link.length;
-                        let max_speed = match nearby_link.max_speed {
-                            0 => 40,
-                            speed => speed,
+                self.next_links(key, link.link_id)
+                    .into_iter()
+                    .filter_map(|(nearby_link, penalty)| {
+                        let cost = model.cost(nearby_link)?;
+                        let profile = match nearby_link.profile {
+                            Some(ref profile) => profile.clone(),
+                            None => TravelTimeProfile::constant(cost as f64),
                         };
-                        // seconds = len / (max_speed / 3.6)
-                        let cost = (len as f32 * 3.6 / max_speed as f32) as u32;
-                        IdxLink::new(idx, cost, IdxLinkDir::Forward)
+                        let turn_cost = model.turn_cost(penalty);
+                        let profile = if turn_cost > 0 {
+                            TravelTimeProfile::constant(turn_cost as f64).link(&profile)
+                        } else {
+                            profile
+                        };
+                        Some(TdLink {
+                            enode_idx: self.link_key_to_idx(nearby_link.link_key()),
+                            profile,
+                        })
                     })
                     .collect::<Vec<_>>()
             })
             .collect::<Vec<_>>();
+        TdGraph::from_links(links)
+    }
+}
+
+impl<'a> From<&'a Network> for Graph {
+    fn from(network: &'a Network) -> Self {
+        network.to_graph(&Fastest::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    /// two meshes joined at node 2 of mesh 1 and node 5 of mesh 2, with `turns` as `turn.csv`
+    fn network(name: &str, turns: &str) -> Network {
+        let turns = format!(
+            "mesh,from_link,via_node,to_link,kind,penalty,to_mesh\n{}",
+            turns
+        );
+        let path = testutil::network_dir(
+            name,
+            &[
+                (
+                    "link.csv",
+                    "mid,mesh,link_id,snode_id,enode_id,link_l,max_speed,pass_code,k_control\n\
+                     1,1,10,1,2,100,36,1,1\n\
+                     2,1,11,2,3,200,36,1,1\n\
+                     3,2,20,5,6,300,36,1,1\n",
+                ),
+                (
+                    "node.csv",
+                    "mid,mesh,node_id,edge_mesh,edge_node\n\
+                     1,1,1,0,0\n\
+                     2,1,2,2,5\n\
+                     3,1,3,0,0\n\
+                     5,2,5,1,2\n\
+                     6,2,6,0,0\n",
+                ),
+                ("turn.csv", &turns),
+            ],
+        );
+        Network::from_path(&path).unwrap()
+    }
+
+    /// (mesh, link_id, penalty) of links following link 10 at node 2 of mesh 1
+    fn next_links(network: &Network) -> Vec<(u32, u32, u32)> {
+        let mut out = network
+            .next_links(NodeKey::new(1, 2), 10)
+            .into_iter()
+            .map(|(link, penalty)| (link.mesh_id, link.link_id, penalty))
+            .collect::<Vec<_>>();
+        out.sort_unstable();
+        out
+    }
+
+    #[test]
+    fn turns_across_meshes() {
+        let all = vec![(1, 10, 0), (1, 11, 0), (2, 20, 0)];
+        assert_eq!(next_links(&network("turn-none", "")), all);
+
+        // turn without `to_mesh` targets the link in the mesh of the via node
+        let turns = "1,10,2,20,1,,\n";
+        assert_eq!(next_links(&network("turn-same-mesh", turns)), all);
+
+        let turns = "1,10,2,20,1,,2\n";
+        let expected = vec![(1, 10, 0), (1, 11, 0)];
+        assert_eq!(next_links(&network("turn-prohibited", turns)), expected);
+
+        let turns = "1,10,2,10,1,,\n1,10,2,20,0,5,2\n";
+        let expected = vec![(1, 11, 0), (2, 20, 5)];
+        assert_eq!(next_links(&network("turn-u-turn", turns)), expected);
+
+        let turns = "1,10,2,20,2,3,2\n";
+        assert_eq!(
+            next_links(&network("turn-mandatory", turns)),
+            vec![(2, 20, 3)]
+        );
+    }
+
+    #[test]
+    fn route_at_follows_td_search() {
+        let path = testutil::network_dir(
+            "route-at",
+            &[
+                (
+                    "link.csv",
+                    "mid,mesh,link_id,snode_id,enode_id,link_l,max_speed,pass_code,k_control\n\
+                     1,1,10,1,2,100,36,1,3\n\
+                     2,1,11,2,3,200,36,1,3\n\
+                     3,1,12,3,4,300,72,1,3\n",
+                ),
+                (
+                    "node.csv",
+                    "mid,mesh,node_id,edge_mesh,edge_node\n\
+                     1,1,1,0,0\n2,1,2,0,0\n3,1,3,0,0\n4,1,4,0,0\n",
+                ),
+                (
+                    "turn.csv",
+                    "mesh,from_link,via_node,to_link,kind,penalty\n1,10,2,11,0,10\n",
+                ),
+                (
+                    "profile.csv",
+                    "mid,mesh,link_id,node_id,time,travel_time\n\
+                     2,,,,0,20\n2,,,,28800,200\n2,,,,36000,20\n",
+                ),
+            ],
+        );
+        let network = Network::from_path(&path).unwrap();
+        let graph = network.to_td_graph(&Fastest::default());
+        let idx = network.link_key_to_idx(LinkKey::new(1, 11, 2));
+        let profile = network.links[idx.index()].profile().unwrap().clone();
+
+        let src = network.link_key_to_idx(LinkKey::new(1, 10, 1));
+        let dst = network.link_key_to_idx(LinkKey::new(1, 12, 3));
+        for departure in [0.0, 28790.0, 30000.0, 86395.0] {
+            let (path, arrival) = graph.search(src, dst, departure).unwrap();
+            let route = network.route_at(&graph, &path, departure).unwrap();
+
+            // link 11 is entered after the turn penalty, and link 12 takes 15 seconds
+            let time = 10.0 + profile.eval(departure + 10.0);
+            let times = route.items.iter().map(|item| item.time).collect::<Vec<_>>();
+            let expected = vec![0, time.round() as u32, (time + 15.0).round() as u32];
+            assert_eq!(times, expected, "departure={}", departure);
+            assert_eq!(route.cost, (arrival - departure).round() as u32);
+            assert_eq!(route.time(), route.cost);
+        }
+    }
+
+    #[test]
+    fn search_route_keys_and_length() {
+        // entering link 20 of the other mesh costs 5 seconds of penalty
+        let network = network("route", "1,10,2,20,0,5,2\n");
+        let graph = network.to_graph(&Fastest::default());
+        let mut ch = CH::new(&graph);
+        ch.build();
+        let query = ch.to_query();
+        let searches: [&dyn ShortestPath; 3] = [&graph, &ch, &query];
+
+        let src = LinkKey::new(1, 10, 1);
+        for search in searches {
+            let route = network
+                .search_route(search, src, LinkKey::new(2, 20, 5))
+                .unwrap()
+                .unwrap();
+            let keys = route.items.iter().map(|item| item.key).collect::<Vec<_>>();
+            assert_eq!(keys, vec![src, LinkKey::new(2, 20, 5)]);
+            // the first link is excluded as search starts at its end
+            assert_eq!(route.length(), 300);
+            let times = route.items.iter().map(|item| item.time).collect::<Vec<_>>();
+            assert_eq!(times, vec![0, 35]);
+            let costs = route.items.iter().map(|item| item.cost).collect::<Vec<_>>();
+            assert_eq!(costs, vec![0, route.cost]);
+            assert_eq!(route.cost, 35);
+
+            let route = network
+                .search_route(search, src, LinkKey::new(1, 11, 2))
+                .unwrap()
+                .unwrap();
+            assert_eq!(route.items.len(), 2);
+            assert_eq!(route.length(), 200);
+            assert_eq!(route.time(), 20);
+
+            let route = network.search_route(search, src, src).unwrap().unwrap();
+            assert_eq!(route.items.len(), 1);
+            assert_eq!((route.length(), route.time(), route.cost), (0, 0, 0));
+        }
 
-        Self::from_links(idx_links)
+        // link 11 does not lead to link 10
+        let path = [
+            network.link_key_to_idx(LinkKey::new(1, 11, 2)),
+            network.link_key_to_idx(src),
+        ];
+        assert!(network.route(&graph, &path, 0).is_none());
+        assert!(network
+            .search_route(&graph, src, LinkKey::new(9, 9, 9))
+            .is_err());
     }
 }
@@ -0,0 +1,100 @@
+use super::*;
+
+/// an item of `Route`: a link for road network, a node for walk network
+#[derive(Clone, Debug)]
+pub struct RouteItem<K> {
+    pub key: K,
+    /// length of the link in meters. For walk network, length of the link reaching the node, so
+    /// 0 for the first node.
+    pub length: u32,
+    /// max speed in km/h used to calculate time
+    pub max_speed: u8,
+    /// cumulative travel time in seconds from the start of route to the end of the item,
+    /// including turn penalties. Route starts where search does, at the end of the first link
+    /// for road network and at the first node for walk network, so it is 0 for the first item.
+    pub time: u32,
+    /// cost of reaching the item from the previous one on the searched graph, 0 for the first
+    /// item. Costs of items sum to `Route::cost`.
+    pub cost: u32,
+}
+
+/// path of a query mapped to keys of original network
+#[derive(Clone, Debug)]
+pub struct Route<K> {
+    pub items: Vec<RouteItem<K>>,
+    /// cost of the path reported by search
+    pub cost: u32,
+}
+
+impl<K> Route<K> {
+    /// total length in meters from the start of route, so the first link of road network is
+    /// excluded as in `RouteItem::time`
+    pub fn length(&self) -> u32 {
+        self.items.iter().skip(1).map(|item| item.length).sum()
+    }
+
+    /// total travel time in seconds
+    pub fn time(&self) -> u32 {
+        self.items.last().map(|item| item.time).unwrap_or(0)
+    }
+}
+
+/// seconds to travel `length` meters with `max_speed` km/h
+pub(crate) fn travel_time(length: u32, max_speed: u8) -> u32 {
+    (length as f32 * 3.6 / max_speed as f32) as u32
+}
+
+/// shortest path search between `IdxNodeKey`s, used to search `Route`s
+pub trait ShortestPath {
+    fn shortest_path(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u32)>;
+
+    /// cost of the cheapest link from `snode_idx` to `enode_idx`, which are consecutive nodes of
+    /// a path of `shortest_path`. None if there is no such link.
+    fn link_cost(&self, snode_idx: IdxNodeKey, enode_idx: IdxNodeKey) -> Option<u32>;
+}
+
+impl ShortestPath for Graph {
+    fn shortest_path(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u32)> {
+        self.search(src, dst)
+    }
+
+    fn link_cost(&self, snode_idx: IdxNodeKey, enode_idx: IdxNodeKey) -> Option<u32> {
+        self.idx_links
+            .dir_links(snode_idx.index(), IdxLinkDir::Forward)
+            .iter()
+            .filter(|l| l.enode_idx == enode_idx)
+            .map(|l| l.cost())
+            .min()
+    }
+}
+
+impl<'a> ShortestPath for CH<'a> {
+    fn shortest_path(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u32)> {
+        self.search(src, dst)
+    }
+
+    fn link_cost(&self, snode_idx: IdxNodeKey, enode_idx: IdxNodeKey) -> Option<u32> {
+        self.hierarchy().link_cost(snode_idx, enode_idx)
+    }
+}
+
+impl ShortestPath for CHQuery {
+    fn shortest_path(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u32)> {
+        self.search(src, dst)
+    }
+
+    fn link_cost(&self, snode_idx: IdxNodeKey, enode_idx: IdxNodeKey) -> Option<u32> {
+        let (snode_idx, enode_idx) = (self.inner_key(snode_idx), self.inner_key(enode_idx));
+        self.hierarchy().link_cost(snode_idx, enode_idx)
+    }
+}
+
+/// costs of links between consecutive nodes of `path`, None if a link is missing
+pub(crate) fn path_link_costs<S>(search: &S, path: &[IdxNodeKey]) -> Option<Vec<u32>>
+where
+    S: ShortestPath + ?Sized,
+{
+    path.windows(2)
+        .map(|pair| search.link_cost(pair[0], pair[1]))
+        .collect()
+}
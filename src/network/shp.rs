@@ -32,68 +32,54 @@ fn km_to_angle(km: f64) -> Angle {
     Angle::from(Rad(km / 6371.01))
 }
 
-impl Network {
-    pub fn from_path<P>(p: P) -> Result<Network>
+/// points and edges of a network while loading, see `Builder::add_line`
+#[derive(Default)]
+struct Builder {
+    points: HashSet<CellID>,
+    edges: Vec<(CellID, CellID, u32)>,
+    duplicate_count: usize,
+    total_dist: usize,
+}
+
+impl Builder {
+    /// add edges between consecutive points of a polyline, on both directions unless `oneway`
+    fn add_line<I>(&mut self, line: I, oneway: bool)
     where
-        P: AsRef<Path>,
+        I: IntoIterator<Item = LatLng>,
     {
-        let mut reader = shapefile::Reader::from_path(p)?;
-
-        let mut total_dist = 0usize;
-        let mut record_count = 0;
-        let mut duplicate_count = 0;
-
-        // collect points
-        let mut points = HashSet::new();
-        let mut edges = Vec::new();
-        for result in reader.iter_shapes_and_records() {
-            let (shape, _records) = result?;
-            record_count += 1;
-
-            let oneway = _records
-                .get("oneway")
-                .map(|v| {
-                    if let dbase::FieldValue::Character(Some(c)) = v {
-                        c == "yes"
-                    } else {
-                        false
-                    }
-                })
-                .unwrap_or(false);
-
-            let pl = match shape {
-                shapefile::Shape::Polyline(pl) => pl,
-                _ => todo!(),
-            };
+        let mut last: Option<(CellID, LatLng)> = None;
+        for ll in line {
+            let cell = CellID::from(ll);
+            // measure on the center of the cell, same as `Network::distance`
+            let ll = LatLng::from(cell);
 
-            for part in pl.parts() {
-                let mut last: Option<(CellID, LatLng)> = None;
-                for point in part {
-                    let y = Deg(point.y);
-                    let x = Deg(point.x);
-                    let ll = LatLng::new(Angle::from(y), Angle::from(x));
-                    let cell = CellID::from(ll);
+            if !self.points.insert(cell) {
+                self.duplicate_count += 1;
+            }
+            if let Some((last, last_ll)) = last {
+                // distance in centimeter, rounded up so `Network::distance` is a lower bound
+                let len = angle_to_km(last_ll.distance(&ll)) * 1000.0 * 100.0;
+                let len = len.ceil() as u32;
+                self.total_dist += len as usize;
 
-                    if !points.insert(cell) {
-                        duplicate_count += 1;
-                    }
-                    if let Some((last, last_ll)) = last {
-                        // distance in centimeter
-                        let len = angle_to_km(last_ll.distance(&ll)) * 1000.0 * 100.0;
-                        let len = len as u32;
-                        total_dist += len as usize;
-
-                        if oneway {
-                            edges.push((last, cell, len));
-                        } else {
-                            edges.push((last, cell, len));
-                            edges.push((cell, last, len));
-                        }
-                    }
-                    last = Some((cell, ll));
+                if oneway {
+                    self.edges.push((last, cell, len));
+                } else {
+                    self.edges.push((last, cell, len));
+                    self.edges.push((cell, last, len));
                 }
             }
+            last = Some((cell, ll));
         }
+    }
+
+    fn build(self, record_count: usize) -> Network {
+        let Self {
+            points,
+            edges,
+            duplicate_count,
+            total_dist,
+        } = self;
 
         // sort points
         let points = {
@@ -119,12 +105,57 @@ impl Network {
             points.len(),
             duplicate_count,
             total_dist / (100 * 1000),
-            total_dist / 100 / edges.len(),
+            total_dist / 100 / edges.len().max(1),
         );
 
         edges.sort();
 
-        Ok(Self { points, edges })
+        Network { points, edges }
+    }
+}
+
+impl Network {
+    pub fn from_path<P>(p: P) -> Result<Network>
+    where
+        P: AsRef<Path>,
+    {
+        let mut reader = shapefile::Reader::from_path(p)?;
+
+        let mut record_count = 0;
+
+        // collect points
+        let mut builder = Builder::default();
+        for result in reader.iter_shapes_and_records() {
+            let (shape, _records) = result?;
+            record_count += 1;
+
+            let oneway = _records
+                .get("oneway")
+                .map(|v| {
+                    if let dbase::FieldValue::Character(Some(c)) = v {
+                        c == "yes"
+                    } else {
+                        false
+                    }
+                })
+                .unwrap_or(false);
+
+            let pl = match shape {
+                shapefile::Shape::Polyline(pl) => pl,
+                _ => todo!(),
+            };
+
+            for part in pl.parts() {
+                let line = part.iter().map(|point| {
+                    let y = Deg(point.y);
+                    let x = Deg(point.x);
+                    LatLng::new(Angle::from(y), Angle::from(x))
+                });
+                builder.add_line(line, oneway);
+            }
+        }
+
+        Ok(builder.build(record_count))
     }
 
     pub fn nearest(&self, lat: f64, lng: f64, dist_km: f64) -> Option<u32> {
@@ -144,10 +175,8 @@ impl Network {
         let cu = cov.covering(&cap);
         for cell_id in &cu.0 {
             let level = cell_id.level();
-            let r = self
-                .points
-                .equal_range_by(|p| p.parent(level).cmp(&cell_id));
-            if r.len() > 0 {
+            let r = self.points.equal_range_by(|p| p.parent(level).cmp(cell_id));
+            if !r.is_empty() {
                 return Some(r.start as u32);
             }
         }
@@ -159,6 +188,33 @@ impl Network {
         self.points[id as usize]
     }
 
+    /// s2 `CellID` of each node, as position on a space-filling curve for
+    /// `Renumbering::by_curve`
+    pub fn curve_positions(&self) -> Vec<u64> {
+        self.points.iter().map(|p| p.0).collect()
+    }
+
+    /// great-circle distance between points in centimeter, which is a consistent lower bound of
+    /// cost between them: lengths of edges are measured between the same points, the centers
+    /// of s2 leaf cells, and rounded up. It is scaled down for rounding errors of floating
+    /// point, so the bound holds after rounding down.
+    pub fn distance(&self, a: u32, b: u32) -> u32 {
+        let a = LatLng::from(self.point(a));
+        let b = LatLng::from(self.point(b));
+        let d = angle_to_km(a.distance(&b)) * 1000.0 * 100.0;
+        (d * (1.0 - 1e-6)) as u32
+    }
+
+    /// A* search on `graph` built from the network, with great-circle distance as lower bound
+    pub fn search_astar(
+        &self,
+        graph: &Graph,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+    ) -> Option<(Vec<IdxNodeKey>, u32)> {
+        graph.search_astar(src, dst, |key| self.distance(key.0, dst.0))
+    }
+
     fn links(&self, id: u32) -> &[Edge] {
         let r = self.edges.equal_range_by(|e| e.s.cmp(&id));
         &self.edges[r]
@@ -168,12 +224,14 @@ impl Network {
 impl<'a> From<&'a Network> for Graph {
     fn from(network: &'a Network) -> Self {
         let mut idx_links = Vec::with_capacity(network.points.len());
+        let mut overflows = 0;
 
         for i in 0..network.points.len() {
             let links = network.links(i as u32);
+            overflows += overflow_len(links.iter().map(|link| link.len));
             idx_links.push(
                 links
-                    .into_iter()
+                    .iter()
                     .map(|link| {
                         let idx = IdxNodeKey(link.t);
                         let cost = link.len;
@@ -183,6 +241,87 @@ impl<'a> From<&'a Network> for Graph {
             );
         }
 
+        report_overflow(overflows);
         Self::from_links(idx_links)
     }
 }
+
+impl<'a> From<&'a Network> for WideGraph {
+    fn from(network: &'a Network) -> Self {
+        let links = (0..network.points.len())
+            .map(|i| {
+                network
+                    .links(i as u32)
+                    .iter()
+                    .map(|link| WideLink {
+                        enode_idx: IdxNodeKey(link.t),
+                        cost: link.len as u64,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        Self::from_links(links)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    /// `n` x `n` grid of roads about 100m apart with jittered points, where every third row
+    /// is one-way
+    fn grid_network(n: usize) -> Network {
+        let mut state = 7u64;
+        let mut jitter = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((state >> 33) % 1000) as f64 * 1e-7
+        };
+        let points = (0..n)
+            .map(|y| {
+                (0..n)
+                    .map(|x| {
+                        let lat = 37.5 + y as f64 * 1e-3 + jitter();
+                        let lng = 127.0 + x as f64 * 1e-3 + jitter();
+                        LatLng::new(Angle::from(Deg(lat)), Angle::from(Deg(lng)))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut builder = Builder::default();
+        for (y, row) in points.iter().enumerate() {
+            builder.add_line(row.iter().cloned(), y % 3 == 0);
+        }
+        for x in 0..n {
+            builder.add_line(points.iter().map(|row| row[x]), false);
+        }
+        builder.build(0)
+    }
+
+    #[test]
+    fn astar_and_alt_match_dijkstra() {
+        let network = grid_network(6);
+        let graph = Graph::from(&network);
+        let landmarks = Landmarks::build(&graph, 4, LandmarkSelection::Avoid);
+
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            let expected = graph.search(src, dst).map(|r| r.1);
+            assert!(network.distance(src.0, dst.0) <= expected.unwrap());
+            assert_eq!(
+                network.search_astar(&graph, src, dst).map(|r| r.1),
+                expected,
+                "{:?} -> {:?}",
+                src,
+                dst
+            );
+            assert_eq!(
+                graph.search_alt(&landmarks, src, dst).map(|r| r.1),
+                expected
+            );
+        }
+    }
+}
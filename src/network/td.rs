@@ -0,0 +1,161 @@
+use super::*;
+
+/// link of `TdGraph`, with travel time by departure time
+#[derive(Clone, Debug)]
+pub struct TdLink {
+    pub enode_idx: IdxNodeKey,
+    pub profile: TravelTimeProfile,
+}
+
+/// heap entry of time-dependent searches, popping the earliest arrival first
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TimeEntry {
+    pub time: f64,
+    pub key: IdxNodeKey,
+}
+impl PartialEq for TimeEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for TimeEntry {}
+impl PartialOrd for TimeEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for TimeEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.total_cmp(&self.time)
+    }
+}
+
+/// time-dependent graph, where travel time of each link depends on departure time. Only
+/// forward links are stored.
+pub struct TdGraph {
+    pub node_len: usize,
+    offsets: Vec<u32>,
+    links: Vec<TdLink>,
+}
+
+impl TdGraph {
+    /// build from forward links of each node
+    pub fn from_links(links: Vec<Vec<TdLink>>) -> Self {
+        let node_len = links.len();
+        let mut offsets = Vec::with_capacity(node_len + 1);
+        let mut offset = 0usize;
+        offsets.push(0u32);
+        for v in links.iter() {
+            offset += v.len();
+            assert!(offset <= u32::MAX as usize, "too many links: {}", offset);
+            offsets.push(offset as u32);
+        }
+        Self {
+            node_len,
+            offsets,
+            links: links.concat(),
+        }
+    }
+
+    pub fn link_len(&self) -> usize {
+        self.links.len()
+    }
+
+    /// forward links of `idx`
+    #[inline]
+    pub fn links(&self, idx: usize) -> &[TdLink] {
+        &self.links[self.offsets[idx] as usize..self.offsets[idx + 1] as usize]
+    }
+
+    /// static graph with minimum travel time of each link, rounded down. It has the same
+    /// topology, so it can be used to build `CCH` for `TdCCH`.
+    pub fn lower_bound_graph(&self) -> Graph {
+        let idx_links = (0..self.node_len)
+            .map(|i| {
+                self.links(i)
+                    .iter()
+                    .map(|l| {
+                        let cost = l.profile.min().floor().min(MAX_COST as f64) as u32;
+                        IdxLink::new(l.enode_idx, cost, IdxLinkDir::Forward)
+                    })
+                    .collect()
+            })
+            .collect();
+        Graph::from_links(idx_links)
+    }
+
+    /// time-dependent dijkstra search departing `src` at `departure` seconds. Returns path and
+    /// arrival time at `dst`.
+    pub fn search(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        departure: f64,
+    ) -> Option<(Vec<IdxNodeKey>, f64)> {
+        let mut times = vec![f64::INFINITY; self.node_len];
+        let mut prevs = vec![IdxNodeKey::NONE; self.node_len];
+        let mut heap = BinaryHeap::new();
+
+        times[src.index()] = departure;
+        heap.push(TimeEntry {
+            time: departure,
+            key: src,
+        });
+        while let Some(TimeEntry { time, key }) = heap.pop() {
+            if time > times[key.index()] {
+                continue;
+            }
+            if key == dst {
+                let mut path = vec![dst];
+                let mut cur = dst;
+                while cur != src {
+                    cur = prevs[cur.index()];
+                    path.push(cur);
+                }
+                path.reverse();
+                return Some((path, time));
+            }
+
+            for link in self.links(key.index()) {
+                let next_time = time + link.profile.eval(time);
+                let next = link.enode_idx.index();
+                if next_time < times[next] {
+                    times[next] = next_time;
+                    prevs[next] = key;
+                    heap.push(TimeEntry {
+                        time: next_time,
+                        key: link.enode_idx,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// arrival time of traveling `path` departing at `departure`, None if a link is missing
+    pub fn path_arrival(&self, path: &[IdxNodeKey], departure: f64) -> Option<f64> {
+        let times = self.path_times(path, departure)?;
+        Some(times.last().copied().unwrap_or(departure))
+    }
+
+    /// arrival time at each node of `path` departing at `departure`, starting with `departure`
+    /// at the first node. None if a link is missing.
+    pub fn path_times(&self, path: &[IdxNodeKey], departure: f64) -> Option<Vec<f64>> {
+        let mut times = Vec::with_capacity(path.len());
+        let mut time = departure;
+        if !path.is_empty() {
+            times.push(time);
+        }
+        for w in path.windows(2) {
+            let travel_time = self
+                .links(w[0].index())
+                .iter()
+                .filter(|l| l.enode_idx == w[1])
+                .map(|l| l.profile.eval(time))
+                .min_by(|a, b| a.total_cmp(b))?;
+            time += travel_time;
+            times.push(time);
+        }
+        Some(times)
+    }
+}
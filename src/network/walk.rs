@@ -1,7 +1,12 @@
 use rayon::prelude::*;
 
+use anyhow::bail;
+
 use super::*;
 
+/// walking speed in km/h, used for times of route
+pub const WALK_SPEED: u8 = 4;
+
 // alternative to mid
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct NodeKey {
@@ -134,7 +139,7 @@ impl Network {
             let mut edge_nodes = nodes
                 .iter()
                 .filter(|node| node.edge_mesh_id != 0)
-                .map(|node| node.clone())
+                .cloned()
                 .collect::<Vec<_>>();
             edge_nodes
                 .as_mut_slice()
@@ -167,8 +172,69 @@ impl Network {
         }
     }
 
+    /// map path of nodes found by `search` to route, with times at `WALK_SPEED`. None if
+    /// consecutive nodes of `path` are not connected on this network or on `search`.
+    pub fn route<S>(&self, search: &S, path: &[IdxNodeKey], cost: u32) -> Option<Route<NodeKey>>
+    where
+        S: ShortestPath + ?Sized,
+    {
+        let costs = path_link_costs(search, path)?;
+        let mut time = 0;
+        let mut items = Vec::with_capacity(path.len());
+        for (i, idx) in path.iter().enumerate() {
+            let key = self.nodes[idx.index()].node_key();
+            let (length, item_cost) = match i {
+                0 => (0, 0),
+                _ => {
+                    let prev = self.nodes[path[i - 1].index()].node_key();
+                    let length = self
+                        .nearby_nodes(prev)
+                        .into_iter()
+                        .filter(|(next, _)| *next == key)
+                        .map(|(_, link)| link.length)
+                        .min()?;
+                    (length, costs[i - 1])
+                }
+            };
+            time += travel_time(length, WALK_SPEED);
+            items.push(RouteItem {
+                key,
+                length,
+                max_speed: WALK_SPEED,
+                time,
+                cost: item_cost,
+            });
+        }
+        Some(Route { items, cost })
+    }
+
+    /// search route from `src` node to `dst` node with `search`, which is built from this
+    /// network
+    pub fn search_route<S>(
+        &self,
+        search: &S,
+        src: NodeKey,
+        dst: NodeKey,
+    ) -> Result<Option<Route<NodeKey>>>
+    where
+        S: ShortestPath + ?Sized,
+    {
+        let (src, dst) = match (self.node_map.get(&src), self.node_map.get(&dst)) {
+            (Some(src), Some(dst)) => (IdxNodeKey::new(*src), IdxNodeKey::new(*dst)),
+            (None, _) => bail!("unknown node_key: {:?}", src),
+            (_, None) => bail!("unknown node_key: {:?}", dst),
+        };
+        match search.shortest_path(src, dst) {
+            Some((path, cost)) => match self.route(search, &path, cost) {
+                Some(route) => Ok(Some(route)),
+                None => bail!("path is not on network, search should be built from it"),
+            },
+            None => Ok(None),
+        }
+    }
+
     /// find all connected nodes
-    fn nearby_nodes(&self, src: NodeKey) -> Vec<Neighbor> {
+    fn nearby_nodes(&self, src: NodeKey) -> Vec<Neighbor<'_>> {
         let mut nodes = self.nearby_mesh_nodes(src);
 
         // find nodes on other meshes
@@ -187,7 +253,7 @@ impl Network {
     }
 
     /// find all connected nodes within same mesh
-    fn nearby_mesh_nodes(&self, src: NodeKey) -> Vec<Neighbor> {
+    fn nearby_mesh_nodes(&self, src: NodeKey) -> Vec<Neighbor<'_>> {
         let links = self.links.as_slice();
         let range = self.links_map.get(&src).cloned().unwrap_or(0..0);
 
@@ -224,6 +290,64 @@ impl<'a> From<&'a Network> for Graph {
             })
             .collect::<Vec<_>>();
 
+        report_overflow(overflow_len(network.links.iter().map(|l| l.length)));
         Self::from_links(idx_links)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn search_route_keys_and_length() {
+        let path = testutil::network_dir(
+            "walk-route",
+            &[
+                (
+                    "link.csv",
+                    "mid,mesh,link_id,snode_id,enode_id,link_l\n\
+                     1,1,10,1,2,100\n\
+                     2,1,11,2,3,50\n\
+                     3,1,12,1,3,400\n\
+                     4,1,13,3,4,30\n",
+                ),
+                (
+                    "node.csv",
+                    "mid,mesh,node_id,edge_mesh,edge_node\n\
+                     1,1,1,0,0\n2,1,2,0,0\n3,1,3,0,0\n4,1,4,0,0\n",
+                ),
+            ],
+        );
+        let network = Network::from_path(&path).unwrap();
+        let graph = Graph::from(&network);
+        let mut ch = CH::new(&graph);
+        ch.build();
+        let searches: [&dyn ShortestPath; 2] = [&graph, &ch];
+
+        let (src, dst) = (NodeKey::new(1, 1), NodeKey::new(1, 4));
+        for search in searches {
+            let route = network.search_route(search, src, dst).unwrap().unwrap();
+            let keys = route.items.iter().map(|item| item.key).collect::<Vec<_>>();
+            let expected = [1, 2, 3, 4].map(|id| NodeKey::new(1, id));
+            assert_eq!(keys, expected);
+            assert_eq!(route.length(), 180);
+            assert_eq!(route.cost, 180);
+            let costs = route.items.iter().map(|item| item.cost).collect::<Vec<_>>();
+            assert_eq!(costs, vec![0, 100, 50, 30]);
+            let times = route.items.iter().map(|item| item.time).collect::<Vec<_>>();
+            assert_eq!(times, vec![0, 90, 135, 162]);
+            // reversed links are walkable as well
+            let route = network.search_route(search, dst, src).unwrap().unwrap();
+            assert_eq!(route.length(), 180);
+        }
+
+        // nodes 1 and 4 are not linked, so the path has no length instead of 0 m
+        let path = [network.node_key_to_idx(src), network.node_key_to_idx(dst)];
+        assert!(network.route(&graph, &path, 0).is_none());
+        assert!(network
+            .search_route(&graph, src, NodeKey::new(2, 1))
+            .is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{bail, Result};
+
+use super::*;
+
+/// link of `WideGraph`, with cost which does not fit in `IdxLink`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WideLink {
+    pub enode_idx: IdxNodeKey,
+    pub cost: u64,
+}
+
+/// graph with 64-bit costs, for networks whose costs or path costs exceed `MAX_COST` or
+/// `u32`, e.g. lengths in centimeters of a national network. Only forward links are stored.
+/// There is no hierarchy on it: `to_graph` rejects links which overflow, and `search` gives
+/// costs of paths which `Graph` and `CH` report as `SearchResult::Overflow`.
+pub struct WideGraph {
+    pub node_len: usize,
+    offsets: Vec<u32>,
+    links: Vec<WideLink>,
+}
+
+impl WideGraph {
+    /// build from forward links of each node
+    pub fn from_links(links: Vec<Vec<WideLink>>) -> Self {
+        let node_len = links.len();
+        let mut offsets = Vec::with_capacity(node_len + 1);
+        let mut offset = 0usize;
+        offsets.push(0u32);
+        for v in links.iter() {
+            offset += v.len();
+            assert!(offset <= u32::MAX as usize, "too many links: {}", offset);
+            offsets.push(offset as u32);
+        }
+        Self {
+            node_len,
+            offsets,
+            links: links.concat(),
+        }
+    }
+
+    pub fn link_len(&self) -> usize {
+        self.links.len()
+    }
+
+    /// forward links of `idx`
+    #[inline]
+    pub fn links(&self, idx: usize) -> &[WideLink] {
+        &self.links[self.offsets[idx] as usize..self.offsets[idx + 1] as usize]
+    }
+
+    pub fn max_cost(&self) -> u64 {
+        self.links.iter().map(|l| l.cost).max().unwrap_or(0)
+    }
+
+    /// convert to `Graph`, failing if a cost exceeds `MAX_COST` instead of saturating it
+    pub fn to_graph(&self) -> Result<Graph> {
+        let mut idx_links = Vec::with_capacity(self.node_len);
+        for i in 0..self.node_len {
+            let mut v = Vec::with_capacity(self.links(i).len());
+            for link in self.links(i) {
+                if link.cost > MAX_COST as u64 {
+                    bail!(
+                        "cost overflow: link {} -> {:?} costs {}, exceeds {}",
+                        i,
+                        link.enode_idx,
+                        link.cost,
+                        MAX_COST
+                    );
+                }
+                let cost = link.cost as u32;
+                v.push(IdxLink::new(link.enode_idx, cost, IdxLinkDir::Forward));
+            }
+            idx_links.push(v);
+        }
+        Ok(Graph::from_links(idx_links))
+    }
+
+    /// dijkstra search with 64-bit path costs
+    pub fn search(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u64)> {
+        let mut costs = vec![u64::MAX; self.node_len];
+        let mut prevs = vec![IdxNodeKey::NONE; self.node_len];
+        let mut heap = BinaryHeap::new();
+
+        costs[src.index()] = 0;
+        heap.push(Reverse((0u64, src)));
+        while let Some(Reverse((cost, key))) = heap.pop() {
+            if cost > costs[key.index()] {
+                continue;
+            }
+            if key == dst {
+                let mut path = vec![dst];
+                let mut cur = dst;
+                while cur != src {
+                    cur = prevs[cur.index()];
+                    path.push(cur);
+                }
+                path.reverse();
+                return Some((path, cost));
+            }
+
+            for link in self.links(key.index()) {
+                let next_cost = cost.saturating_add(link.cost);
+                let next = link.enode_idx.index();
+                if next_cost < costs[next] {
+                    costs[next] = next_cost;
+                    prevs[next] = key;
+                    heap.push(Reverse((next_cost, link.enode_idx)));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<'a> From<&'a Graph> for WideGraph {
+    fn from(graph: &'a Graph) -> Self {
+        let links = (0..graph.node_len)
+            .map(|i| {
+                graph
+                    .idx_links
+                    .dir_links(i, IdxLinkDir::Forward)
+                    .iter()
+                    .map(|l| WideLink {
+                        enode_idx: l.enode_idx,
+                        cost: l.cost() as u64,
+                    })
+                    .collect()
+            })
+            .collect();
+        Self::from_links(links)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_search_beyond_u32() {
+        // links cost `MAX_COST`, so path from 0 to 5 costs 5 * `MAX_COST`, beyond `u32::MAX`
+        let links = (0..5)
+            .map(|i| {
+                vec![WideLink {
+                    enode_idx: IdxNodeKey::new(i + 1),
+                    cost: MAX_COST as u64,
+                }]
+            })
+            .chain(std::iter::once(Vec::new()))
+            .collect::<Vec<_>>();
+        let wide = WideGraph::from_links(links);
+        let graph = wide.to_graph().unwrap();
+        // contracted from the ends, so there is no shortcut which overflows
+        let mut ch = CH::new(&graph);
+        ch.build();
+        assert!(ch.all_contractions.is_empty());
+
+        let unbounded = SearchOptions::default();
+        let (src, dst) = (IdxNodeKey::new(0), IdxNodeKey::new(5));
+        assert_eq!(
+            graph.search_bounded(src, dst, &unbounded),
+            SearchResult::Overflow
+        );
+        assert_eq!(
+            ch.search_bounded(src, dst, &unbounded),
+            SearchResult::Overflow
+        );
+        let (path, cost) = wide.search(src, dst).unwrap();
+        assert_eq!(path.len(), 6);
+        assert_eq!(cost, 5 * MAX_COST as u64);
+        assert!(cost > u32::MAX as u64);
+
+        // path within `u32` is found by all of them
+        let dst = IdxNodeKey::new(3);
+        let expected = Some(3 * MAX_COST);
+        assert_eq!(
+            graph
+                .search_bounded(src, dst, &unbounded)
+                .into_option()
+                .map(|r| r.1),
+            expected
+        );
+        assert_eq!(
+            ch.search_bounded(src, dst, &unbounded)
+                .into_option()
+                .map(|r| r.1),
+            expected
+        );
+        assert_eq!(
+            wide.search(src, dst).map(|r| r.1),
+            Some(3 * MAX_COST as u64)
+        );
+
+        // a link beyond `MAX_COST` is rejected instead of saturated
+        let wide = WideGraph::from_links(vec![
+            vec![WideLink {
+                enode_idx: IdxNodeKey::new(1),
+                cost: MAX_COST as u64 + 1,
+            }],
+            Vec::new(),
+        ]);
+        assert!(wide.to_graph().is_err());
+    }
+}
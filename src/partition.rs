@@ -1,9 +1,9 @@
 use std;
 use std::ops::Range;
 
-pub fn par_partition_range_by_key_inner<'a, K, V, F>(
+pub fn par_partition_range_by_key_inner<K, V, F>(
     base: usize,
-    v: &'a [V],
+    v: &[V],
     f: &F,
 ) -> Vec<(K, Range<usize>)>
 where
@@ -41,7 +41,7 @@ where
 }
 
 /// `par_partition_range_by_key` returns range of slice which items have same key.
-pub fn par_partition_range_by_key<'a, K, V, F>(v: &'a [V], f: F) -> Vec<(K, Range<usize>)>
+pub fn par_partition_range_by_key<K, V, F>(v: &[V], f: F) -> Vec<(K, Range<usize>)>
 where
     V: Sync,
     K: Eq + Send,
@@ -51,7 +51,7 @@ where
 }
 
 /// `partition_range_by_key` returns range of slice which items have same key.
-pub fn partition_range_by_key<'a, K, V, F>(v: &'a [V], f: F) -> Vec<(K, Range<usize>)>
+pub fn partition_range_by_key<K, V, F>(v: &[V], f: F) -> Vec<(K, Range<usize>)>
 where
     K: Eq,
     F: Fn(&V) -> K,
@@ -63,7 +63,7 @@ where
     let mut out = Vec::new();
     let mut start_idx = 0usize;
     let mut cur_id = f(&v[start_idx]);
-    for (i, ref item) in v.iter().enumerate() {
+    for (i, item) in v.iter().enumerate() {
         let id = f(item);
         if cur_id != id {
             out.push((cur_id, start_idx..i));
@@ -79,7 +79,7 @@ where
 }
 
 /// `partition_by_key` returns vector of slice which items have same key.
-pub fn partition_by_key<'a, K, V, F>(vec: &'a [V], f: F) -> Vec<(K, &'a [V])>
+pub fn partition_by_key<K, V, F>(vec: &[V], f: F) -> Vec<(K, &[V])>
 where
     V: Sync,
     K: Eq + Send,
@@ -92,7 +92,7 @@ where
 }
 
 /// `partition_mut_by_key` returns vector of mutable slice which items have same key.
-pub fn partition_mut_by_key<'a, K, V, F>(v: &'a mut [V], f: F) -> Vec<(K, &'a mut [V])>
+pub fn partition_mut_by_key<K, V, F>(v: &mut [V], f: F) -> Vec<(K, &mut [V])>
 where
     V: Sync,
     K: Eq + Send,
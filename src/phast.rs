@@ -0,0 +1,157 @@
+use rayon::prelude::*;
+
+use super::*;
+
+/// PHAST one-to-all search. Runs upward search from source, then sweeps all nodes in descending
+/// order relaxing downward links, so every node is visited exactly once.
+pub struct Phast<'a> {
+    hierarchy: Hierarchy<'a>,
+    /// nodes in descending order
+    sweep: Vec<IdxNodeKey>,
+    /// renumbering of hierarchy, costs are returned by original nodes
+    renumbering: Option<&'a Renumbering>,
+}
+
+impl<'a> Phast<'a> {
+    fn new(hierarchy: Hierarchy<'a>, renumbering: Option<&'a Renumbering>) -> Self {
+        let mut sweep = (0..hierarchy.order.len())
+            .map(IdxNodeKey::new)
+            .collect::<Vec<_>>();
+        sweep.par_sort_unstable_by_key(|key| Reverse(hierarchy.order[key.index()]));
+        Self {
+            hierarchy,
+            sweep,
+            renumbering,
+        }
+    }
+
+    fn inner_key(&self, key: IdxNodeKey) -> IdxNodeKey {
+        self.renumbering.map_or(key, |r| r.new_key(key))
+    }
+
+    /// costs of nodes of hierarchy in order of original nodes
+    fn outer_costs(&self, costs: Vec<u32>) -> Vec<u32> {
+        match self.renumbering {
+            Some(r) => (0..costs.len())
+                .map(|i| costs[r.new_key(IdxNodeKey::new(i)).index()])
+                .collect(),
+            None => costs,
+        }
+    }
+
+    /// returns costs from `src` to all nodes, `u32::MAX` if unreachable.
+    pub fn one_to_all(&self, src: IdxNodeKey) -> Vec<u32> {
+        let src = self.inner_key(src);
+        let mut costs = vec![u32::MAX; self.sweep.len()];
+        for (key, cost) in self.hierarchy.upward_costs(src, IdxLinkDir::Forward) {
+            costs[key.index()] = cost;
+        }
+
+        let contractions = self.hierarchy.contractions;
+        for key in self.sweep.iter() {
+            let idx = key.index();
+            let mut cost = costs[idx];
+            // backward links are from higher-ordered nodes, which are already swept
+            for link in contractions.dir_links(idx, IdxLinkDir::Backward) {
+                let prev_cost = costs[link.enode_idx.index()];
+                cost = std::cmp::min(cost, prev_cost.saturating_add(link.cost()));
+            }
+            costs[idx] = cost;
+        }
+        self.outer_costs(costs)
+    }
+
+    /// same as `one_to_all` for multiple sources, with a single sweep for all sources. Returns
+    /// costs for each source.
+    pub fn one_to_all_batch(&self, srcs: &[IdxNodeKey]) -> Vec<Vec<u32>> {
+        let k = srcs.len();
+        if k == 0 {
+            return Vec::new();
+        }
+
+        // costs of node `i` from `srcs[j]` is at `i * k + j`
+        let mut costs = vec![u32::MAX; self.sweep.len() * k];
+        let upwards = srcs
+            .par_iter()
+            .map(|src| {
+                self.hierarchy
+                    .upward_costs(self.inner_key(*src), IdxLinkDir::Forward)
+            })
+            .collect::<Vec<_>>();
+        for (j, upward) in upwards.into_iter().enumerate() {
+            for (key, cost) in upward {
+                costs[key.index() * k + j] = cost;
+            }
+        }
+
+        let contractions = self.hierarchy.contractions;
+        let mut node_costs = vec![u32::MAX; k];
+        for key in self.sweep.iter() {
+            let idx = key.index();
+            node_costs.copy_from_slice(&costs[idx * k..(idx + 1) * k]);
+            for link in contractions.dir_links(idx, IdxLinkDir::Backward) {
+                let prev_idx = link.enode_idx.index();
+                let prev_costs = &costs[prev_idx * k..(prev_idx + 1) * k];
+                for (cost, prev_cost) in node_costs.iter_mut().zip(prev_costs) {
+                    *cost = std::cmp::min(*cost, prev_cost.saturating_add(link.cost()));
+                }
+            }
+            costs[idx * k..(idx + 1) * k].copy_from_slice(&node_costs);
+        }
+
+        (0..k)
+            .into_par_iter()
+            .map(|j| self.outer_costs(costs.iter().skip(j).step_by(k).cloned().collect()))
+            .collect()
+    }
+}
+
+impl<'a> CH<'a> {
+    /// prepare PHAST one-to-all search
+    pub fn phast(&self) -> Phast<'_> {
+        Phast::new(self.hierarchy(), None)
+    }
+}
+
+impl CHQuery {
+    /// prepare PHAST one-to-all search
+    pub fn phast(&self) -> Phast<'_> {
+        Phast::new(self.hierarchy(), self.renumbering())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn phast_matches_dijkstra() {
+        let graph = testutil::grid_with_dead_end(6, 13);
+        let mut ch = CH::new(&graph);
+        ch.build();
+        let phast = ch.phast();
+
+        let expected = (0..graph.node_len)
+            .map(|i| graph.one_to_all(IdxNodeKey::new(i), IdxLinkDir::Forward).0)
+            .collect::<Vec<_>>();
+        for (i, costs) in expected.iter().enumerate() {
+            assert_eq!(&phast.one_to_all(IdxNodeKey::new(i)), costs, "src={}", i);
+        }
+        // the dead end reaches only itself
+        assert_eq!(expected[36][36], 0);
+        assert_eq!(expected[36].iter().filter(|c| **c == u32::MAX).count(), 36);
+
+        let srcs = [3, 36, 3, 0, 20, 0]
+            .iter()
+            .map(|i| IdxNodeKey::new(*i))
+            .collect::<Vec<_>>();
+        let batch = phast.one_to_all_batch(&srcs);
+        assert_eq!(batch.len(), srcs.len());
+        for (src, costs) in srcs.iter().zip(batch.iter()) {
+            assert_eq!(costs, &expected[src.index()]);
+            assert_eq!(costs, &phast.one_to_all(*src));
+        }
+        assert!(phast.one_to_all_batch(&[]).is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+use super::*;
+
+/// limits of a query, see `Graph::search_bounded` and `CH::search_bounded`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    /// do not find paths whose cost is greater than given cost
+    pub max_cost: Option<u32>,
+    /// stop after settling given number of nodes, summed over all directions of the search
+    pub max_settled: Option<usize>,
+}
+
+impl SearchOptions {
+    pub fn with_max_cost(max_cost: u32) -> Self {
+        Self {
+            max_cost: Some(max_cost),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_max_settled(max_settled: usize) -> Self {
+        Self {
+            max_settled: Some(max_settled),
+            ..Default::default()
+        }
+    }
+
+    /// `cost_limit` of `dijkstra::Search`, which excludes the limit itself
+    pub(crate) fn cost_limit(&self) -> u32 {
+        self.max_cost
+            .map(|c| c.saturating_add(1))
+            .unwrap_or(u32::MAX)
+    }
+
+    pub(crate) fn settled_limit(&self) -> usize {
+        self.max_settled.unwrap_or(usize::MAX)
+    }
+}
+
+/// result of a query with `SearchOptions`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SearchResult {
+    /// shortest path and its cost
+    Found(Vec<IdxNodeKey>, u32),
+    /// search stopped at limits before finding a path, so a path may exist beyond them
+    OutOfRange,
+    /// no path, searched without reaching limits
+    Unreachable,
+    /// search stopped at paths whose cost reaches `u32::MAX`, so a path may exist but its cost
+    /// does not fit in `u32`. Only reported without `max_cost`, see `WideGraph` for such graphs.
+    Overflow,
+}
+
+impl SearchResult {
+    pub fn is_found(&self) -> bool {
+        matches!(self, SearchResult::Found(..))
+    }
+
+    pub fn into_option(self) -> Option<(Vec<IdxNodeKey>, u32)> {
+        match self {
+            SearchResult::Found(path, cost) => Some((path, cost)),
+            _ => None,
+        }
+    }
+}
+
+type ArraySearch = dijkstra::Search<IdxNodeKey, dijkstra::ArrayCosts<IdxNodeKey>>;
+
+/// reusable state of forward and backward searches on nodes of a graph, so queries do not
+/// allocate. Each query clears the state in O(1).
+pub struct QueryContext {
+    pub(crate) forward: ArraySearch,
+    pub(crate) backward: ArraySearch,
+}
+
+impl QueryContext {
+    pub fn new(node_len: usize) -> Self {
+        Self {
+            forward: dijkstra::Search::with_node_len(node_len),
+            backward: dijkstra::Search::with_node_len(node_len),
+        }
+    }
+
+    pub fn node_len(&self) -> usize {
+        self.forward.node_len()
+    }
+
+    /// cleared searches, checking that the context is for a graph with `node_len` nodes
+    pub(crate) fn searches(&mut self, node_len: usize) -> (&mut ArraySearch, &mut ArraySearch) {
+        assert_eq!(self.node_len(), node_len, "context for different graph");
+        for search in [&mut self.forward, &mut self.backward] {
+            search.clear();
+            search.cost_limit = u32::MAX;
+        }
+        (&mut self.forward, &mut self.backward)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn context_is_reused_across_queries() {
+        let graph = testutil::grid(5, 8);
+        let mut ch = CH::new(&graph);
+        ch.build();
+
+        let mut ctx = QueryContext::new(graph.node_len);
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            let expected = graph.search(src, dst).map(|r| r.1);
+            assert_eq!(graph.search_with(&mut ctx, src, dst).map(|r| r.1), expected);
+            assert_eq!(
+                graph.search_bidir_with(&mut ctx, src, dst).map(|r| r.1),
+                expected
+            );
+            assert_eq!(ch.search_with(&mut ctx, src, dst).map(|r| r.1), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "context for different graph")]
+    fn context_of_other_graph() {
+        let small = testutil::grid(4, 1);
+        let large = testutil::grid(5, 1);
+        let mut ctx = QueryContext::new(small.node_len);
+        let (src, dst) = (IdxNodeKey::new(0), IdxNodeKey::new(15));
+        assert!(small.search_with(&mut ctx, src, dst).is_some());
+        large.search_with(&mut ctx, src, dst);
+    }
+
+    #[test]
+    fn search_bounded_limits() {
+        // line of 10 nodes where each link costs 10
+        let links = (0..9).map(|i| (i, i + 1, 10)).collect::<Vec<_>>();
+        let line = testutil::graph(10, &links);
+        let (src, dst) = (IdxNodeKey::new(0), IdxNodeKey::new(9));
+        let found = |r: SearchResult| r.into_option().map(|r| r.1);
+        assert_eq!(
+            found(line.search_bounded(src, dst, &SearchOptions::with_max_cost(90))),
+            Some(90)
+        );
+        assert_eq!(
+            line.search_bounded(src, dst, &SearchOptions::with_max_cost(89)),
+            SearchResult::OutOfRange
+        );
+        // 9 nodes are settled before reaching `dst`
+        assert_eq!(
+            line.search_bounded(src, dst, &SearchOptions::with_max_settled(5)),
+            SearchResult::OutOfRange
+        );
+        assert_eq!(
+            found(line.search_bounded(src, dst, &SearchOptions::with_max_settled(10))),
+            Some(90)
+        );
+        // exhausted before reaching the limit
+        assert_eq!(
+            line.search_bounded(dst, src, &SearchOptions::with_max_cost(50)),
+            SearchResult::Unreachable
+        );
+
+        let graph = testutil::grid(6, 4);
+        let mut ch = CH::new(&graph);
+        ch.build();
+        let query = ch.to_query();
+        let mut max_settled = 0;
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            let cost = match graph.search(src, dst) {
+                Some((_, cost)) if src != dst => cost,
+                _ => continue,
+            };
+            let within = SearchOptions::with_max_cost(cost);
+            let beyond = SearchOptions::with_max_cost(cost - 1);
+            assert_eq!(found(graph.search_bounded(src, dst, &within)), Some(cost));
+            assert_eq!(
+                graph.search_bounded(src, dst, &beyond),
+                SearchResult::OutOfRange
+            );
+            assert_eq!(found(ch.search_bounded(src, dst, &within)), Some(cost));
+            assert_eq!(
+                ch.search_bounded(src, dst, &beyond),
+                SearchResult::OutOfRange
+            );
+            assert_eq!(found(query.search_bounded(src, dst, &within)), Some(cost));
+            assert_eq!(
+                query.search_bounded(src, dst, &beyond),
+                SearchResult::OutOfRange
+            );
+
+            // both directions settle a node each round, so the query stops a round before the
+            // last one of the unbounded query. Stalled nodes are not counted, so the limit has
+            // to exceed settled nodes of the unbounded query to let it pop them.
+            let (_, stats) = ch.search_with_stats(src, dst);
+            max_settled = std::cmp::max(max_settled, stats.settled);
+            let enough = SearchOptions::with_max_settled(stats.settled + 1);
+            let short = SearchOptions::with_max_settled(stats.settled - 2);
+            assert_eq!(found(ch.search_bounded(src, dst, &enough)), Some(cost));
+            assert_eq!(
+                ch.search_bounded(src, dst, &short),
+                SearchResult::OutOfRange
+            );
+            assert_eq!(found(query.search_bounded(src, dst, &enough)), Some(cost));
+            assert_eq!(
+                query.search_bounded(src, dst, &short),
+                SearchResult::OutOfRange
+            );
+        }
+        assert!(max_settled > 2);
+    }
+}
@@ -0,0 +1,375 @@
+use rayon::prelude::*;
+use std::collections::VecDeque;
+
+use super::*;
+
+/// permutation of node indices, with mapping in both directions. Used to renumber nodes so
+/// nodes visited together are stored close to each other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Renumbering {
+    /// new index of each original node
+    new_keys: Vec<IdxNodeKey>,
+    /// original index of each new node
+    old_keys: Vec<IdxNodeKey>,
+}
+
+impl Renumbering {
+    /// build from original nodes in new order
+    pub fn from_old_keys(old_keys: Vec<IdxNodeKey>) -> Self {
+        let mut new_keys = vec![IdxNodeKey::NONE; old_keys.len()];
+        for (i, old_key) in old_keys.iter().enumerate() {
+            assert!(
+                new_keys[old_key.index()] == IdxNodeKey::NONE,
+                "duplicate node: {:?}",
+                old_key
+            );
+            new_keys[old_key.index()] = IdxNodeKey::new(i);
+        }
+        Self { new_keys, old_keys }
+    }
+
+    /// descending CH order, so upward searches move toward the front of arrays. Nodes without
+    /// order come first, as they are treated as the highest ones.
+    pub fn by_order(order: &[u32]) -> Self {
+        let mut old_keys = (0..order.len()).map(IdxNodeKey::new).collect::<Vec<_>>();
+        old_keys.par_sort_unstable_by_key(|k| (Reverse(order[k.index()]), *k));
+        Self::from_old_keys(old_keys)
+    }
+
+    /// ascending position of each node on a space-filling curve, e.g. `CellID` of s2 or
+    /// Hilbert index of coordinates
+    pub fn by_curve(positions: &[u64]) -> Self {
+        let mut old_keys = (0..positions.len())
+            .map(IdxNodeKey::new)
+            .collect::<Vec<_>>();
+        old_keys.par_sort_unstable_by_key(|k| (positions[k.index()], *k));
+        Self::from_old_keys(old_keys)
+    }
+
+    /// breadth-first order of `graph`, for networks without coordinates. Neighbors are numbered
+    /// close to each other, similar to spatial order.
+    pub fn by_bfs(graph: &Graph) -> Self {
+        let node_len = graph.node_len;
+        let mut visited = vec![false; node_len];
+        let mut old_keys = Vec::with_capacity(node_len);
+        let mut queue = VecDeque::new();
+
+        for start in 0..node_len {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            queue.push_back(start);
+
+            while let Some(idx) = queue.pop_front() {
+                old_keys.push(IdxNodeKey::new(idx));
+                // links on both directions, so weakly connected nodes are kept together
+                for link in graph.idx_links.links(idx) {
+                    let next = link.enode_idx.index();
+                    if !visited[next] {
+                        visited[next] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        Self::from_old_keys(old_keys)
+    }
+
+    pub fn node_len(&self) -> usize {
+        self.old_keys.len()
+    }
+
+    /// original node of each new node, see `from_old_keys`
+    pub fn old_keys(&self) -> &[IdxNodeKey] {
+        &self.old_keys
+    }
+
+    /// renumbering from new nodes back to original nodes
+    pub fn inverse(&self) -> Self {
+        Self {
+            new_keys: self.old_keys.clone(),
+            old_keys: self.new_keys.clone(),
+        }
+    }
+
+    #[inline]
+    pub fn new_key(&self, key: IdxNodeKey) -> IdxNodeKey {
+        self.new_keys[key.index()]
+    }
+
+    #[inline]
+    pub fn old_key(&self, key: IdxNodeKey) -> IdxNodeKey {
+        self.old_keys[key.index()]
+    }
+
+    /// map renumbered path back to original nodes
+    pub fn old_path(&self, path: &[IdxNodeKey]) -> Vec<IdxNodeKey> {
+        path.iter().map(|k| self.old_key(*k)).collect()
+    }
+
+    /// `IdxNodeKey::NONE` is kept as is
+    fn map_mid(&self, key: IdxNodeKey) -> IdxNodeKey {
+        if key == IdxNodeKey::NONE {
+            key
+        } else {
+            self.new_key(key)
+        }
+    }
+
+    fn map_link(&self, link: &IdxLink) -> IdxLink {
+        IdxLink::new(self.new_key(link.enode_idx), link.cost(), link.dir())
+    }
+
+    /// links and per-link values of each node in new order. Order of links of each node is kept.
+    fn map_csr<T, F>(&self, links: &Csr, values: &[T], f: F) -> (Csr, Vec<T>)
+    where
+        T: Copy,
+        F: Fn(T) -> T,
+    {
+        let mut offsets = Vec::with_capacity(self.node_len() + 1);
+        offsets.push(0u32);
+        let mut flat_links = Vec::with_capacity(links.link_len());
+        let mut flat_values = Vec::with_capacity(values.len());
+        for old_key in self.old_keys.iter() {
+            let r = links.range(old_key.index());
+            flat_links.extend(
+                links.flat_links()[r.clone()]
+                    .iter()
+                    .map(|l| self.map_link(l)),
+            );
+            if !values.is_empty() {
+                flat_values.extend(values[r].iter().map(|v| f(*v)));
+            }
+            offsets.push(flat_links.len() as u32);
+        }
+        (Csr::from_parts(offsets, flat_links), flat_values)
+    }
+}
+
+impl Graph {
+    /// copy of graph with nodes renumbered. Link indices of `forward_links` change as well.
+    pub fn renumber(&self, renumbering: &Renumbering) -> Graph {
+        assert_eq!(self.node_len, renumbering.node_len());
+        let (idx_links, secondary) = renumbering.map_csr(&self.idx_links, &self.secondary, |v| v);
+        Graph {
+            node_len: self.node_len,
+            idx_links,
+            secondary,
+        }
+    }
+}
+
+impl CHQuery {
+    /// copy of hierarchy with nodes renumbered, rewriting order, links and contractions. Panics
+    /// if hierarchy is already renumbered, see `renumbering`.
+    pub fn renumber(&self, renumbering: &Renumbering) -> CHQuery {
+        assert!(self.renumbering.is_none(), "already renumbered");
+        let h = self.hierarchy();
+        assert_eq!(h.order.len(), renumbering.node_len());
+
+        let order = renumbering
+            .old_keys
+            .iter()
+            .map(|k| h.order[k.index()])
+            .collect::<Vec<_>>();
+        let (contractions, mids) =
+            renumbering.map_csr(h.contractions, h.mids, |k| renumbering.map_mid(k));
+        let (_, secondary) = renumbering.map_csr(h.contractions, h.secondary, |v| v);
+
+        let mut all_contractions = h
+            .all_contractions
+            .par_iter()
+            .map(|c| CHContraction {
+                snode_idx: renumbering.new_key(c.snode_idx),
+                enode_idx: renumbering.new_key(c.enode_idx),
+                mnode_idx: renumbering.new_key(c.mnode_idx),
+                length: c.length,
+                secondary: c.secondary,
+            })
+            .collect::<Vec<_>>();
+        sort_contractions(&mut all_contractions);
+
+        CHQuery::from_parts(order, contractions, mids, secondary, all_contractions)
+    }
+
+    /// renumber by `renumbering` and keep it, so searches still take and return original nodes.
+    /// Renumbering is written to `ch.bin` with the hierarchy and loaded by `from_file`, so
+    /// nodes are renumbered once at build time, see `ch-build --renumber`.
+    pub fn with_renumbering(self, renumbering: Renumbering) -> CHQuery {
+        let mut query = self.renumber(&renumbering);
+        query.renumbering = Some(renumbering);
+        query
+    }
+
+    /// renumber by descending order, keeping mapping to original nodes
+    pub fn into_renumbered(self) -> Renumbered<CHQuery> {
+        let renumbering = Renumbering::by_order(self.hierarchy().order);
+        self.into_renumbered_with(renumbering)
+    }
+
+    /// renumber by given `renumbering`, e.g. `Renumbering::by_curve`, keeping mapping to
+    /// original nodes
+    pub fn into_renumbered_with(self, renumbering: Renumbering) -> Renumbered<CHQuery> {
+        Renumbered {
+            inner: self.renumber(&renumbering),
+            renumbering,
+        }
+    }
+}
+
+/// search on renumbered nodes, taking and returning original nodes. Nodes are renumbered on
+/// every load, use `CHQuery::with_renumbering` to renumber once and write it with the hierarchy.
+pub struct Renumbered<S> {
+    pub inner: S,
+    pub renumbering: Renumbering,
+}
+
+impl Renumbered<CHQuery> {
+    /// same as `CHQuery::search_bounded`
+    pub fn search_bounded(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        options: &SearchOptions,
+    ) -> SearchResult {
+        let src = self.renumbering.new_key(src);
+        let dst = self.renumbering.new_key(dst);
+        match self.inner.search_bounded(src, dst, options) {
+            SearchResult::Found(path, cost) => {
+                SearchResult::Found(self.renumbering.old_path(&path), cost)
+            }
+            result => result,
+        }
+    }
+
+    /// same as `CHQuery::many_to_many`
+    pub fn many_to_many(&self, srcs: &[IdxNodeKey], dsts: &[IdxNodeKey]) -> Vec<Vec<u32>> {
+        let new_keys = |keys: &[IdxNodeKey]| {
+            keys.iter()
+                .map(|k| self.renumbering.new_key(*k))
+                .collect::<Vec<_>>()
+        };
+        self.inner.many_to_many(&new_keys(srcs), &new_keys(dsts))
+    }
+}
+
+impl<S> ShortestPath for Renumbered<S>
+where
+    S: ShortestPath,
+{
+    fn shortest_path(&self, src: IdxNodeKey, dst: IdxNodeKey) -> Option<(Vec<IdxNodeKey>, u32)> {
+        let src = self.renumbering.new_key(src);
+        let dst = self.renumbering.new_key(dst);
+        self.inner
+            .shortest_path(src, dst)
+            .map(|(path, cost)| (self.renumbering.old_path(&path), cost))
+    }
+
+    fn link_cost(&self, snode_idx: IdxNodeKey, enode_idx: IdxNodeKey) -> Option<u32> {
+        let snode_idx = self.renumbering.new_key(snode_idx);
+        let enode_idx = self.renumbering.new_key(enode_idx);
+        self.inner.link_cost(snode_idx, enode_idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn renumbered_matches_dijkstra() {
+        let n = 7;
+        let graph = testutil::grid(n, 13);
+        let mut ch = CH::new(&graph);
+        ch.build();
+
+        // position on a curve which visits rows alternately from both ends
+        let positions = (0..n * n)
+            .map(|i| {
+                let (y, x) = (i / n, i % n);
+                (y * n + if y % 2 == 0 { x } else { n - 1 - x }) as u64
+            })
+            .collect::<Vec<_>>();
+        for renumbering in [
+            Renumbering::by_curve(&positions),
+            Renumbering::by_bfs(&graph),
+        ] {
+            let renumbered = ch.to_query().into_renumbered_with(renumbering);
+            for (src, dst) in testutil::all_pairs(graph.node_len) {
+                let expected = graph.search(src, dst).map(|r| r.1);
+                assert_eq!(renumbered.shortest_path(src, dst).map(|r| r.1), expected);
+                let options = SearchOptions::default();
+                match renumbered.search_bounded(src, dst, &options) {
+                    SearchResult::Found(path, cost) => {
+                        assert_eq!((path[0], path[path.len() - 1]), (src, dst));
+                        assert_eq!(Some(cost), expected);
+                    }
+                    result => assert_eq!(result, SearchResult::Unreachable),
+                }
+            }
+
+            let keys = (0..graph.node_len).map(IdxNodeKey::new).collect::<Vec<_>>();
+            assert_eq!(
+                renumbered.many_to_many(&keys, &keys[..9]),
+                ch.many_to_many(&keys, &keys[..9])
+            );
+        }
+    }
+
+    #[test]
+    fn renumbering_is_written_with_hierarchy() {
+        let graph = testutil::grid_with_dead_end(6, 21);
+        let mut ch = CH::new(&graph);
+        ch.build();
+        let renumbering = Renumbering::by_order(ch.order());
+        let path = testutil::temp_dir("renumber-file");
+        ch.to_query()
+            .with_renumbering(renumbering.clone())
+            .write(&path)
+            .unwrap();
+
+        // searches of query take and return original nodes
+        let query = CHQuery::from_file(&path).unwrap();
+        assert!(query.is_mapped());
+        assert_eq!(query.renumbering(), Some(&renumbering));
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            let expected = graph.search(src, dst).map(|r| r.1);
+            let result = query.search(src, dst);
+            assert_eq!(result.as_ref().map(|r| r.1), expected);
+            if let Some((path, cost)) = result {
+                assert_eq!(path_cost(&graph, &path), Some(cost));
+            }
+            let options = SearchOptions::with_max_cost(40);
+            match query.search_bounded(src, dst, &options) {
+                SearchResult::Found(path, cost) => {
+                    assert_eq!(path_cost(&graph, &path), Some(cost));
+                    assert_eq!(Some(cost), expected);
+                }
+                SearchResult::OutOfRange => assert!(expected.is_none_or(|c| c > 40)),
+                result => assert_eq!((result, expected), (SearchResult::Unreachable, None)),
+            }
+        }
+
+        let keys = (0..graph.node_len).map(IdxNodeKey::new).collect::<Vec<_>>();
+        let matrix = query.many_to_many(&keys, &keys);
+        let phast = query.phast();
+        for src in keys.iter() {
+            let (expected, _) = graph.one_to_all(*src, IdxLinkDir::Forward);
+            assert_eq!(matrix[src.index()], expected);
+            assert_eq!(phast.one_to_all(*src), expected);
+        }
+        assert_eq!(phast.one_to_all_batch(&keys[..3]), matrix[..3].to_vec());
+
+        // `CH` borrows graph of original nodes, so renumbering is undone on load
+        let loaded = CH::from_file(&graph, &path).unwrap();
+        assert_eq!(loaded.order(), ch.order());
+        for (src, dst) in testutil::all_pairs(graph.node_len) {
+            assert_eq!(
+                loaded.search(src, dst).map(|r| r.1),
+                graph.search(src, dst).map(|r| r.1)
+            );
+        }
+    }
+}
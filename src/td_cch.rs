@@ -0,0 +1,398 @@
+use anyhow::{bail, Result};
+
+use super::*;
+
+type Profile = Option<TravelTimeProfile>;
+
+/// travel time of `first` then `second`, None if any of them is missing
+fn link_profiles(first: &Profile, second: &Profile) -> Profile {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(first.link(second)),
+        _ => None,
+    }
+}
+
+fn merge_profile(target: &mut Profile, profile: TravelTimeProfile) {
+    *target = Some(match target.take() {
+        Some(p) => p.merge_min(&profile),
+        None => profile,
+    });
+}
+
+/// time-dependent customization of `CCH`. Each edge of hierarchy carries a travel time profile
+/// on each direction, the lower envelope of original links and shortcuts through lower
+/// triangles, so shortcuts are unpacked by departure time on query.
+pub struct TdCCH<'a> {
+    cch: &'a CCH,
+
+    /// profiles of original links on each edge of `cch`, upward and downward
+    original_forward: Vec<Profile>,
+    original_backward: Vec<Profile>,
+    /// profiles of each edge of `cch` including shortcuts, upward and downward
+    forward: Vec<Profile>,
+    backward: Vec<Profile>,
+
+    /// lower neighbors of each node in CSR form, as (node, upward edge of the node), sorted by
+    /// node index
+    down_first: Vec<u32>,
+    downs: Vec<(IdxNodeKey, u32)>,
+}
+
+impl<'a> TdCCH<'a> {
+    /// compute profiles of hierarchy for `graph`, which has the same topology with the graph
+    /// used to build `cch`, e.g. `TdGraph::lower_bound_graph`
+    pub fn new(cch: &'a CCH, graph: &TdGraph) -> Result<Self> {
+        if cch.node_len() != graph.node_len {
+            bail!(
+                "node count mismatch: graph={}, cch={}",
+                graph.node_len,
+                cch.node_len()
+            );
+        }
+
+        let sw = Timer::new();
+        let edge_len = cch.heads.len();
+        let mut original_forward = vec![None; edge_len];
+        let mut original_backward = vec![None; edge_len];
+        for i in 0..graph.node_len {
+            let snode_idx = IdxNodeKey::new(i);
+            for link in graph.links(i) {
+                let enode_idx = link.enode_idx;
+                if snode_idx == enode_idx {
+                    continue;
+                }
+                let profile = link.profile.clone();
+                if cch.order[i] < cch.order[enode_idx.index()] {
+                    let e = cch.edge(snode_idx, enode_idx) as usize;
+                    merge_profile(&mut original_forward[e], profile);
+                } else {
+                    let e = cch.edge(enode_idx, snode_idx) as usize;
+                    merge_profile(&mut original_backward[e], profile);
+                }
+            }
+        }
+
+        let mut forward = original_forward.clone();
+        let mut backward = original_backward.clone();
+
+        let mut sequence = (0..cch.node_len()).map(IdxNodeKey::new).collect::<Vec<_>>();
+        sequence.sort_unstable_by_key(|n| cch.order[n.index()]);
+
+        // lower triangles as `CCH::customize`, with linking and minimum of profiles
+        for v in sequence.into_iter() {
+            let r = cch.edge_range(v);
+            for e1 in r.clone() {
+                for e2 in r.clone() {
+                    let u = cch.heads[e1];
+                    let w = cch.heads[e2];
+                    if cch.order[u.index()] >= cch.order[w.index()] {
+                        continue;
+                    }
+                    let e3 = cch.edge(u, w) as usize;
+
+                    if let Some(p) = link_profiles(&backward[e1], &forward[e2]) {
+                        merge_profile(&mut forward[e3], p);
+                    }
+                    if let Some(p) = link_profiles(&backward[e2], &forward[e1]) {
+                        merge_profile(&mut backward[e3], p);
+                    }
+                }
+            }
+        }
+
+        let mut downs = vec![Vec::new(); cch.node_len()];
+        for i in 0..cch.node_len() {
+            let key = IdxNodeKey::new(i);
+            for e in cch.edge_range(key) {
+                downs[cch.heads[e].index()].push((key, e as u32));
+            }
+        }
+        let mut down_first = Vec::with_capacity(cch.node_len() + 1);
+        down_first.push(0);
+        for v in downs.iter() {
+            down_first.push(down_first[down_first.len() - 1] + v.len() as u32);
+        }
+
+        eprintln!(
+            "td customization took: {}, knots={}",
+            sw.took(),
+            forward
+                .iter()
+                .chain(backward.iter())
+                .flatten()
+                .map(|p| p.knots().len())
+                .sum::<usize>()
+        );
+        Ok(Self {
+            cch,
+            original_forward,
+            original_backward,
+            forward,
+            backward,
+            down_first,
+            downs: downs.concat(),
+        })
+    }
+
+    fn downs(&self, key: IdxNodeKey) -> &[(IdxNodeKey, u32)] {
+        let idx = key.index();
+        &self.downs[self.down_first[idx] as usize..self.down_first[idx + 1] as usize]
+    }
+
+    /// profile of the edge between adjacent nodes `from` and `to`, including shortcuts
+    fn profile(&self, from: IdxNodeKey, to: IdxNodeKey) -> &Profile {
+        if self.cch.order[from.index()] < self.cch.order[to.index()] {
+            &self.forward[self.cch.edge(from, to) as usize]
+        } else {
+            &self.backward[self.cch.edge(to, from) as usize]
+        }
+    }
+
+    /// profile of original links between adjacent nodes `from` and `to`
+    fn original(&self, from: IdxNodeKey, to: IdxNodeKey) -> &Profile {
+        if self.cch.order[from.index()] < self.cch.order[to.index()] {
+            &self.original_forward[self.cch.edge(from, to) as usize]
+        } else {
+            &self.original_backward[self.cch.edge(to, from) as usize]
+        }
+    }
+
+    /// time-dependent search departing `src` at `departure` seconds. Returns path and arrival
+    /// time at `dst`, same as `TdGraph::search`.
+    ///
+    /// Nodes reachable upward from `dst` are marked first, as departure time at them is not
+    /// known. Then upward search from `src` finds arrival times at all upward nodes, and
+    /// downward search from them within marked nodes finds arrival time at `dst`.
+    pub fn search(
+        &self,
+        src: IdxNodeKey,
+        dst: IdxNodeKey,
+        departure: f64,
+    ) -> Option<(Vec<IdxNodeKey>, f64)> {
+        let node_len = self.cch.node_len();
+        let cch = self.cch;
+
+        let mut marked = vec![false; node_len];
+        let mut stack = vec![dst];
+        marked[dst.index()] = true;
+        while let Some(key) = stack.pop() {
+            for e in cch.edge_range(key) {
+                let head = cch.heads[e];
+                if self.backward[e].is_some() && !marked[head.index()] {
+                    marked[head.index()] = true;
+                    stack.push(head);
+                }
+            }
+        }
+
+        let mut up_times = vec![f64::INFINITY; node_len];
+        let mut up_prevs = vec![IdxNodeKey::NONE; node_len];
+        let mut heap = BinaryHeap::new();
+        up_times[src.index()] = departure;
+        heap.push(TimeEntry {
+            time: departure,
+            key: src,
+        });
+        while let Some(TimeEntry { time, key }) = heap.pop() {
+            if time > up_times[key.index()] {
+                continue;
+            }
+            for e in cch.edge_range(key) {
+                let profile = match self.forward[e] {
+                    Some(ref profile) => profile,
+                    None => continue,
+                };
+                let head = cch.heads[e];
+                let next_time = time + profile.eval(time);
+                if next_time < up_times[head.index()] {
+                    up_times[head.index()] = next_time;
+                    up_prevs[head.index()] = key;
+                    heap.push(TimeEntry {
+                        time: next_time,
+                        key: head,
+                    });
+                }
+            }
+        }
+
+        let mut down_times = vec![f64::INFINITY; node_len];
+        let mut down_prevs = vec![IdxNodeKey::NONE; node_len];
+        for i in 0..node_len {
+            if marked[i] && up_times[i].is_finite() {
+                down_times[i] = up_times[i];
+                heap.push(TimeEntry {
+                    time: up_times[i],
+                    key: IdxNodeKey::new(i),
+                });
+            }
+        }
+        let mut arrival = None;
+        while let Some(TimeEntry { time, key }) = heap.pop() {
+            if time > down_times[key.index()] {
+                continue;
+            }
+            if key == dst {
+                arrival = Some(time);
+                break;
+            }
+            for &(lower, e) in self.downs(key) {
+                if !marked[lower.index()] {
+                    continue;
+                }
+                let profile = match self.backward[e as usize] {
+                    Some(ref profile) => profile,
+                    None => continue,
+                };
+                let next_time = time + profile.eval(time);
+                if next_time < down_times[lower.index()] {
+                    down_times[lower.index()] = next_time;
+                    down_prevs[lower.index()] = key;
+                    heap.push(TimeEntry {
+                        time: next_time,
+                        key: lower,
+                    });
+                }
+            }
+        }
+        let arrival = arrival?;
+
+        // nodes on hierarchy: downward part ends at a node reached by upward search
+        let mut nodes = vec![dst];
+        let mut cur = dst;
+        while down_prevs[cur.index()] != IdxNodeKey::NONE {
+            cur = down_prevs[cur.index()];
+            nodes.push(cur);
+        }
+        while cur != src {
+            cur = up_prevs[cur.index()];
+            nodes.push(cur);
+        }
+        nodes.reverse();
+
+        let mut path = vec![src];
+        let mut time = departure;
+        for w in nodes.windows(2) {
+            time = self.unpack(w[0], w[1], time, &mut path);
+        }
+        Some((path, arrival))
+    }
+
+    /// append original path of edge `from -> to` departing at `time` to `path`, excluding
+    /// `from`. The edge is unpacked to original links or the lower triangle which is the fastest
+    /// at `time`. Returns arrival time at `to`.
+    fn unpack(
+        &self,
+        from: IdxNodeKey,
+        to: IdxNodeKey,
+        time: f64,
+        path: &mut Vec<IdxNodeKey>,
+    ) -> f64 {
+        let mut best = match self.original(from, to) {
+            Some(profile) => (profile.eval(time), IdxNodeKey::NONE),
+            None => (f64::INFINITY, IdxNodeKey::NONE),
+        };
+
+        let (a, b) = (self.downs(from), self.downs(to));
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            let (v, w) = (a[i].0, b[j].0);
+            if v < w {
+                i += 1;
+                continue;
+            }
+            if v > w {
+                j += 1;
+                continue;
+            }
+            i += 1;
+            j += 1;
+
+            if let (Some(first), Some(second)) = (self.profile(from, v), self.profile(v, to)) {
+                let mid_time = time + first.eval(time);
+                let travel_time = mid_time - time + second.eval(mid_time);
+                if travel_time < best.0 {
+                    best = (travel_time, v);
+                }
+            }
+        }
+
+        let (travel_time, mid) = best;
+        if mid == IdxNodeKey::NONE {
+            assert!(
+                travel_time.is_finite(),
+                "missing link of edge: {:?} -> {:?}",
+                from,
+                to
+            );
+            path.push(to);
+            return time + travel_time;
+        }
+        let mid_time = self.unpack(from, mid, time, path);
+        self.unpack(mid, to, mid_time, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    /// grid whose links are congested at a different time of day each
+    fn td_grid(n: usize, seed: u64) -> TdGraph {
+        let graph = testutil::grid(n, seed);
+        let mut links = vec![Vec::new(); graph.node_len];
+        for (i, (src, link)) in graph.forward_links().enumerate() {
+            let cost = link.cost() as f64;
+            let peak = (i % 24) as f64 * 3600.0;
+            let profile = TravelTimeProfile::from_knots(vec![
+                (peak, cost),
+                ((peak + 1800.0) % DAY_SECONDS, cost * 5.0),
+                ((peak + 3600.0) % DAY_SECONDS, cost),
+            ])
+            .unwrap();
+            links[src.index()].push(TdLink {
+                enode_idx: link.enode_idx,
+                profile,
+            });
+        }
+        TdGraph::from_links(links)
+    }
+
+    #[test]
+    fn search_matches_td_dijkstra() {
+        let graph = td_grid(5, 11);
+        let cch = CCH::new(&graph.lower_bound_graph());
+        let td_cch = TdCCH::new(&cch, &graph).unwrap();
+
+        for departure in [0.0, 1700.0, 30000.0, 85000.0] {
+            for (src, dst) in testutil::all_pairs(graph.node_len) {
+                let expected = graph.search(src, dst, departure);
+                let actual = td_cch.search(src, dst, departure);
+                assert_eq!(
+                    actual.is_some(),
+                    expected.is_some(),
+                    "{:?} -> {:?}",
+                    src,
+                    dst
+                );
+                let ((path, arrival), (_, expected)) = match (actual, expected) {
+                    (Some(actual), Some(expected)) => (actual, expected),
+                    _ => continue,
+                };
+                assert!(
+                    (arrival - expected).abs() < 1e-6,
+                    "{:?} -> {:?} at {}: {} != {}",
+                    src,
+                    dst,
+                    departure,
+                    arrival,
+                    expected
+                );
+                assert_eq!(path.first(), Some(&src));
+                assert_eq!(path.last(), Some(&dst));
+                let path_arrival = graph.path_arrival(&path, departure).unwrap();
+                assert!((path_arrival - arrival).abs() < 1e-6);
+            }
+        }
+    }
+}
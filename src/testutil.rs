@@ -0,0 +1,127 @@
+//! graphs and helpers shared by unit tests
+
+use super::*;
+
+/// graph with given forward links `(snode, enode, cost)`
+pub(crate) fn graph(node_len: usize, links: &[(usize, usize, u32)]) -> Graph {
+    let mut idx_links = vec![Vec::new(); node_len];
+    for &(s, e, cost) in links {
+        idx_links[s].push(IdxLink::new(IdxNodeKey::new(e), cost, IdxLinkDir::Forward));
+    }
+    let mut rev = map_rev(&idx_links);
+    for (i, v) in idx_links.iter_mut().enumerate() {
+        v.append(&mut rev[i]);
+    }
+    Graph {
+        node_len,
+        idx_links: idx_links.into(),
+        secondary: Vec::new(),
+    }
+}
+
+/// next random number of xorshift64, `state` should not be zero
+pub(crate) fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// `n` x `n` grid with random costs in `1..=100`, where some links are missing
+pub(crate) fn grid(n: usize, seed: u64) -> Graph {
+    let mut state = seed;
+    let mut next = move || xorshift(&mut state);
+    let mut links = Vec::new();
+    for y in 0..n {
+        for x in 0..n {
+            let i = y * n + x;
+            let mut neighbors = Vec::new();
+            if x + 1 < n {
+                neighbors.push(i + 1);
+            }
+            if x > 0 {
+                neighbors.push(i - 1);
+            }
+            if y + 1 < n {
+                neighbors.push(i + n);
+            }
+            if y > 0 {
+                neighbors.push(i - n);
+            }
+            for j in neighbors {
+                if next() % 10 != 0 {
+                    links.push((i, j, (next() % 100 + 1) as u32));
+                }
+            }
+        }
+    }
+    graph(n * n, &links)
+}
+
+/// graph with given forward links `(snode, enode, cost, secondary)`
+pub(crate) fn graph_with_secondary(node_len: usize, links: &[(usize, usize, u32, u32)]) -> Graph {
+    let mut idx_links = vec![Vec::new(); node_len];
+    for &(s, e, cost, secondary) in links {
+        let link = IdxLink::new(IdxNodeKey::new(e), cost, IdxLinkDir::Forward);
+        idx_links[s].push((link, secondary));
+    }
+    Graph::from_links_with_secondary(idx_links)
+}
+
+/// road-like `grid`, where secondary metric is length in meters and cost is travel time in
+/// milliseconds with a random speed of 30, 60 or 90 km/h
+pub(crate) fn road_grid(n: usize, seed: u64) -> Graph {
+    let mut state = seed;
+    let grid = grid(n, seed);
+    let links = grid
+        .forward_links()
+        .map(|(s, l)| {
+            let state = xorshift(&mut state);
+            let length = l.cost() * 10 + (state % 10) as u32;
+            let speed = [30, 60, 90][(state / 10 % 3) as usize];
+            (
+                s.index(),
+                l.enode_idx.index(),
+                length * 3600 / speed,
+                length,
+            )
+        })
+        .collect::<Vec<_>>();
+    graph_with_secondary(grid.node_len, &links)
+}
+
+/// `grid` with an extra last node, reachable from node 0 but reaching no other node
+pub(crate) fn grid_with_dead_end(n: usize, seed: u64) -> Graph {
+    let grid = grid(n, seed);
+    let mut links = grid
+        .forward_links()
+        .map(|(s, l)| (s.index(), l.enode_idx.index(), l.cost()))
+        .collect::<Vec<_>>();
+    links.push((0, n * n, 3));
+    graph(n * n + 1, &links)
+}
+
+/// all pairs of nodes
+pub(crate) fn all_pairs(node_len: usize) -> Vec<(IdxNodeKey, IdxNodeKey)> {
+    (0..node_len)
+        .flat_map(|s| (0..node_len).map(move |e| (IdxNodeKey::new(s), IdxNodeKey::new(e))))
+        .collect()
+}
+
+/// empty directory under temporary directory, unique for `name`
+pub(crate) fn temp_dir(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("ch-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// network directory under `temp_dir` with given `(file name, content)`, e.g. `link.csv` and
+/// `node.csv` with headers, to be loaded by `from_path` of networks
+pub(crate) fn network_dir(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+    let path = temp_dir(name);
+    for (file_name, content) in files {
+        std::fs::write(path.join(file_name), content).unwrap();
+    }
+    path
+}
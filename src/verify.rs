@@ -0,0 +1,332 @@
+use rayon::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::*;
+
+/// searches checked by `verify`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifySearch {
+    Dijkstra,
+    Bidir,
+    CH,
+    /// a cell of `CH::many_to_many`, see `verify_many_to_many`
+    ManyToMany,
+    /// a node of `Phast::one_to_all_batch`, see `verify_one_to_all`
+    Phast,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyErrorKind {
+    /// cost differs from cost of `Graph::search`
+    CostMismatch,
+    /// path is found by only one of `Graph::search` and given search
+    Unreachable,
+    /// sum of link costs of returned path differs from returned cost, or path has a missing link
+    PathCost,
+    /// secondary metric of `CH::search_metrics` differs from sum of secondary metric of links
+    /// on returned path
+    SecondaryMismatch,
+}
+
+#[derive(Clone, Debug)]
+pub struct VerifyError {
+    pub kind: VerifyErrorKind,
+    pub search: VerifySearch,
+    pub src: IdxNodeKey,
+    pub dst: IdxNodeKey,
+    /// cost of `Graph::search`, or returned value if `kind` is `PathCost` or
+    /// `SecondaryMismatch`
+    pub expected: Option<u32>,
+    /// cost of given search, or sum over links if `kind` is `PathCost` or `SecondaryMismatch`
+    pub actual: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub queries: usize,
+    /// number of queries where `Graph::search` found a path
+    pub reachable: usize,
+    pub errors: Vec<VerifyError>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn count(&self, search: VerifySearch, kind: VerifyErrorKind) -> usize {
+        self.errors
+            .iter()
+            .filter(|e| e.search == search && e.kind == kind)
+            .count()
+    }
+}
+
+/// `count` pairs of random nodes, empty if there is no node. Seed is taken from current time if
+/// not given, so returned seed can be used to reproduce failures.
+pub fn random_pairs(
+    node_len: usize,
+    count: usize,
+    seed: Option<u64>,
+) -> (Vec<(IdxNodeKey, IdxNodeKey)>, u64) {
+    let seed = seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+    });
+    if node_len == 0 {
+        return (Vec::new(), seed);
+    }
+
+    // xorshift64, state should not be zero
+    let mut state = seed | 1;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        IdxNodeKey::new((state % node_len as u64) as usize)
+    };
+
+    let pairs = (0..count).map(|_| (next(), next())).collect();
+    (pairs, seed)
+}
+
+/// sum of costs of links on `path`, choosing the cheapest link between consecutive nodes. None
+/// if there is no link between consecutive nodes.
+pub fn path_cost(graph: &Graph, path: &[IdxNodeKey]) -> Option<u32> {
+    let mut cost = 0u32;
+    for pair in path.windows(2) {
+        let link_cost = graph
+            .idx_links
+            .dir_links(pair[0].index(), IdxLinkDir::Forward)
+            .iter()
+            .filter(|l| l.enode_idx == pair[1])
+            .map(|l| l.cost())
+            .min()?;
+        cost = cost.checked_add(link_cost)?;
+    }
+    Some(cost)
+}
+
+/// sum of secondary metric of links on `path`, choosing the cheapest link between consecutive
+/// nodes with the smallest secondary metric. None if graph has no secondary metric or there is
+/// no link between consecutive nodes.
+pub fn path_secondary(graph: &Graph, path: &[IdxNodeKey]) -> Option<u32> {
+    if graph.secondary.is_empty() {
+        return None;
+    }
+    let mut secondary = 0u32;
+    for pair in path.windows(2) {
+        let idx = pair[0].index();
+        let r = graph.idx_links.range(idx);
+        let (_, link_secondary) = graph.idx_links.flat_links()[r.clone()]
+            .iter()
+            .zip(graph.secondary[r].iter())
+            .filter(|(l, _)| l.dir() == IdxLinkDir::Forward && l.enode_idx == pair[1])
+            .map(|(l, s)| (l.cost(), *s))
+            .min()?;
+        secondary = secondary.checked_add(link_secondary)?;
+    }
+    Some(secondary)
+}
+
+fn verify_pair(
+    graph: &Graph,
+    ch: &CH,
+    ctx: &mut QueryContext,
+    src: IdxNodeKey,
+    dst: IdxNodeKey,
+) -> (bool, Vec<VerifyError>) {
+    let mut errors = Vec::new();
+    let mut push = |kind, search, expected, actual| {
+        errors.push(VerifyError {
+            kind,
+            search,
+            src,
+            dst,
+            expected,
+            actual,
+        });
+    };
+
+    let expected = graph.search(src, dst);
+    let metrics = ch.search_metrics(ctx, src, dst);
+    if let Some((path, _, secondary)) = metrics.as_ref() {
+        let sum = path_secondary(graph, path);
+        if sum.is_some() && sum != Some(*secondary) {
+            push(
+                VerifyErrorKind::SecondaryMismatch,
+                VerifySearch::CH,
+                Some(*secondary),
+                sum,
+            );
+        }
+    }
+    let results = [
+        (VerifySearch::Dijkstra, expected.clone()),
+        (VerifySearch::Bidir, graph.search_bidir(src, dst)),
+        (
+            VerifySearch::CH,
+            metrics.map(|(path, cost, _)| (path, cost)),
+        ),
+    ];
+    let expected_cost = expected.as_ref().map(|(_, cost)| *cost);
+
+    for (search, result) in results.iter() {
+        let cost = result.as_ref().map(|(_, cost)| *cost);
+        if expected_cost.is_some() != cost.is_some() {
+            push(VerifyErrorKind::Unreachable, *search, expected_cost, cost);
+        } else if expected_cost != cost {
+            push(VerifyErrorKind::CostMismatch, *search, expected_cost, cost);
+        }
+
+        if let Some((path, cost)) = result {
+            let sum = path_cost(graph, path);
+            if sum != Some(*cost) {
+                push(VerifyErrorKind::PathCost, *search, Some(*cost), sum);
+            }
+        }
+    }
+    (expected.is_some(), errors)
+}
+
+/// compare `Graph::search_bidir` and `CH::search` against `Graph::search` on given pairs.
+/// Secondary metric of `CH::search_metrics` is checked as well if graph has one.
+pub fn verify(graph: &Graph, ch: &CH, pairs: &[(IdxNodeKey, IdxNodeKey)]) -> VerifyReport {
+    let results = pairs
+        .par_iter()
+        .map_init(
+            || QueryContext::new(graph.node_len),
+            |ctx, &(src, dst)| verify_pair(graph, ch, ctx, src, dst),
+        )
+        .collect::<Vec<_>>();
+
+    let mut report = VerifyReport {
+        queries: pairs.len(),
+        ..Default::default()
+    };
+    for (reachable, mut errors) in results.into_iter() {
+        if reachable {
+            report.reachable += 1;
+        }
+        report.errors.append(&mut errors);
+    }
+    report
+}
+
+/// compare each cell of `CH::many_to_many` from `srcs` to `dsts` against `Graph::search`. Pairs
+/// of the report are all cells of the matrix.
+pub fn verify_many_to_many(
+    graph: &Graph,
+    ch: &CH,
+    srcs: &[IdxNodeKey],
+    dsts: &[IdxNodeKey],
+) -> VerifyReport {
+    let matrix = ch.many_to_many(srcs, dsts);
+    let results = srcs
+        .par_iter()
+        .zip(matrix.par_iter())
+        .flat_map_iter(|(&src, row)| {
+            dsts.iter().zip(row.iter()).map(move |(&dst, &cost)| {
+                let expected = graph.search(src, dst).map(|(_, cost)| cost);
+                let actual = if cost == u32::MAX { None } else { Some(cost) };
+                let kind = if expected.is_some() != actual.is_some() {
+                    Some(VerifyErrorKind::Unreachable)
+                } else if expected != actual {
+                    Some(VerifyErrorKind::CostMismatch)
+                } else {
+                    None
+                };
+                let error = kind.map(|kind| VerifyError {
+                    kind,
+                    search: VerifySearch::ManyToMany,
+                    src,
+                    dst,
+                    expected,
+                    actual,
+                });
+                (expected.is_some(), error)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut report = VerifyReport {
+        queries: results.len(),
+        ..Default::default()
+    };
+    for (reachable, error) in results.into_iter() {
+        if reachable {
+            report.reachable += 1;
+        }
+        report.errors.extend(error);
+    }
+    report
+}
+
+/// compare costs of `Phast::one_to_all_batch` from `srcs` to all nodes against dijkstra. Pairs
+/// of the report are sources and all nodes.
+pub fn verify_one_to_all(graph: &Graph, ch: &CH, srcs: &[IdxNodeKey]) -> VerifyReport {
+    let batch = ch.phast().one_to_all_batch(srcs);
+    let mut report = VerifyReport {
+        queries: srcs.len() * graph.node_len,
+        ..Default::default()
+    };
+    for (&src, costs) in srcs.iter().zip(batch.iter()) {
+        let (expected, _) = graph.one_to_all(src, IdxLinkDir::Forward);
+        for (i, (&expected, &actual)) in expected.iter().zip(costs.iter()).enumerate() {
+            if expected != u32::MAX {
+                report.reachable += 1;
+            }
+            if expected == actual {
+                continue;
+            }
+            let kind = if expected == u32::MAX || actual == u32::MAX {
+                VerifyErrorKind::Unreachable
+            } else {
+                VerifyErrorKind::CostMismatch
+            };
+            let cost = |c: u32| if c == u32::MAX { None } else { Some(c) };
+            report.errors.push(VerifyError {
+                kind,
+                search: VerifySearch::Phast,
+                src,
+                dst: IdxNodeKey::new(i),
+                expected: cost(expected),
+                actual: cost(actual),
+            });
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn verify_small_graph() {
+        let graph = testutil::grid(7, 9);
+        let mut ch = CH::new(&graph);
+        ch.build();
+
+        let report = verify(&graph, &ch, &testutil::all_pairs(graph.node_len));
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert!(report.reachable > 0);
+
+        let (pairs, seed) = random_pairs(graph.node_len, 100, Some(3));
+        assert_eq!((pairs.len(), seed), (100, 3));
+        assert!(verify(&graph, &ch, &pairs).is_ok());
+        assert!(random_pairs(0, 100, None).0.is_empty());
+
+        let (srcs, dsts): (Vec<_>, Vec<_>) = pairs.into_iter().take(10).unzip();
+        let report = verify_many_to_many(&graph, &ch, &srcs, &dsts);
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.queries, 100);
+
+        let report = verify_one_to_all(&graph, &ch, &srcs[..3]);
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!(report.queries, 3 * graph.node_len);
+    }
+}